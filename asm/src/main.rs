@@ -0,0 +1,36 @@
+#![deny(unused_imports)]
+#![deny(dead_code)]
+#![deny(unused_must_use)]
+#![deny(unused_variables)]
+#![deny(unused_mut)]
+use chip8::asm;
+use std::{fs, path::PathBuf, process};
+use structopt::StructOpt;
+
+/// CHIP-8 assembler
+#[derive(StructOpt)]
+struct Opts {
+    /// Source file
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+
+    /// Output ROM (defaults to the source file with the `ch8` extension)
+    #[structopt(short, long, parse(from_os_str))]
+    output: Option<PathBuf>,
+}
+
+fn main() {
+    let Opts { input, output } = Opts::from_args();
+    let rom = match asm::assemble_file(&input) {
+        Ok(rom) => rom,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            process::exit(1);
+        }
+    };
+    let output = output.unwrap_or_else(|| input.with_extension("ch8"));
+    if let Err(err) = fs::write(&output, &rom) {
+        eprintln!("error: {}: {}", output.display(), err);
+        process::exit(1);
+    }
+}
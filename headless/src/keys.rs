@@ -0,0 +1,123 @@
+//! Scripted key presses.
+//!
+//! A script has one event per line, made of the frame in which it happens,
+//! the key (a hexadecimal digit) and its new state (`down` or `up`):
+//!
+//! ```text
+//! # start the game
+//! 60 5 down
+//! 62 5 up
+//! ```
+//!
+//! Empty lines and lines starting with `#` are ignored.
+use chip8::cpu::KeyState;
+use std::{fs, path::Path};
+
+/// Key press or release.
+#[derive(Clone, Copy)]
+pub struct Event {
+    pub frame: u64,
+    pub key: usize,
+    pub state: KeyState,
+}
+
+/// Reads a script, returning its events sorted by frame.
+pub fn load(path: &Path) -> Result<Vec<Event>, String> {
+    let script = fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    let mut events = Vec::new();
+    for (n, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let event = parse(line).map_err(|err| format!("{}:{}: {}", path.display(), n + 1, err))?;
+        events.push(event);
+    }
+    // stable, so events in the same frame keep their order
+    events.sort_by_key(|event| event.frame);
+    Ok(events)
+}
+
+fn parse(line: &str) -> Result<Event, String> {
+    let fields: Vec<_> = line.split_whitespace().collect();
+    if fields.len() != 3 {
+        return Err(format!(
+            "expected `<frame> <key> <down|up>`, found `{}`",
+            line
+        ));
+    }
+    let frame = fields[0]
+        .parse()
+        .map_err(|_| format!("invalid frame = {}", fields[0]))?;
+    let key = match usize::from_str_radix(fields[1], 16) {
+        Ok(key) if key < 16 => key,
+        _ => return Err(format!("invalid key = {}", fields[1])),
+    };
+    let state = match fields[2] {
+        "down" => KeyState::Down,
+        "up" => KeyState::Up,
+        state => return Err(format!("invalid key state = {}", state)),
+    };
+    Ok(Event { frame, key, state })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load, parse, Event};
+    use chip8::cpu::KeyState;
+    use std::{env, fs, process};
+
+    fn summary(events: &[Event]) -> Vec<(u64, usize, bool)> {
+        events
+            .iter()
+            .map(|event| (event.frame, event.key, event.state == KeyState::Down))
+            .collect()
+    }
+
+    #[test]
+    fn valid_lines() {
+        let event = parse("60 a down").unwrap();
+        assert_eq!((event.frame, event.key), (60, 0xA));
+        assert!(event.state == KeyState::Down);
+        let event = parse("  62\tF   up").unwrap();
+        assert_eq!((event.frame, event.key), (62, 0xF));
+        assert!(event.state == KeyState::Up);
+    }
+
+    #[test]
+    fn bad_lines() {
+        let error = |line| parse(line).err().unwrap();
+        assert_eq!(
+            error("60 5"),
+            "expected `<frame> <key> <down|up>`, found `60 5`"
+        );
+        assert_eq!(error("60 10 down"), "invalid key = 10");
+        assert_eq!(error("60 g down"), "invalid key = g");
+        assert_eq!(error("-1 5 down"), "invalid frame = -1");
+        assert_eq!(error("60 5 pressed"), "invalid key state = pressed");
+    }
+
+    #[test]
+    fn load_script() {
+        let path = env::temp_dir().join(format!("chip8-keys-{}.txt", process::id()));
+        fs::write(
+            &path,
+            "# start the game\n\n62 5 up\n60 5 down\n   \n# jump\n62 4 down\n",
+        )
+        .unwrap();
+        let events = load(&path).unwrap();
+        assert_eq!(
+            summary(&events),
+            [(60, 5, true), (62, 5, false), (62, 4, true)]
+        );
+
+        fs::write(&path, "# start the game\n60 5 down\nsixty 5 up\n").unwrap();
+        let err = load(&path).err().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(err, format!("{}:3: invalid frame = sixty", path.display()));
+        assert!(load(&path)
+            .err()
+            .unwrap()
+            .starts_with(&format!("{}: ", path.display())));
+    }
+}
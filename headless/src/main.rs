@@ -0,0 +1,220 @@
+#![deny(unused_imports)]
+#![deny(dead_code)]
+#![deny(unused_must_use)]
+#![deny(unused_variables)]
+#![deny(unused_mut)]
+use crate::opts::Opts;
+use chip8::{
+    audio::{Generator, WavWriter},
+    clock::Clock,
+    cpu::{Cpu, CpuState, Timing, XorShift, VIP_HZ},
+    export::{self, FrameDumper, GifRecorder, Style},
+    movie::{Movie, Player},
+};
+use std::{
+    fs,
+    io::{self, BufWriter},
+    iter::Peekable,
+    path::Path,
+    process, vec,
+};
+
+mod keys;
+mod opts;
+
+/// Exit status of a program that halted or ran for all the frames.
+const EXIT_OK: i32 = 0;
+/// Exit status of a program that faulted.
+const EXIT_FAULT: i32 = 1;
+/// Exit status of invalid arguments or I/O errors.
+const EXIT_ERROR: i32 = 2;
+
+/// Number of frames run when neither `--frames` nor a movie is given.
+const DEFAULT_FRAMES: u64 = 600;
+
+/// Sample rate of recorded WAV files.
+const WAV_RATE: u32 = 44100;
+
+/// Characters used to print each colour index.
+const ASCII: [char; 4] = ['.', '#', '+', '@'];
+
+fn main() {
+    let opts = Opts::from_args();
+    match run(&opts) {
+        Ok(status) => process::exit(status),
+        Err(err) => {
+            eprintln!("error: {}", err);
+            process::exit(EXIT_ERROR);
+        }
+    }
+}
+
+fn run(opts: &Opts) -> Result<i32, String> {
+    let rom = fs::read(&opts.rom).map_err(|err| format!("{}: {}", opts.rom.display(), err))?;
+    let (mut cpu, mut clock, mut input) = match &opts.movie {
+        Some(path) => {
+            let movie = fs::read(path)
+                .map_err(|err| err.to_string())
+                .and_then(|movie| Movie::from_bytes(&movie).map_err(|err| err.to_string()))
+                .map_err(|err| format!("{}: {}", path.display(), err))?;
+            let (cpu, clock) = movie
+                .start(&rom)
+                .map_err(|err| format!("{}: {}", path.display(), err))?;
+            (cpu, clock, Input::Movie(Player::new(movie)))
+        }
+        None => {
+            let events = match &opts.keys {
+                Some(path) => keys::load(path)?,
+                None => Vec::new(),
+            };
+            let (cpu, clock) = setup(opts, &rom)?;
+            (cpu, clock, Input::Script(events.into_iter().peekable()))
+        }
+    };
+    let frames = opts.frames.unwrap_or(match &input {
+        Input::Movie(player) => player.movie().frames(),
+        Input::Script(_) => DEFAULT_FRAMES,
+    });
+
+    let style = Style::new(opts.fg, opts.bg).with_scale(opts.scale);
+    let mut dumper = opts
+        .dump
+        .as_ref()
+        .map(|prefix| FrameDumper::new(prefix, opts.dump_format, style));
+    let mut gif = match &opts.gif {
+        Some(path) => {
+            Some(create_gif(path, &style).map_err(|err| format!("{}: {}", path.display(), err))?)
+        }
+        None => None,
+    };
+    // the sound is rendered in emulated time, so it matches the frames
+    let mut wav = match &opts.wav {
+        Some(path) => {
+            clock.set_audio(Some(Generator::new(WAV_RATE)));
+            Some(create_wav(path).map_err(|err| format!("{}: {}", path.display(), err))?)
+        }
+        None => None,
+    };
+    let mut frame = 0;
+    while frame < frames {
+        frame += 1;
+        let result = match &mut input {
+            Input::Movie(player) => player.advance_frame(&mut cpu, &mut clock),
+            Input::Script(events) => {
+                while let Some(event) = events.next_if(|event| event.frame < frame) {
+                    cpu.set_key(event.key, event.state);
+                }
+                clock.advance_frame(&mut cpu)
+            }
+        };
+        if let Some(dumper) = &mut dumper {
+            dumper
+                .dump(cpu.display())
+                .map_err(|err| format!("failed to dump frame {} = {}", frame, err))?;
+        }
+        if let Some(gif) = &mut gif {
+            gif.frame(cpu.display())
+                .map_err(|err| format!("failed to record frame {} = {}", frame, err))?;
+        }
+        if let (Some(wav), Some(audio)) = (&mut wav, clock.audio_mut()) {
+            wav.write(&audio.take_samples())
+                .map_err(|err| format!("failed to record sound of frame {} = {}", frame, err))?;
+        }
+        // a fault is recorded in the state of the cpu
+        if result.is_err() {
+            break;
+        }
+        if let CpuState::Halt = cpu.state() {
+            break;
+        }
+    }
+
+    if let (Some(gif), Some(path)) = (gif, &opts.gif) {
+        gif.finish()
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+    }
+    if let (Some(wav), Some(path)) = (wav, &opts.wav) {
+        wav.finish()
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+    }
+    if let Some(path) = &opts.image {
+        export::save(path, cpu.display(), &style)
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+    }
+
+    if !opts.no_ascii {
+        print!("{}", ascii(&cpu));
+    }
+    print_registers(&cpu, frame);
+
+    match cpu.state() {
+        CpuState::Fault(err) => {
+            eprintln!("fault: {}", err);
+            Ok(EXIT_FAULT)
+        }
+        _ => Ok(EXIT_OK),
+    }
+}
+
+/// Source of key presses.
+enum Input {
+    Script(Peekable<vec::IntoIter<keys::Event>>),
+    Movie(Player),
+}
+
+/// Creates a cpu and a clock from the program arguments, with the ROM loaded.
+fn setup(opts: &Opts, rom: &[u8]) -> Result<(Cpu, Clock), String> {
+    let mut cpu = Cpu::with_mode(opts.mode, opts.quirks.unwrap_or_default());
+    cpu.set_rng(Box::new(XorShift::new(opts.seed)));
+    if opts.vip_timing {
+        cpu.set_timing(Timing::CosmacVip);
+    }
+    cpu.load(rom)
+        .map_err(|err| format!("{}: {}", opts.rom.display(), err))?;
+    let clock = match cpu.timing() {
+        Timing::CosmacVip => Clock::new(VIP_HZ),
+        Timing::Instructions => Clock::new(opts.clock),
+    };
+    Ok((cpu, clock))
+}
+
+fn create_gif(path: &Path, style: &Style) -> io::Result<GifRecorder<BufWriter<fs::File>>> {
+    GifRecorder::new(BufWriter::new(fs::File::create(path)?), style)
+}
+
+fn create_wav(path: &Path) -> io::Result<WavWriter<BufWriter<fs::File>>> {
+    WavWriter::new(BufWriter::new(fs::File::create(path)?), WAV_RATE)
+}
+
+/// Return the display as text, one line per row.
+fn ascii(cpu: &Cpu) -> String {
+    let display = cpu.display();
+    let colors: Vec<_> = display.colors().collect();
+    let mut text = String::with_capacity((display.width() + 1) * display.height());
+    for row in colors.chunks(display.width()) {
+        text.extend(row.iter().map(|c| ASCII[usize::from(*c)]));
+        text.push('\n');
+    }
+    text
+}
+
+fn print_registers(cpu: &Cpu, frames: u64) {
+    println!("frames = {}", frames);
+    println!("state = {:?}", cpu.state());
+    println!(
+        "PC = {:03X}  I = {:03X}  SP = {}  DT = {}  ST = {}",
+        cpu.program_counter(),
+        cpu.i(),
+        cpu.stack_pointer(),
+        cpu.delay_timer(),
+        cpu.sound_timer()
+    );
+    let registers: Vec<_> = cpu
+        .registers()
+        .iter()
+        .enumerate()
+        .map(|(i, v)| format!("V{:X} = {:02X}", i, v))
+        .collect();
+    println!("{}", registers[..8].join("  "));
+    println!("{}", registers[8..].join("  "));
+}
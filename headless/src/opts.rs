@@ -0,0 +1,91 @@
+use chip8::{
+    cpu::{Mode, Quirks},
+    export::{Format, Rgb},
+};
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+/// Runs a CHIP-8 ROM without a window
+#[derive(StructOpt)]
+pub struct Opts {
+    /// Rom location
+    #[structopt(parse(from_os_str))]
+    pub rom: PathBuf,
+
+    /// Number of frames (1/60 s) to run, unless the program halts or faults
+    /// (defaults to 600, or to the length of the movie)
+    #[structopt(long)]
+    pub frames: Option<u64>,
+
+    /// Instructions per second.
+    #[structopt(short, long, default_value = "700")]
+    pub clock: u32,
+
+    /// Use the COSMAC VIP timing model (ignores --clock)
+    #[structopt(long = "vip-timing")]
+    pub vip_timing: bool,
+
+    /// Instruction set (chip8, schip or xochip)
+    #[structopt(short, long, default_value = "chip8")]
+    pub mode: Mode,
+
+    /// Seed of the random number generator
+    #[structopt(long, default_value = "0")]
+    pub seed: u64,
+
+    /// Quirks profile (vip, chip48, schip or modern)
+    #[structopt(short, long)]
+    pub quirks: Option<Quirks>,
+
+    /// Scripted key presses, one `<frame> <key> <down|up>` event per line
+    #[structopt(short, long, parse(from_os_str))]
+    pub keys: Option<PathBuf>,
+
+    /// Replay an input movie (ignores the mode, quirks, seed and clock
+    /// options)
+    #[structopt(long, parse(from_os_str), conflicts_with = "keys")]
+    pub movie: Option<PathBuf>,
+
+    /// Write the final display to an image file (png, ppm or pbm)
+    #[structopt(long, parse(from_os_str))]
+    pub image: Option<PathBuf>,
+
+    /// Write every frame to numbered image files starting with this prefix
+    #[structopt(long, parse(from_os_str))]
+    pub dump: Option<PathBuf>,
+
+    /// Format of the frames written with --dump (png, ppm or pbm)
+    #[structopt(long = "dump-format", default_value = "png")]
+    pub dump_format: Format,
+
+    /// Record every frame into an animated GIF
+    #[structopt(long, parse(from_os_str))]
+    pub gif: Option<PathBuf>,
+
+    /// Record the sound into a WAV file
+    #[structopt(long, parse(from_os_str))]
+    pub wav: Option<PathBuf>,
+
+    /// Size of a pixel in the exported images
+    #[structopt(long, default_value = "4")]
+    pub scale: usize,
+
+    /// Foreground colour of the exported images
+    #[structopt(long, default_value = "#FFFFFF")]
+    pub fg: Rgb,
+
+    /// Background colour of the exported images
+    #[structopt(long, default_value = "#000000")]
+    pub bg: Rgb,
+
+    /// Don't print the final display to stdout
+    #[structopt(long = "no-ascii")]
+    pub no_ascii: bool,
+}
+
+impl Opts {
+    #[inline]
+    pub fn from_args() -> Self {
+        <Self as StructOpt>::from_args()
+    }
+}
@@ -0,0 +1,54 @@
+//! Exit status of the headless runner.
+use std::{
+    env, fs,
+    path::Path,
+    process::{self, Command},
+};
+
+/// Writes the ROM to a temporary file and runs it, returning the exit status.
+fn run(name: &str, rom: &[u8], args: &[&str]) -> Option<i32> {
+    let path = env::temp_dir().join(format!("chip8-headless-{}-{}.ch8", process::id(), name));
+    fs::write(&path, rom).unwrap();
+    let status = status(&path, args);
+    fs::remove_file(&path).unwrap();
+    status
+}
+
+/// Runs the ROM at the given path for a few frames, returning the exit status.
+fn status(rom: &Path, args: &[&str]) -> Option<i32> {
+    Command::new(env!("CARGO_BIN_EXE_chip8-headless"))
+        .arg(rom)
+        .args(["--frames", "10", "--no-ascii"])
+        .args(args)
+        .output()
+        .unwrap()
+        .status
+        .code()
+}
+
+#[test]
+fn all_frames() {
+    // JP 0x200
+    assert_eq!(run("loop", &[0x12, 0x00], &[]), Some(0));
+}
+
+#[test]
+fn halt() {
+    // EXIT
+    assert_eq!(run("halt", &[0x00, 0xFD], &["--mode", "schip"]), Some(0));
+}
+
+#[test]
+fn fault() {
+    // RET
+    assert_eq!(run("fault", &[0x00, 0xEE], &[]), Some(1));
+}
+
+#[test]
+fn error() {
+    let missing = env::temp_dir().join(format!("chip8-headless-{}-missing.ch8", process::id()));
+    assert_eq!(status(&missing, &[]), Some(2));
+    let keys = missing.with_extension("txt");
+    let keys = keys.to_str().unwrap();
+    assert_eq!(run("keys", &[0x12, 0x00], &["--keys", keys]), Some(2));
+}
@@ -0,0 +1,160 @@
+//! Key bindings, read from a TOML file.
+//!
+//! Bindings are keyed by CHIP-8 key (a hex digit). Keyboard keys use SDL
+//! scancode names, and game controller inputs SDL button names, or SDL axis
+//! names followed by a direction. An empty name unbinds a key. Keys missing
+//! from the file keep their default bindings.
+//!
+//! Tables under `roms`, keyed by the CRC-32 of a ROM, override the bindings
+//! of that ROM only.
+//!
+//! ```toml
+//! [keys]
+//! 5 = "Up"
+//! 8 = "Down"
+//!
+//! [gamepad]
+//! 7 = "leftx-"
+//! 9 = "leftx+"
+//!
+//! [roms.8A1C7E20.keys]
+//! 6 = "Space"
+//! ```
+use crate::input::Keymap;
+use log::warn;
+use sdl2::keyboard::Scancode;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, error::Error, fs, io, path::Path};
+
+/// Bindings that override the defaults.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Bindings {
+    /// Keyboard key of each CHIP-8 key.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub keys: BTreeMap<String, String>,
+    /// Game controller input of each CHIP-8 key.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub gamepad: BTreeMap<String, String>,
+}
+
+impl Bindings {
+    /// Binds a keyboard key to a CHIP-8 key. An empty name unbinds it.
+    pub fn set_key(&mut self, key: usize, name: String) {
+        set(&mut self.keys, key, name);
+    }
+
+    /// Binds a game controller input to a CHIP-8 key. An empty name unbinds
+    /// it.
+    pub fn set_pad(&mut self, key: usize, name: String) {
+        set(&mut self.gamepad, key, name);
+    }
+
+    fn apply(&self, keymap: &mut Keymap) {
+        for (key, name) in bound(&self.keys) {
+            keymap.keys[key] = match name {
+                "" => None,
+                name => match Scancode::from_name(name) {
+                    Some(code) => Some(code),
+                    None => {
+                        warn!("unknown keyboard key = {}", name);
+                        continue;
+                    }
+                },
+            };
+        }
+        for (key, name) in bound(&self.gamepad) {
+            keymap.pad[key] = match name {
+                "" => None,
+                name => match name.parse() {
+                    Ok(pad) => Some(pad),
+                    Err(err) => {
+                        warn!("{}", err);
+                        continue;
+                    }
+                },
+            };
+        }
+    }
+}
+
+/// Contents of the config file.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    #[serde(flatten)]
+    pub bindings: Bindings,
+    /// Bindings of individual ROMs, keyed by their CRC-32 in hex.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub roms: BTreeMap<String, Bindings>,
+}
+
+impl Config {
+    /// Reads the config file. A missing file is an empty config.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        match fs::read_to_string(path) {
+            Ok(config) => Ok(toml::from_str(&config)?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Return the bindings of a ROM: the defaults, overridden by the global
+    /// bindings, overridden by the bindings of the ROM.
+    pub fn keymap(&self, rom_hash: u32) -> Keymap {
+        let mut keymap = Keymap::default();
+        self.bindings.apply(&mut keymap);
+        let id = format!("{:08X}", rom_hash);
+        let rom = self
+            .roms
+            .iter()
+            .find(|(rom, _)| rom.eq_ignore_ascii_case(&id));
+        if let Some((_, bindings)) = rom {
+            bindings.apply(&mut keymap);
+        }
+        keymap
+    }
+
+    /// Return the bindings of a ROM, if `rom_hash` is given, or else the
+    /// global bindings.
+    pub fn bindings_mut(&mut self, rom_hash: Option<u32>) -> &mut Bindings {
+        match rom_hash {
+            Some(hash) => {
+                let id = format!("{:08X}", hash);
+                let id = self
+                    .roms
+                    .keys()
+                    .find(|rom| rom.eq_ignore_ascii_case(&id))
+                    .cloned()
+                    .unwrap_or(id);
+                self.roms.entry(id).or_default()
+            }
+            None => &mut self.bindings,
+        }
+    }
+}
+
+/// Return the valid entries of a table of bindings, with the key parsed.
+fn bound(table: &BTreeMap<String, String>) -> impl Iterator<Item = (usize, &str)> {
+    table.iter().filter_map(|(key, name)| match parse_key(key) {
+        Some(key) => Some((key, name.as_str())),
+        None => {
+            warn!("invalid CHIP-8 key = {}", key);
+            None
+        }
+    })
+}
+
+fn set(table: &mut BTreeMap<String, String>, key: usize, name: String) {
+    table.retain(|k, _| parse_key(k) != Some(key));
+    table.insert(format!("{:X}", key), name);
+}
+
+fn parse_key(key: &str) -> Option<usize> {
+    usize::from_str_radix(key, 16).ok().filter(|key| *key < 16)
+}
@@ -0,0 +1,204 @@
+use crate::movie::Tape;
+use chip8::cpu::{Cpu, KeyState};
+use imgui::Ui;
+use sdl2::{
+    controller::{Axis, Button, GameController},
+    keyboard::Scancode,
+};
+use std::{fmt, str::FromStr};
+
+/// Keyboard key bound to each CHIP-8 key, laid out like the COSMAC VIP
+/// keypad on the left side of a QWERTY keyboard:
+///
+/// ```text
+/// 1 2 3 C      1 2 3 4
+/// 4 5 6 D  ->  Q W E R
+/// 7 8 9 E      A S D F
+/// A 0 B F      Z X C V
+/// ```
+pub const DEFAULT_KEYS: [Scancode; 16] = [
+    Scancode::X,
+    Scancode::Num1,
+    Scancode::Num2,
+    Scancode::Num3,
+    Scancode::Q,
+    Scancode::W,
+    Scancode::E,
+    Scancode::A,
+    Scancode::S,
+    Scancode::D,
+    Scancode::Z,
+    Scancode::C,
+    Scancode::Num4,
+    Scancode::R,
+    Scancode::F,
+    Scancode::V,
+];
+
+/// Game controller buttons bound by default, matching the WASD cluster of
+/// the default keys (5, 7, 8 and 9), with Q and E (4 and 6) on B and A.
+const DEFAULT_PAD: [(usize, Button); 6] = [
+    (0x4, Button::B),
+    (0x5, Button::DPadUp),
+    (0x6, Button::A),
+    (0x7, Button::DPadLeft),
+    (0x8, Button::DPadDown),
+    (0x9, Button::DPadRight),
+];
+
+/// Axis position past which an axis direction counts as held.
+const AXIS_THRESHOLD: i16 = 16384;
+
+const BUTTONS: [Button; 15] = [
+    Button::A,
+    Button::B,
+    Button::X,
+    Button::Y,
+    Button::Back,
+    Button::Guide,
+    Button::Start,
+    Button::LeftStick,
+    Button::RightStick,
+    Button::LeftShoulder,
+    Button::RightShoulder,
+    Button::DPadUp,
+    Button::DPadDown,
+    Button::DPadLeft,
+    Button::DPadRight,
+];
+
+const AXES: [Axis; 6] = [
+    Axis::LeftX,
+    Axis::LeftY,
+    Axis::RightX,
+    Axis::RightY,
+    Axis::TriggerLeft,
+    Axis::TriggerRight,
+];
+
+/// Game controller input: a button, or an axis pushed in one direction.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Pad {
+    Button(Button),
+    /// Axis, and whether it is pushed towards positive values.
+    Axis(Axis, bool),
+}
+
+impl Pad {
+    /// Return true if the input is held on the controller.
+    pub fn is_down(&self, controller: &GameController) -> bool {
+        match *self {
+            Pad::Button(button) => controller.button(button),
+            Pad::Axis(axis, true) => controller.axis(axis) > AXIS_THRESHOLD,
+            Pad::Axis(axis, false) => controller.axis(axis) < -AXIS_THRESHOLD,
+        }
+    }
+}
+
+impl FromStr for Pad {
+    type Err = String;
+
+    /// Parses SDL button names (`a`, `dpup`, ...), or SDL axis names
+    /// followed by a direction (`leftx-`, `triggerright+`, ...).
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let pad = if let Some(axis) = name.strip_suffix('+') {
+            Axis::from_string(axis).map(|axis| Pad::Axis(axis, true))
+        } else if let Some(axis) = name.strip_suffix('-') {
+            Axis::from_string(axis).map(|axis| Pad::Axis(axis, false))
+        } else {
+            Button::from_string(name).map(Pad::Button)
+        };
+        pad.ok_or_else(|| format!("unknown game controller input = {}", name))
+    }
+}
+
+impl fmt::Display for Pad {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Pad::Button(button) => write!(f, "{}", button.string()),
+            Pad::Axis(axis, true) => write!(f, "{}+", axis.string()),
+            Pad::Axis(axis, false) => write!(f, "{}-", axis.string()),
+        }
+    }
+}
+
+/// Keyboard key and game controller input bound to each CHIP-8 key.
+#[derive(Clone)]
+pub struct Keymap {
+    pub keys: [Option<Scancode>; 16],
+    pub pad: [Option<Pad>; 16],
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut pad = [None; 16];
+        for (key, button) in DEFAULT_PAD.iter() {
+            pad[*key] = Some(Pad::Button(*button));
+        }
+        let mut keys = [None; 16];
+        for (key, code) in DEFAULT_KEYS.iter().enumerate() {
+            keys[key] = Some(*code);
+        }
+        Self { keys, pad }
+    }
+}
+
+/// Keys held by the host, from the keyboard, the game controller and the
+/// on-screen keypad.
+pub struct Input {
+    /// Keys held on the on-screen keypad.
+    pub keypad: [bool; 16],
+    keymap: Keymap,
+    controller: Option<GameController>,
+    held: [bool; 16],
+}
+
+impl Input {
+    pub fn new(keymap: Keymap, controller: Option<GameController>) -> Self {
+        Self {
+            keypad: [false; 16],
+            keymap,
+            controller,
+            held: [false; 16],
+        }
+    }
+
+    /// Return the bindings in use.
+    pub fn keymap(&self) -> &Keymap {
+        &self.keymap
+    }
+
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.keymap = keymap;
+    }
+
+    /// Return the first game controller input found held, if any.
+    pub fn pad_pressed(&self) -> Option<Pad> {
+        let controller = self.controller.as_ref()?;
+        let buttons = BUTTONS.iter().map(|button| Pad::Button(*button));
+        let axes = AXES
+            .iter()
+            .flat_map(|axis| [true, false].iter().map(move |dir| Pad::Axis(*axis, *dir)));
+        buttons.chain(axes).find(|pad| pad.is_down(controller))
+    }
+
+    /// Forwards the keys pressed or released since the last call.
+    pub fn update(&mut self, ui: &Ui, cpu: &mut Cpu, tape: &mut Tape) {
+        let io = ui.io();
+        for (key, held) in self.held.iter_mut().enumerate() {
+            // typing into a text field doesn't press keys
+            let keyboard = !io.want_text_input
+                && matches!(self.keymap.keys[key], Some(code) if io.keys_down[code as usize]);
+            let pad = match (&self.controller, self.keymap.pad[key]) {
+                (Some(controller), Some(pad)) => pad.is_down(controller),
+                _ => false,
+            };
+            let down = keyboard || pad || self.keypad[key];
+            if down != *held {
+                *held = down;
+                let state = if down { KeyState::Down } else { KeyState::Up };
+                tape.set_key(cpu, key, state);
+            }
+        }
+    }
+}
@@ -3,26 +3,45 @@
 #![deny(unused_must_use)]
 #![deny(unused_variables)]
 #![deny(unused_mut)]
-use crate::opts::Opts;
-use chip8::cpu::Cpu;
+use crate::{config::Config, movie::Tape, opts::Opts, sdl2_runner::Emulator};
+use chip8::{
+    audio::{WavWriter, Waveform},
+    clock::Clock,
+    cpu::{Cpu, Timing, XorShift},
+    export::{GifRecorder, Style},
+    movie::{rom_hash, Movie, Player, Recorder},
+};
 use imgui::MenuItem;
-use log::{error, info, warn};
+use log::{error, info};
 use std::{
     error::Error,
     fs,
-    io::{self, Read},
+    io::{self, BufWriter, Read},
+    mem,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
+mod config;
+mod input;
+mod movie;
 mod opts;
 mod sdl2_runner;
 mod ui;
 
+/// Number of save state slots in the "Rom" menu.
+const STATE_SLOTS: usize = 4;
+
+/// Size of a high resolution pixel in recorded GIFs.
+const GIF_SCALE: usize = 2;
+
 struct App {
     pub display: bool,
     pub keypad: bool,
     pub debug: bool,
+    pub disasm: bool,
     pub memory: bool,
     pub registers: bool,
+    pub bindings: bool,
 }
 
 fn main() {
@@ -45,47 +64,301 @@ fn run() -> Result<(), Box<dyn Error>> {
         display: true,
         keypad: true,
         debug: true,
+        disasm: true,
         memory: true,
         registers: true,
+        bindings: false,
     };
+    let mut config = Config::load(&opts.config)?;
+    let mut bindings = ui::bindings::Dialog::default();
+    let mut disasm = ui::disasm::Disasm::default();
+    let rom_hash = rom_hash(&program);
 
-    sdl2_runner::run(Cpu::new(), |cpu, ui| {
+    let seed = opts.seed.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_nanos() as u64)
+            .unwrap_or_default()
+    });
+    info!("random seed = {}", seed);
+    let mut cpu = Cpu::with_mode(opts.mode, opts.quirks.unwrap_or_default());
+    cpu.set_rng(Box::new(XorShift::new(seed)));
+    if opts.vip_timing {
+        cpu.set_timing(Timing::CosmacVip);
+    }
+    sdl2_runner::run(cpu, config.keymap(rom_hash), |emu, ui| {
         ui.main_menu_bar(|| {
             ui.menu(imgui::im_str!("App"), true, || {
                 ui.checkbox(imgui::im_str!("Display"), &mut app.display);
                 ui.checkbox(imgui::im_str!("Keypad"), &mut app.keypad);
                 ui.checkbox(imgui::im_str!("Memory"), &mut app.memory);
                 ui.checkbox(imgui::im_str!("Debug"), &mut app.debug);
+                ui.checkbox(imgui::im_str!("Disassembly"), &mut app.disasm);
                 ui.checkbox(imgui::im_str!("Registers"), &mut app.registers);
+                ui.checkbox(imgui::im_str!("Bindings"), &mut app.bindings);
             });
             ui.menu(imgui::im_str!("Rom"), true, || {
-                if MenuItem::new(imgui::im_str!("Load")).build(ui) {
-                    cpu.load(&program);
+                // changing the state of the cpu would desync a movie
+                let stopped = emu.tape.is_stopped();
+                if MenuItem::new(imgui::im_str!("Load"))
+                    .enabled(stopped)
+                    .build(ui)
+                {
+                    if let Err(err) = emu.cpu.load(&program) {
+                        error!("failed to load ROM = {}", err);
+                    }
+                }
+                if MenuItem::new(imgui::im_str!("Reset"))
+                    .enabled(stopped)
+                    .build(ui)
+                {
+                    emu.cpu.reset();
+                }
+                ui.separator();
+                ui.menu(imgui::im_str!("Save state"), true, || {
+                    for slot in 1..=STATE_SLOTS {
+                        if MenuItem::new(&imgui::im_str!("Slot {}", slot)).build(ui) {
+                            save_state(&emu.cpu, &opts.rom, slot);
+                        }
+                    }
+                });
+                ui.menu(imgui::im_str!("Load state"), stopped, || {
+                    for slot in 1..=STATE_SLOTS {
+                        if MenuItem::new(&imgui::im_str!("Slot {}", slot)).build(ui) {
+                            load_state(&mut emu.cpu, &opts.rom, slot);
+                        }
+                    }
+                });
+                ui.separator();
+                let recording_gif = emu.gif.is_some();
+                if MenuItem::new(imgui::im_str!("Record GIF"))
+                    .selected(recording_gif)
+                    .build(ui)
+                {
+                    if recording_gif {
+                        stop_gif(emu);
+                    } else {
+                        start_gif(emu, &opts.rom);
+                    }
+                }
+                let recording_wav = emu.wav.is_some();
+                if MenuItem::new(imgui::im_str!("Record WAV"))
+                    .selected(recording_wav)
+                    .build(ui)
+                {
+                    if recording_wav {
+                        stop_wav(emu);
+                    } else {
+                        start_wav(emu, &opts.rom);
+                    }
                 }
-                if MenuItem::new(imgui::im_str!("Reset")).build(ui) {
-                    cpu.reset();
+                ui.separator();
+                ui.menu(imgui::im_str!("Movie"), true, || {
+                    if MenuItem::new(imgui::im_str!("Record"))
+                        .enabled(stopped)
+                        .build(ui)
+                    {
+                        record_movie(emu, &program);
+                    }
+                    if MenuItem::new(imgui::im_str!("Stop"))
+                        .enabled(!stopped)
+                        .build(ui)
+                    {
+                        stop_movie(emu, &opts.rom);
+                    }
+                    if MenuItem::new(imgui::im_str!("Play"))
+                        .enabled(stopped)
+                        .build(ui)
+                    {
+                        play_movie(emu, &program, &opts.rom);
+                    }
+                });
+            });
+            ui.menu(imgui::im_str!("Audio"), emu.clock.audio().is_some(), || {
+                if let Some(audio) = emu.clock.audio_mut() {
+                    for waveform in &Waveform::ALL {
+                        if MenuItem::new(&imgui::im_str!("{}", waveform))
+                            .selected(audio.waveform() == *waveform)
+                            .build(ui)
+                        {
+                            audio.set_waveform(*waveform);
+                        }
+                    }
+                    ui.separator();
+                    let mut volume = audio.volume();
+                    if ui
+                        .slider_float(imgui::im_str!("Volume"), &mut volume, 0.0, 1.0)
+                        .build()
+                    {
+                        audio.set_volume(volume);
+                    }
                 }
             });
         });
 
-        if app.keypad {
-            if let Some(_key) = ui::keypad::draw(ui) {
-                warn!("gui keypad not implemented yet");
-            }
-        }
+        emu.input.keypad = if app.keypad {
+            ui::keypad::draw(ui, emu.cpu.keypad())
+        } else {
+            [false; 16]
+        };
         if app.debug {
-            ui::debug::draw(ui, cpu);
+            ui::debug::draw(ui, &mut emu.cpu);
+        }
+        if app.disasm {
+            ui::disasm::draw(ui, &mut disasm, &emu.cpu);
         }
         if app.memory {
-            ui::memory::draw(ui, cpu);
+            ui::memory::draw(ui, &emu.cpu);
         }
         if app.registers {
-            ui::registers::draw(ui, cpu);
+            ui::registers::draw(ui, &mut emu.cpu);
+        }
+        if app.bindings && ui::bindings::draw(ui, &mut bindings, &mut config, rom_hash, &emu.input)
+        {
+            emu.input.set_keymap(config.keymap(rom_hash));
+            match config.save(&opts.config) {
+                Ok(_) => info!("saved bindings = {}", opts.config.display()),
+                Err(err) => error!("failed to save bindings = {}", err),
+            }
         }
     })?;
     Ok(())
 }
 
+/// Save states are stored next to the ROM, one file per slot.
+fn state_path(rom: &Option<String>, slot: usize) -> String {
+    format!("{}.state{}", rom.as_deref().unwrap_or("stdin"), slot)
+}
+
+fn save_state(cpu: &Cpu, rom: &Option<String>, slot: usize) {
+    let path = state_path(rom, slot);
+    match fs::write(&path, cpu.save_state()) {
+        Ok(_) => info!("saved state = {}", path),
+        Err(err) => error!("failed to save state = {}", err),
+    }
+}
+
+fn load_state(cpu: &mut Cpu, rom: &Option<String>, slot: usize) {
+    let path = state_path(rom, slot);
+    let result = fs::read(&path)
+        .map_err(Box::<dyn Error>::from)
+        .and_then(|state| Ok(cpu.load_state(&state)?));
+    match result {
+        Ok(_) => info!("loaded state = {}", path),
+        Err(err) => error!("failed to load state = {}", err),
+    }
+}
+
+fn start_gif(emu: &mut Emulator, rom: &Option<String>) {
+    let path = sdl2_runner::numbered_path(rom, "gif");
+    let style = Style::default().with_scale(GIF_SCALE);
+    let result =
+        fs::File::create(&path).and_then(|file| GifRecorder::new(BufWriter::new(file), &style));
+    match result {
+        Ok(gif) => {
+            info!("recording GIF = {}", path);
+            emu.gif = Some(gif);
+        }
+        Err(err) => error!("failed to record GIF = {}", err),
+    }
+}
+
+fn stop_gif(emu: &mut Emulator) {
+    if let Some(gif) = emu.gif.take() {
+        match gif.finish() {
+            Ok(_) => info!("saved GIF"),
+            Err(err) => error!("failed to save GIF = {}", err),
+        }
+    }
+}
+
+fn start_wav(emu: &mut Emulator, rom: &Option<String>) {
+    let rate = match emu.clock.audio() {
+        Some(audio) => audio.rate(),
+        None => return,
+    };
+    let path = sdl2_runner::numbered_path(rom, "wav");
+    let result =
+        fs::File::create(&path).and_then(|file| WavWriter::new(BufWriter::new(file), rate));
+    match result {
+        Ok(wav) => {
+            info!("recording WAV = {}", path);
+            emu.wav = Some(wav);
+        }
+        Err(err) => error!("failed to record WAV = {}", err),
+    }
+}
+
+fn stop_wav(emu: &mut Emulator) {
+    if let Some(wav) = emu.wav.take() {
+        match wav.finish() {
+            Ok(_) => info!("saved WAV"),
+            Err(err) => error!("failed to save WAV = {}", err),
+        }
+    }
+}
+
+/// Movies are stored next to the ROM.
+fn movie_path(rom: &Option<String>) -> String {
+    format!("{}.movie", rom.as_deref().unwrap_or("stdin"))
+}
+
+/// Restarts the program with a fresh cpu, set up like the current one, and
+/// starts recording its input.
+fn record_movie(emu: &mut Emulator, program: &[u8]) {
+    let movie = Movie::new(program, &emu.cpu, emu.clock.hz());
+    match movie.start(program) {
+        Ok((cpu, clock)) => {
+            replace_cpu(emu, cpu);
+            replace_clock(emu, clock);
+            emu.tape = Tape::Recording(Recorder::new(movie));
+            info!("recording movie, seed = {}", emu.cpu.seed());
+        }
+        Err(err) => error!("failed to record movie = {}", err),
+    }
+}
+
+/// Stops recording or playing a movie. Recorded movies are saved.
+fn stop_movie(emu: &mut Emulator, rom: &Option<String>) {
+    if let Tape::Recording(recorder) = mem::replace(&mut emu.tape, Tape::Stopped) {
+        let movie = recorder.finish();
+        let path = movie_path(rom);
+        match fs::write(&path, movie.to_bytes()) {
+            Ok(_) => info!("saved movie = {} ({} frames)", path, movie.frames()),
+            Err(err) => error!("failed to save movie = {}", err),
+        }
+    }
+}
+
+fn play_movie(emu: &mut Emulator, program: &[u8], rom: &Option<String>) {
+    let path = movie_path(rom);
+    let result = fs::read(&path)
+        .map_err(Box::<dyn Error>::from)
+        .and_then(|movie| Ok(Movie::from_bytes(&movie)?))
+        .and_then(|movie| Ok((movie.start(program)?, movie)));
+    match result {
+        Ok(((cpu, clock), movie)) => {
+            info!("playing movie = {} ({} frames)", path, movie.frames());
+            replace_cpu(emu, cpu);
+            replace_clock(emu, clock);
+            emu.tape = Tape::Playing(Player::new(movie));
+        }
+        Err(err) => error!("failed to play movie = {}", err),
+    }
+}
+
+/// Replaces the cpu, keeping the breakpoints set from the debugger.
+fn replace_cpu(emu: &mut Emulator, mut cpu: Cpu) {
+    *cpu.breakpoints_mut() = mem::take(emu.cpu.breakpoints_mut());
+    emu.cpu = cpu;
+}
+
+/// Replaces the clock, keeping the audio generator.
+fn replace_clock(emu: &mut Emulator, mut clock: Clock) {
+    clock.set_audio(emu.clock.take_audio());
+    emu.clock = clock;
+}
+
 fn load_program(path: &Option<String>) -> io::Result<Box<[u8]>> {
     let mut rom: Box<dyn Read> = match path {
         None => {
@@ -0,0 +1,39 @@
+use chip8::{
+    clock::{Clock, Elapsed},
+    cpu::{Cpu, CpuError, KeyState},
+    movie::{Player, Recorder},
+};
+
+/// Input movie being recorded or played back.
+pub enum Tape {
+    Stopped,
+    Recording(Recorder),
+    Playing(Player),
+}
+
+impl Tape {
+    /// Return true if nothing is being recorded or played.
+    pub fn is_stopped(&self) -> bool {
+        matches!(self, Tape::Stopped)
+    }
+
+    /// Presses or releases a key. While recording, the event is recorded
+    /// and applied at the start of the next frame. While playing, the host's
+    /// input is ignored.
+    pub fn set_key(&mut self, cpu: &mut Cpu, key: usize, state: KeyState) {
+        match self {
+            Tape::Stopped => cpu.set_key(key, state),
+            Tape::Recording(recorder) => recorder.set_key(key, state),
+            Tape::Playing(_) => {}
+        }
+    }
+
+    /// Runs a frame, recording the input or feeding the recorded one.
+    pub fn advance_frame(&mut self, cpu: &mut Cpu, clock: &mut Clock) -> Result<Elapsed, CpuError> {
+        match self {
+            Tape::Stopped => clock.advance_frame(cpu),
+            Tape::Recording(recorder) => recorder.advance_frame(cpu, clock),
+            Tape::Playing(player) => player.advance_frame(cpu, clock),
+        }
+    }
+}
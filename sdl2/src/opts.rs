@@ -1,3 +1,8 @@
+use chip8::{
+    audio::Waveform,
+    cpu::{Mode, Quirks},
+};
+use std::path::PathBuf;
 use structopt::StructOpt;
 
 /// Program arguments
@@ -7,9 +12,13 @@ pub struct Opts {
     #[structopt(short, long)]
     pub rom: Option<String>,
 
-    /// Steps per clock cycle.
-    #[structopt(short, long, default_value = "1")]
-    pub clock: usize,
+    /// Instructions per second.
+    #[structopt(short, long, default_value = "700")]
+    pub clock: u32,
+
+    /// Use the COSMAC VIP timing model (ignores --clock)
+    #[structopt(long = "vip-timing")]
+    pub vip_timing: bool,
 
     /// Disable sound
     #[structopt(long = "nosound")]
@@ -18,6 +27,30 @@ pub struct Opts {
     /// Sound frequency
     #[structopt(short = "f", long = "freq", default_value = "500")]
     pub beep_freq: u32,
+
+    /// Waveform of the beep (square, sine, triangle or noise)
+    #[structopt(long, default_value = "square")]
+    pub waveform: Waveform,
+
+    /// Sound volume, from 0 to 1
+    #[structopt(long, default_value = "0.25")]
+    pub volume: f32,
+
+    /// Instruction set (chip8, schip or xochip)
+    #[structopt(short, long, default_value = "chip8")]
+    pub mode: Mode,
+
+    /// Seed of the random number generator (defaults to the current time)
+    #[structopt(long)]
+    pub seed: Option<u64>,
+
+    /// Quirks profile (vip, chip48, schip or modern)
+    #[structopt(short, long)]
+    pub quirks: Option<Quirks>,
+
+    /// Key bindings file (TOML), written back by the bindings window
+    #[structopt(long, default_value = "chip8.toml", parse(from_os_str))]
+    pub config: PathBuf,
 }
 
 impl Opts {
@@ -1,36 +1,71 @@
-use crate::opts::Opts;
-use chip8::cpu::Cpu;
+use crate::{
+    input::{Input, Keymap},
+    movie::Tape,
+    opts::Opts,
+};
+use chip8::{
+    audio::{Generator, WavWriter},
+    clock::{Clock, MAX_ELAPSED, TIMER_HZ},
+    cpu::{Cpu, Timing, VIP_HZ},
+    export::{self, GifRecorder, Style},
+    rewind::Rewind,
+};
 use imgui::{im_str, Ui, Window};
-use log::{error, info};
-use sdl2::audio::{AudioCallback, AudioStatus};
-use std::{error::Error, ffi::CStr, ptr};
+use log::{error, info, warn};
+use sdl2::{
+    audio::{AudioQueue, AudioSpecDesired},
+    controller::GameController,
+    keyboard::Scancode,
+    Sdl,
+};
+use std::{
+    error::Error,
+    ffi::CStr,
+    fs::File,
+    io::BufWriter,
+    mem,
+    path::Path,
+    time::{Duration, Instant},
+};
 
 const SAMPLE_RATE: i32 = 44100;
-const MAX_FREQ: i32 = 2000;
 
-struct Wave {
-    rate: i32,
-    freq: i32,
-    phase: i32,
-}
+/// Texture intensity of each colour index (combination of display planes).
+const PALETTE: [u8; 4] = [0x00, 0xFF, 0x80, 0xC0];
+const MAX_FREQ: u32 = 2000;
 
-impl AudioCallback for Wave {
-    type Channel = f32;
-    fn callback(&mut self, samples: &mut [Self::Channel]) {
-        let volume = 0.5;
-        for (i, sample) in samples.iter_mut().enumerate() {
-            #[allow(non_snake_case)]
-            let F = (self.freq as f32) / (self.rate as f32);
-            let phase = 2.0 * 3.14159265 * F * (self.phase as usize + i) as f32;
-            *sample = phase.cos() * volume;
-        }
-        self.phase += samples.len() as i32;
-    }
+/// Silence queued ahead when playback starts, or runs dry, so the device
+/// doesn't starve between frames.
+const PREBUFFER: Duration = Duration::from_millis(50);
+
+/// Most sound queued ahead. Past this the queue is dropped, so the sound
+/// doesn't lag behind the display.
+const MAX_QUEUED: Duration = Duration::from_millis(250);
+
+/// Rewind history, in frames (10 seconds at 60 fps).
+const REWIND_FRAMES: usize = 600;
+
+/// Holding this key plays time backwards.
+const REWIND_KEY: Scancode = Scancode::Backspace;
+
+/// Saves a screenshot of the display next to the ROM.
+const SCREENSHOT_KEY: Scancode = Scancode::F12;
+
+/// Emulation driven by the runner.
+pub struct Emulator {
+    pub cpu: Cpu,
+    pub clock: Clock,
+    pub tape: Tape,
+    /// Animated GIF being recorded, one frame per timer update.
+    pub gif: Option<GifRecorder<BufWriter<File>>>,
+    /// Sound being recorded into a WAV file.
+    pub wav: Option<WavWriter<BufWriter<File>>>,
+    pub input: Input,
 }
 
-pub fn run<F>(mut cpu: Cpu, mut closure: F) -> Result<(), Box<dyn Error>>
+pub fn run<F>(cpu: Cpu, keymap: Keymap, mut closure: F) -> Result<(), Box<dyn Error>>
 where
-    F: FnMut(&mut Cpu, &Ui),
+    F: FnMut(&mut Emulator, &Ui),
 {
     let opts = Opts::from_args();
     let app = imgui_very_quick::builder()
@@ -38,30 +73,26 @@ where
         .background(0.5, 0.5, 0.5, 1.0)
         .build()?;
 
-    let audio_spec = sdl2::audio::AudioSpecDesired {
+    let audio_spec = AudioSpecDesired {
         freq: Some(SAMPLE_RATE),
         channels: Some(1),
         samples: None,
     };
-    let device = if !opts.no_sound {
+    // the clock renders the sound as it runs, and the samples of every frame
+    // are queued for playback
+    let queue = if !opts.no_sound {
         info!("initializing SDL audio device");
         let audio = app.sdl().audio()?;
-        let device = audio.open_playback(None, &audio_spec, |spec| {
-            info!("sampling rate = {}", spec.freq);
-            info!("channels = {}", spec.channels);
-            info!("format = {:?}", spec.format);
-            info!("buffer size (samples) = {}", spec.samples);
-            info!("size = {}", spec.size);
-            let freq = (opts.beep_freq as i32).min(MAX_FREQ);
-            info!("wave frequency = {}", freq);
-            Wave {
-                rate: spec.freq,
-                freq,
-                phase: 0,
+        match audio.open_queue::<f32, _>(None, &audio_spec) {
+            Ok(queue) => {
+                let spec = queue.spec();
+                info!("sampling rate = {}", spec.freq);
+                info!("channels = {}", spec.channels);
+                info!("format = {:?}", spec.format);
+                info!("buffer size (samples) = {}", spec.samples);
+                queue.resume();
+                Some(queue)
             }
-        });
-        match device {
-            Ok(device) => Some(device),
             Err(err) => {
                 error!("failed to initialize audio device = {}", err);
                 None
@@ -75,6 +106,38 @@ where
     log_gl();
 
     let mut scale = 4.0;
+    let mut pixels = Vec::new();
+    let mut rewind = Rewind::new(1, REWIND_FRAMES);
+    let mut clock = match cpu.timing() {
+        Timing::CosmacVip => Clock::new(VIP_HZ),
+        Timing::Instructions => Clock::new(opts.clock),
+    };
+    // sound is rendered even without an audio device, so it can be recorded
+    let rate = queue
+        .as_ref()
+        .map_or(SAMPLE_RATE, |queue| queue.spec().freq);
+    let freq = opts.beep_freq.min(MAX_FREQ);
+    info!("beep frequency = {}", freq);
+    info!("waveform = {}", opts.waveform);
+    let mut generator = Generator::new(rate as u32);
+    generator.set_frequency(f64::from(freq));
+    generator.set_waveform(opts.waveform);
+    generator.set_volume(opts.volume);
+    clock.set_audio(Some(generator));
+    let mut emu = Emulator {
+        cpu,
+        clock,
+        tape: Tape::Stopped,
+        gif: None,
+        wav: None,
+        input: Input::new(keymap, open_controller(app.sdl())),
+    };
+    // wall time not yet run while a movie is recorded or played
+    let mut lag = Duration::default();
+    let frame = Duration::from_secs(1) / TIMER_HZ;
+    let mut screenshot_held = false;
+    let mut dropped = 0;
+    let mut last_frame = Instant::now();
     let mut texture: gl::types::GLuint = 0;
     unsafe {
         gl::GenTextures(1, &mut texture);
@@ -83,50 +146,117 @@ where
         gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as _);
         gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as _);
         gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as _);
-        #[rustfmt::skip]
-        gl::TexImage2D(
-            gl::TEXTURE_2D, 0, gl::R8 as _, 64, 32, 0, gl::RED, gl::UNSIGNED_BYTE, ptr::null());
         gl::BindTexture(gl::TEXTURE_2D, 0);
     }
 
     app.run(|ui| {
-        for _ in 0..opts.clock {
-            cpu.step();
+        let now = Instant::now();
+        let elapsed = now - last_frame;
+        last_frame = now;
+
+        emu.input.update(&ui, &mut emu.cpu, &mut emu.tape);
+        let Emulator {
+            cpu, clock, tape, ..
+        } = &mut emu;
+        let mut frames = 0;
+        if tape.is_stopped() {
+            if ui.io().keys_down[REWIND_KEY as usize] {
+                rewind.rewind(cpu);
+            } else {
+                match clock.advance(cpu, elapsed) {
+                    Ok(elapsed) => {
+                        frames = elapsed.frames;
+                        if frames > 0 {
+                            rewind.record(cpu);
+                        }
+                    }
+                    Err(err) => error!("cpu fault = {}", err),
+                }
+            }
+        } else {
+            // movies are driven frame by frame, so input lands on the same
+            // instruction when played back (rewinding is disabled)
+            lag = (lag + elapsed).min(MAX_ELAPSED);
+            while lag >= frame {
+                lag -= frame;
+                match tape.advance_frame(cpu, clock) {
+                    Ok(_) => {
+                        frames += 1;
+                        rewind.record(cpu);
+                    }
+                    Err(err) => {
+                        error!("cpu fault = {}", err);
+                        lag = Duration::default();
+                    }
+                }
+            }
+            if let Tape::Playing(player) = tape {
+                if player.is_finished() {
+                    info!("movie finished, {} frames", player.frame());
+                    *tape = Tape::Stopped;
+                }
+            }
         }
+        if let Some(gif) = &mut emu.gif {
+            let display = emu.cpu.display();
+            if let Err(err) = (0..frames).try_for_each(|_| gif.frame(display)) {
+                error!("failed to record GIF = {}", err);
+                emu.gif = None;
+            }
+        }
+        let cpu = &emu.cpu;
 
-        cpu.update_timers();
+        let screenshot_down = ui.io().keys_down[SCREENSHOT_KEY as usize];
+        if screenshot_down && !screenshot_held {
+            screenshot(cpu, &opts.rom, scale as usize);
+        }
+        screenshot_held = screenshot_down;
 
-        if let Some(device) = &device {
-            let st = cpu.sound_timer();
-            match (st, device.status()) {
-                (0, AudioStatus::Playing) => device.pause(),
-                (_, AudioStatus::Paused) | (_, AudioStatus::Stopped) if st > 0 => {
-                    device.resume();
+        if let Some(audio) = emu.clock.audio_mut() {
+            let samples = audio.take_samples();
+            if audio.dropped() > dropped {
+                warn!("dropped {} audio samples", audio.dropped() - dropped);
+                dropped = audio.dropped();
+            }
+            if let Some(queue) = &queue {
+                play(queue, &samples);
+            }
+            if let Some(wav) = &mut emu.wav {
+                if let Err(err) = wav.write(&samples) {
+                    error!("failed to record WAV = {}", err);
+                    emu.wav = None;
                 }
-                _ => {}
             }
         }
 
-        // update texture
+        // update texture (the resolution may change between frames)
+        let display = cpu.display();
+        pixels.clear();
+        pixels.extend(display.colors().map(|color| PALETTE[color as usize]));
         unsafe {
-            let pixels = cpu.display().as_ptr();
+            let (width, height) = (display.width() as _, display.height() as _);
             gl::BindTexture(gl::TEXTURE_2D, texture);
             #[rustfmt::skip]
-                gl::TexSubImage2D(
-                gl::TEXTURE_2D, 0, 0, 0, 64, 32, gl::RED, gl::UNSIGNED_BYTE, pixels as _);
+            gl::TexImage2D(
+                gl::TEXTURE_2D, 0, gl::R8 as _, width, height, 0, gl::RED, gl::UNSIGNED_BYTE, pixels.as_ptr() as _);
             gl::BindTexture(gl::TEXTURE_2D, 0);
         }
 
-        closure(&mut cpu, &ui);
+        closure(&mut emu, &ui);
 
-        // texture window
+        // texture window, sized after the current resolution
+        let display = emu.cpu.display();
+        let size = [
+            display.width() as f32 * scale,
+            display.height() as f32 * scale,
+        ];
         Window::new(im_str!("Display"))
             .always_auto_resize(true)
             .resizable(false)
             .build(&ui, || {
                 imgui::Image::new(
                     imgui::TextureId::from(texture as usize),
-                    [64.0 * scale, 32.0 * scale],
+                    size,
                 )
                 .border_col([1.0; 4])
                 .build(&ui);
@@ -154,10 +284,78 @@ where
     unsafe {
         gl::DeleteTextures(1, &mut texture);
     }
+    if let Some(gif) = emu.gif.take() {
+        if let Err(err) = gif.finish() {
+            error!("failed to save GIF = {}", err);
+        }
+    }
+    if let Some(wav) = emu.wav.take() {
+        if let Err(err) = wav.finish() {
+            error!("failed to save WAV = {}", err);
+        }
+    }
 
     Ok(())
 }
 
+/// Queues samples for playback.
+fn play(queue: &AudioQueue<f32>, samples: &[f32]) {
+    let rate = queue.spec().freq as u32;
+    let samples_in = |time: Duration| (time.as_secs_f64() * f64::from(rate)) as usize;
+    let mut queued = queue.size() as usize / mem::size_of::<f32>();
+    if queued > samples_in(MAX_QUEUED) {
+        queue.clear();
+        queued = 0;
+    }
+    if queued == 0 {
+        queue.queue(&vec![0.0; samples_in(PREBUFFER)]);
+    }
+    if !queue.queue(samples) {
+        error!("failed to queue audio = {}", sdl2::get_error());
+    }
+}
+
+/// Opens the first game controller, if any is plugged in.
+fn open_controller(sdl: &Sdl) -> Option<GameController> {
+    let subsystem = match sdl.game_controller() {
+        Ok(subsystem) => subsystem,
+        Err(err) => {
+            error!("failed to initialize game controllers = {}", err);
+            return None;
+        }
+    };
+    let joysticks = subsystem.num_joysticks().unwrap_or(0);
+    (0..joysticks)
+        .filter(|index| subsystem.is_game_controller(*index))
+        .find_map(|index| match subsystem.open(index) {
+            Ok(controller) => {
+                info!("game controller = {}", controller.name());
+                Some(controller)
+            }
+            Err(err) => {
+                error!("failed to open game controller = {}", err);
+                None
+            }
+        })
+}
+
+/// Return a path next to the ROM, numbered from 1, that isn't taken yet.
+pub fn numbered_path(rom: &Option<String>, extension: &str) -> String {
+    let rom = rom.as_deref().unwrap_or("stdin");
+    (1..)
+        .map(|n| format!("{}.{}.{}", rom, n, extension))
+        .find(|path| !Path::new(path).exists())
+        .unwrap()
+}
+
+fn screenshot(cpu: &Cpu, rom: &Option<String>, scale: usize) {
+    let path = numbered_path(rom, "png");
+    match export::save(&path, cpu.display(), &Style::default().with_scale(scale)) {
+        Ok(_) => info!("saved screenshot = {}", path),
+        Err(err) => error!("failed to save screenshot = {}", err),
+    }
+}
+
 fn log_gl() {
     unsafe {
         #[rustfmt::skip]
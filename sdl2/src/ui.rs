@@ -1,4 +1,6 @@
+pub mod bindings;
 pub mod debug;
+pub mod disasm;
 pub mod keypad;
 pub mod memory;
 pub mod registers;
@@ -0,0 +1,100 @@
+use crate::{config::Config, input::Input};
+use imgui::{im_str, Ui, Window};
+use sdl2::keyboard::Scancode;
+
+/// Device of a binding.
+#[derive(Clone, Copy, PartialEq)]
+enum Device {
+    Keyboard,
+    Gamepad,
+}
+
+/// State of the bindings window.
+#[derive(Default)]
+pub struct Dialog {
+    /// Changes only apply to the loaded ROM.
+    per_rom: bool,
+    /// Binding waiting for a key or button to be pressed.
+    waiting: Option<(usize, Device)>,
+}
+
+/// Draw key bindings gui. Clicking a binding waits for a key or button to
+/// be pressed (Escape cancels, Delete unbinds the key).
+///
+/// Returns true if the bindings of `config` changed.
+pub fn draw(
+    ui: &Ui,
+    dialog: &mut Dialog,
+    config: &mut Config,
+    rom_hash: u32,
+    input: &Input,
+) -> bool {
+    Window::new(im_str!("Bindings"))
+        .always_auto_resize(true)
+        .resizable(false)
+        .build(ui, || {
+            ui.radio_button(im_str!("All ROMs"), &mut dialog.per_rom, false);
+            ui.same_line(0.0);
+            ui.radio_button(im_str!("This ROM"), &mut dialog.per_rom, true);
+            ui.separator();
+            let keymap = input.keymap();
+            for key in 0..16 {
+                let keyboard = match keymap.keys[key] {
+                    Some(code) => code.name().to_string(),
+                    None => "-".to_string(),
+                };
+                let pad = match keymap.pad[key] {
+                    Some(pad) => pad.to_string(),
+                    None => "-".to_string(),
+                };
+                ui.text(format!("{:X}", key));
+                let columns = [
+                    (Device::Keyboard, keyboard, "key", 32.0),
+                    (Device::Gamepad, pad, "pad", 160.0),
+                ];
+                for (device, name, id, x) in columns.iter().cloned() {
+                    let label = if dialog.waiting == Some((key, device)) {
+                        "...".to_string()
+                    } else {
+                        name
+                    };
+                    ui.same_line(x);
+                    if ui.button(&im_str!("{}##{}{}", label, id, key), [120.0, 0.0]) {
+                        dialog.waiting = Some((key, device));
+                    }
+                }
+            }
+        });
+
+    let (key, device) = match dialog.waiting {
+        Some(waiting) => waiting,
+        None => return false,
+    };
+    let io = ui.io();
+    let name = if io.keys_down[Scancode::Escape as usize] {
+        dialog.waiting = None;
+        return false;
+    } else if io.keys_down[Scancode::Delete as usize] {
+        String::new()
+    } else {
+        let name = match device {
+            Device::Keyboard => (0..io.keys_down.len())
+                .filter(|code| io.keys_down[*code])
+                .filter_map(|code| Scancode::from_i32(code as i32))
+                .map(|code| code.name().to_string())
+                .find(|name| !name.is_empty()),
+            Device::Gamepad => input.pad_pressed().map(|pad| pad.to_string()),
+        };
+        match name {
+            Some(name) => name,
+            None => return false,
+        }
+    };
+    let bindings = config.bindings_mut(if dialog.per_rom { Some(rom_hash) } else { None });
+    match device {
+        Device::Keyboard => bindings.set_key(key, name),
+        Device::Gamepad => bindings.set_pad(key, name),
+    }
+    dialog.waiting = None;
+    true
+}
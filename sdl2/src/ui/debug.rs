@@ -1,12 +1,27 @@
-use chip8::cpu::{Cpu, Opcode};
+use chip8::cpu::{Access, Breakpoint, Cmp, Cpu, CpuError, CpuState, Opcode};
 use imgui::{im_str, Ui, Window};
 use lazy_static::lazy_static;
+use log::error;
 use std::sync::Mutex;
 
+/// Values of the "add breakpoint" form.
+struct Form {
+    addr: i32,
+    len: i32,
+    register: i32,
+    value: i32,
+}
+
 /// Draw debuger gui
 pub fn draw(ui: &Ui, cpu: &mut Cpu) {
     lazy_static! {
-        static ref OPCODE: Mutex<Option<Opcode>> = Mutex::new(None);
+        static ref OPCODE: Mutex<Option<Result<Opcode, CpuError>>> = Mutex::new(None);
+        static ref FORM: Mutex<Form> = Mutex::new(Form {
+            addr: 0x200,
+            len: 1,
+            register: 0,
+            value: 0,
+        });
     }
 
     Window::new(im_str!("Debugger")).build(ui, || {
@@ -14,17 +29,107 @@ pub fn draw(ui: &Ui, cpu: &mut Cpu) {
             cpu.halt();
         }
         if ui.small_button(im_str!("Step")) {
-            cpu.fetch_execute();
+            if let Err(err) = cpu.fetch_execute() {
+                error!("cpu fault = {}", err);
+            }
         }
         if ui.small_button(im_str!("Fetch instruction")) {
             let op = cpu.fetch();
             OPCODE.lock().unwrap().replace(op);
         }
 
-        OPCODE
-            .lock()
-            .unwrap()
-            .iter()
-            .for_each(|op| ui.text(format!("{:?}", op)));
+        OPCODE.lock().unwrap().iter().for_each(|op| match op {
+            Ok(op) => ui.text(format!("{}", op)),
+            Err(err) => ui.text(format!("{}", err)),
+        });
+
+        if let CpuState::Break(trigger) = *cpu.state() {
+            ui.separator();
+            match trigger.addr {
+                Some(addr) => ui.text(format!(
+                    "Breakpoint #{} at {:03X} (address {:03X})",
+                    trigger.id, trigger.pc, addr
+                )),
+                None => ui.text(format!("Breakpoint #{} at {:03X}", trigger.id, trigger.pc)),
+            }
+            if ui.small_button(im_str!("Continue")) {
+                cpu.resume();
+            }
+        }
+
+        ui.separator();
+        ui.text("Breakpoints");
+        let mut toggled = None;
+        let mut removed = None;
+        for entry in cpu.breakpoints().iter() {
+            let mut enabled = entry.enabled;
+            let label = im_str!("#{} {}##enabled{}", entry.id, entry.breakpoint, entry.id);
+            if ui.checkbox(&label, &mut enabled) {
+                toggled = Some((entry.id, enabled));
+            }
+            ui.same_line(0.0);
+            if ui.small_button(&im_str!("Remove##{}", entry.id)) {
+                removed = Some(entry.id);
+            }
+        }
+        if let Some((id, enabled)) = toggled {
+            cpu.breakpoints_mut().set_enabled(id, enabled);
+        }
+        if let Some(id) = removed {
+            cpu.breakpoints_mut().remove(id);
+        }
+
+        ui.separator();
+        let mut form = FORM.lock().unwrap();
+        let mut added = None;
+        ui.input_int(im_str!("Address"), &mut form.addr).build();
+        ui.input_int(im_str!("Length"), &mut form.len).build();
+        let addr = form.addr.max(0) as usize;
+        let range = addr..addr + form.len.max(1) as usize;
+        if ui.small_button(im_str!("Break at PC")) {
+            added = Some(Breakpoint::Pc(addr));
+        }
+        ui.same_line(0.0);
+        if ui.small_button(im_str!("Watch reads")) {
+            added = Some(Breakpoint::Watch {
+                range: range.clone(),
+                access: Access::Read,
+            });
+        }
+        ui.same_line(0.0);
+        if ui.small_button(im_str!("Watch writes")) {
+            added = Some(Breakpoint::Watch {
+                range,
+                access: Access::Write,
+            });
+        }
+
+        ui.input_int(im_str!("Register"), &mut form.register)
+            .build();
+        ui.input_int(im_str!("Value"), &mut form.value).build();
+        let (register, value) = (form.register as u8 & 0xF, form.value as u8);
+        for (i, cmp) in [Cmp::Eq, Cmp::Ne, Cmp::Lt, Cmp::Gt].iter().enumerate() {
+            if i > 0 {
+                ui.same_line(0.0);
+            }
+            if ui.small_button(&im_str!("V{:X} {} {:02X}", register, cmp, value)) {
+                added = Some(Breakpoint::Register {
+                    register,
+                    cmp: *cmp,
+                    value,
+                });
+            }
+        }
+
+        if ui.small_button(im_str!("Break on DRW")) {
+            added = Some(Breakpoint::Opcode(Opcode::DRW_Vx_Vy_nibble(0, 0, 0)));
+        }
+        ui.same_line(0.0);
+        if ui.small_button(im_str!("Break on LD Vx, K")) {
+            added = Some(Breakpoint::Opcode(Opcode::LD_Vx_K(0)));
+        }
+        if let Some(breakpoint) = added {
+            cpu.breakpoints_mut().add(breakpoint);
+        }
     });
 }
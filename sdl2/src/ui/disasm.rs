@@ -0,0 +1,40 @@
+use chip8::{cpu::Cpu, disasm};
+use imgui::{im_str, Ui, Window};
+
+/// Number of lines shown before and after the PC.
+const CONTEXT: usize = 16;
+
+/// State of the disassembly window.
+#[derive(Default)]
+pub struct Disasm {
+    /// Listing of the memory, disassembled again only when it changes.
+    cache: disasm::Cache,
+}
+
+/// Draw disassembly gui, centered on the PC
+pub fn draw(ui: &Ui, disasm: &mut Disasm, cpu: &Cpu) {
+    Window::new(im_str!("Disassembly")).build(ui, || {
+        let pc = cpu.program_counter();
+        let listing = disasm.cache.listing(cpu.memory(), pc);
+        let lines = listing.lines();
+        let center = listing.line_index(pc).unwrap_or(0);
+        let start = center.saturating_sub(CONTEXT);
+        let end = (center + CONTEXT + 1).min(lines.len());
+        for line in &lines[start..end] {
+            if let Some(label) = listing.label(line.addr) {
+                ui.text(format!("{}:", label));
+            }
+            let text = format!(
+                "  {:03X}  {:<14}{}",
+                line.addr,
+                line.hex(),
+                listing.text(line)
+            );
+            if line.addr == pc {
+                ui.text_colored([1.0, 1.0, 0.0, 1.0], text);
+            } else {
+                ui.text(text);
+            }
+        }
+    });
+}
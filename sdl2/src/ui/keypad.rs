@@ -1,8 +1,14 @@
-use imgui::{im_str, Ui, Window};
+use chip8::cpu::KeyState;
+use imgui::{im_str, StyleColor, Ui, Window};
 
-/// Draw keypad gui
-pub fn draw(ui: &Ui) -> Option<usize> {
-    let mut pressed = None;
+/// Colour of the buttons of the keys that are down.
+const HELD_COLOR: [f32; 4] = [0.9, 0.6, 0.1, 1.0];
+
+/// Draw keypad gui. Keys that are down in `keypad` are highlighted.
+///
+/// Returns the buttons held with the mouse.
+pub fn draw(ui: &Ui, keypad: &[KeyState; 16]) -> [bool; 16] {
+    let mut held = [false; 16];
     Window::new(im_str!("Keypad"))
         .always_auto_resize(true)
         .resizable(false)
@@ -24,12 +30,19 @@ pub fn draw(ui: &Ui) -> Option<usize> {
 
             for i in 0..4 {
                 for j in 0..4 {
+                    let key = code[i][j];
+                    let token = match keypad[key] {
+                        KeyState::Down => Some(ui.push_style_color(StyleColor::Button, HELD_COLOR)),
+                        KeyState::Up => None,
+                    };
                     ui.set_cursor_pos([x + 28.0 * (j as f32), y + 28.0 * (i as f32)]);
-                    if ui.button(labels[i][j], size) {
-                        pressed = Some(code[i][j]);
+                    ui.button(labels[i][j], size);
+                    held[key] = ui.is_item_hovered() && ui.io().mouse_down[0];
+                    if let Some(token) = token {
+                        token.pop(ui);
                     }
                 }
             }
         });
-    pressed
+    held
 }
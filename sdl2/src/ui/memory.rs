@@ -1,5 +1,5 @@
 use chip8::cpu::Cpu;
-use imgui::{im_str, StyleColor, Ui, Window};
+use imgui::{im_str, sys, StyleColor, Ui, Window};
 
 fn byte_color(cpu: &Cpu, addr: u16, byte: u8) -> [f32; 4] {
     let pc = cpu.program_counter() as u16;
@@ -22,15 +22,19 @@ pub fn draw(ui: &Ui, cpu: &Cpu) {
             let mem = cpu.memory();
             let [x, y] = ui.cursor_pos();
             let [cw, ch] = ui.calc_text_size(im_str!("0"), false, 0.0);
-            let rows = 4098 / 16;
-            for row in 0..rows {
+            let rows = mem.len() / 16;
+            // only the rows in view are drawn (XO-CHIP memory has 4096 of them)
+            let (scroll, height) = unsafe { (sys::igGetScrollY(), sys::igGetWindowHeight()) };
+            let first = (((scroll - y) / ch).max(0.0) as usize).min(rows);
+            let last = ((((scroll + height - y) / ch).max(0.0) as usize) + 1).min(rows);
+            for row in first..last {
                 let v_offset = ch * row as f32;
                 let token = ui.push_style_color(StyleColor::Text, [0.25, 0.25, 0.25, 1.0]);
                 ui.set_cursor_pos([x, y + v_offset]);
-                ui.text(format!("{:03X}", 0x10 * row));
+                ui.text(format!("{:04X}", 0x10 * row));
                 token.pop(ui);
                 for i in 0..8 {
-                    let h_offset = (cw * 5.0) + (i as f32 * cw * 3.0);
+                    let h_offset = (cw * 6.0) + (i as f32 * cw * 3.0);
                     let addr = 16 * row + i;
                     let byte = mem[addr];
                     let token =
@@ -40,7 +44,7 @@ pub fn draw(ui: &Ui, cpu: &Cpu) {
                     token.pop(ui);
                 }
                 for i in 0..8 {
-                    let h_offset = (cw * 5.0) + (cw * 3.0 * 8.0 + cw) + (i as f32 * cw * 3.0);
+                    let h_offset = (cw * 6.0) + (cw * 3.0 * 8.0 + cw) + (i as f32 * cw * 3.0);
                     let addr = 16 * row + i + 8;
                     let byte = mem[addr];
                     let token =
@@ -50,5 +54,7 @@ pub fn draw(ui: &Ui, cpu: &Cpu) {
                     token.pop(ui);
                 }
             }
+            // keep the scrollable area the size of the whole memory
+            ui.set_cursor_pos([x, y + ch * rows as f32]);
         });
 }
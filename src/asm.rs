@@ -0,0 +1,901 @@
+//! Assembler.
+//!
+//! Source has one statement per line, in the syntax produced by the
+//! [`disasm`](crate::disasm) module. Mnemonics, registers and directives are
+//! case insensitive, while symbols are not.
+//!
+//! ```text
+//! ; comments start with a semicolon
+//! SPEED = 2                   ; constant (`SPEED EQU 2` also works)
+//! INCLUDE "sprites.asm"       ; path relative to the including file
+//!
+//! start:
+//!     LD V0, SPEED
+//!     LD I, ball
+//!     DRW V0, V1, 1
+//!     JP start + 2            ; expressions add and subtract numbers and symbols
+//!
+//! ball:
+//!     DB 0x80                 ; bytes, also "strings"
+//!     DW 0x1234, start        ; big endian words
+//! ```
+//!
+//! Numbers are decimal, or hexadecimal and binary with the `0x` and `0b`
+//! prefixes. Programs are assembled to be loaded at `0x200`.
+use crate::cpu::Opcode;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt, fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+/// Address where programs are loaded.
+const ORIGIN: usize = 0x200;
+/// Maximum depth of nested includes.
+const MAX_INCLUDE_DEPTH: usize = 16;
+/// Maximum depth of constants defined in terms of other constants.
+const MAX_SYMBOL_DEPTH: usize = 64;
+
+const MNEMONICS: &[&str] = &[
+    "SYS", "SCD", "SCU", "CLS", "RET", "SCR", "SCL", "EXIT", "LOW", "HIGH", "JP", "CALL", "SE",
+    "SNE", "SAVE", "LOAD", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SHR", "SUBN", "SHL", "RND",
+    "DRW", "SKP", "SKNP", "PLANE", "AUDIO", "PITCH",
+];
+
+/// Kind of an [`AsmError`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ErrorKind {
+    /// Unknown instruction or directive.
+    UnknownMnemonic(String),
+    /// The operands don't match any form of the instruction.
+    InvalidOperands(String),
+    /// Malformed statement, number or string.
+    Syntax(String),
+    /// The symbol is neither a label nor a constant.
+    UndefinedSymbol(String),
+    /// The symbol is defined more than once.
+    DuplicateSymbol(String),
+    /// The constant is defined in terms of itself.
+    RecursiveSymbol(String),
+    /// The value doesn't fit in its field.
+    OutOfRange { value: i64, min: i64, max: i64 },
+    /// The file couldn't be read.
+    Include(String),
+}
+
+/// Error raised while assembling, with the location it refers to. Lines and
+/// columns start at 1.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub kind: ErrorKind,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::UnknownMnemonic(m) => write!(f, "unknown instruction or directive `{}`", m),
+            ErrorKind::InvalidOperands(m) => write!(f, "invalid operands for `{}`", m),
+            ErrorKind::Syntax(msg) => write!(f, "{}", msg),
+            ErrorKind::UndefinedSymbol(s) => write!(f, "undefined symbol `{}`", s),
+            ErrorKind::DuplicateSymbol(s) => write!(f, "symbol `{}` is already defined", s),
+            ErrorKind::RecursiveSymbol(s) => write!(f, "symbol `{}` is defined recursively", s),
+            ErrorKind::OutOfRange { value, min, max } => {
+                write!(f, "value {} out of range ({} to {})", value, min, max)
+            }
+            ErrorKind::Include(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file, self.line, self.column, self.kind
+        )
+    }
+}
+
+impl Error for AsmError {}
+
+/// Assembles source text. Included files are resolved relative to the
+/// working directory.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut asm = Assembler::default();
+    asm.parse(source, &Rc::from("<source>"), Path::new(""), 0)?;
+    asm.emit()
+}
+
+/// Assembles a source file. Included files are resolved relative to the
+/// file that includes them.
+pub fn assemble_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, AsmError> {
+    let path = path.as_ref();
+    let file = Rc::from(path.display().to_string());
+    let mut asm = Assembler::default();
+    asm.include(path, &Location::new(&file, 0, 0), 0)?;
+    asm.emit()
+}
+
+#[derive(Clone)]
+struct Location {
+    file: Rc<str>,
+    line: usize,
+    column: usize,
+}
+
+impl Location {
+    fn new(file: &Rc<str>, line: usize, column: usize) -> Self {
+        Self {
+            file: Rc::clone(file),
+            line,
+            column,
+        }
+    }
+
+    fn at(&self, column: usize) -> Self {
+        Self {
+            column,
+            ..self.clone()
+        }
+    }
+
+    fn error(&self, kind: ErrorKind) -> AsmError {
+        AsmError {
+            file: self.file.to_string(),
+            line: self.line,
+            column: self.column,
+            kind,
+        }
+    }
+}
+
+/// Expression, with the location of its first character.
+struct Expr {
+    text: String,
+    loc: Location,
+}
+
+enum Operand {
+    Reg(u8),
+    I,
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    Long(Expr),
+    Expr(Expr),
+}
+
+enum Data {
+    Expr(Expr),
+    Str(Vec<u8>),
+}
+
+enum Body {
+    Instruction {
+        mnemonic: String,
+        operands: Vec<Operand>,
+    },
+    Bytes(Vec<Data>),
+    Words(Vec<Data>),
+}
+
+struct Statement {
+    loc: Location,
+    body: Body,
+}
+
+enum Symbol {
+    Label(usize),
+    Const(Expr),
+}
+
+#[derive(Default)]
+struct Assembler {
+    statements: Vec<Statement>,
+    symbols: HashMap<String, Symbol>,
+    size: usize,
+}
+
+impl Assembler {
+    fn include(&mut self, path: &Path, from: &Location, depth: usize) -> Result<(), AsmError> {
+        if depth > MAX_INCLUDE_DEPTH {
+            return Err(from.error(ErrorKind::Include("includes nested too deeply".into())));
+        }
+        let source = fs::read_to_string(path).map_err(|err| {
+            from.error(ErrorKind::Include(format!("{}: {}", path.display(), err)))
+        })?;
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let file = Rc::from(path.display().to_string());
+        self.parse(&source, &file, dir, depth)
+    }
+
+    /// First pass: parses every line, and assigns addresses to labels.
+    fn parse(
+        &mut self,
+        source: &str,
+        file: &Rc<str>,
+        dir: &Path,
+        depth: usize,
+    ) -> Result<(), AsmError> {
+        for (i, line) in source.lines().enumerate() {
+            let loc = Location::new(file, i + 1, 1);
+            self.parse_line(strip_comment(line), &loc, dir, depth)?;
+        }
+        Ok(())
+    }
+
+    fn parse_line(
+        &mut self,
+        line: &str,
+        loc: &Location,
+        dir: &Path,
+        depth: usize,
+    ) -> Result<(), AsmError> {
+        let mut rest = Cursor::new(line);
+        let mut word = rest.word();
+
+        // label definition
+        if let Some((name, column)) = word {
+            if rest.eat(':') {
+                self.define(name, Symbol::Label(ORIGIN + self.size), &loc.at(column))?;
+                word = rest.word();
+            }
+        }
+        let (word, column) = match word {
+            Some(word) => word,
+            None if rest.is_empty() => return Ok(()),
+            None => {
+                let loc = loc.at(rest.column());
+                return Err(loc.error(ErrorKind::Syntax("expected a statement".into())));
+            }
+        };
+
+        // constant definition
+        let mut constant = rest.clone();
+        if constant.eat('=')
+            || matches!(constant.word(), Some((w, _)) if w.eq_ignore_ascii_case("EQU"))
+        {
+            let (text, expr_column) = constant.rest();
+            let expr = Expr {
+                text: text.to_string(),
+                loc: loc.at(expr_column),
+            };
+            return self.define(word, Symbol::Const(expr), &loc.at(column));
+        }
+
+        let mnemonic = word.to_ascii_uppercase();
+        let operands = split_operands(&rest, loc)?;
+        let body = match mnemonic.as_str() {
+            "DB" | "DW" => {
+                let data = operands
+                    .into_iter()
+                    .map(|(text, column)| data(text, &loc.at(column)))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if mnemonic == "DB" {
+                    self.size += data.iter().map(|d| d.len()).sum::<usize>();
+                    Body::Bytes(data)
+                } else {
+                    self.size += 2 * data.len();
+                    Body::Words(data)
+                }
+            }
+            "INCLUDE" => {
+                let path = match operands.as_slice() {
+                    [(text, column)] => match data(text, &loc.at(*column))? {
+                        Data::Str(path) => String::from_utf8_lossy(&path).into_owned(),
+                        _ => {
+                            return Err(loc
+                                .at(*column)
+                                .error(ErrorKind::Syntax("expected a path string".into())))
+                        }
+                    },
+                    _ => return Err(loc.at(column).error(ErrorKind::InvalidOperands(mnemonic))),
+                };
+                let path: PathBuf = dir.join(path);
+                return self.include(&path, &loc.at(column), depth + 1);
+            }
+            m if MNEMONICS.contains(&m) => {
+                let operands: Vec<_> = operands
+                    .into_iter()
+                    .map(|(text, column)| operand(text, loc.at(column)))
+                    .collect();
+                self.size += match operands.as_slice() {
+                    [Operand::I, Operand::Long(_)] => 4,
+                    _ => 2,
+                };
+                Body::Instruction { mnemonic, operands }
+            }
+            _ => {
+                return Err(loc
+                    .at(column)
+                    .error(ErrorKind::UnknownMnemonic(word.to_string())))
+            }
+        };
+        self.statements.push(Statement {
+            loc: loc.at(column),
+            body,
+        });
+        Ok(())
+    }
+
+    fn define(&mut self, name: &str, symbol: Symbol, loc: &Location) -> Result<(), AsmError> {
+        if self.symbols.contains_key(name) {
+            return Err(loc.error(ErrorKind::DuplicateSymbol(name.to_string())));
+        }
+        self.symbols.insert(name.to_string(), symbol);
+        Ok(())
+    }
+
+    /// Second pass: evaluates operands and encodes every statement.
+    fn emit(&self) -> Result<Vec<u8>, AsmError> {
+        let mut out = Vec::with_capacity(self.size);
+        for statement in &self.statements {
+            match &statement.body {
+                Body::Bytes(data) => {
+                    for d in data {
+                        match d {
+                            Data::Expr(expr) => out.push(self.value(expr, -0x80, 0xFF)? as u8),
+                            Data::Str(bytes) => out.extend_from_slice(bytes),
+                        }
+                    }
+                }
+                Body::Words(data) => {
+                    for d in data {
+                        match d {
+                            Data::Expr(expr) => {
+                                let word = self.value(expr, -0x8000, 0xFFFF)? as u16;
+                                out.extend_from_slice(&word.to_be_bytes());
+                            }
+                            Data::Str(_) => {
+                                let kind =
+                                    ErrorKind::Syntax("strings are only allowed in DB".into());
+                                return Err(statement.loc.error(kind));
+                            }
+                        }
+                    }
+                }
+                Body::Instruction { mnemonic, operands } => {
+                    let op = self.instruction(mnemonic, operands, &statement.loc)?;
+                    let word = op.encode().map_err(|_| {
+                        statement
+                            .loc
+                            .error(ErrorKind::InvalidOperands(mnemonic.to_string()))
+                    })?;
+                    out.extend_from_slice(&word.to_be_bytes());
+                    if let [Operand::I, Operand::Long(nnnn)] = operands.as_slice() {
+                        let nnnn = self.value(nnnn, 0, 0xFFFF)? as u16;
+                        out.extend_from_slice(&nnnn.to_be_bytes());
+                    }
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    fn instruction(
+        &self,
+        mnemonic: &str,
+        operands: &[Operand],
+        loc: &Location,
+    ) -> Result<Opcode, AsmError> {
+        use Operand::*;
+        let addr = |e| self.value(e, 0, 0xFFF).map(|v| v as usize);
+        let byte = |e| self.value(e, -0x80, 0xFF).map(|v| v as u8);
+        let nibble = |e| self.value(e, 0, 0xF).map(|v| v as u8);
+        let op = match (mnemonic, operands) {
+            ("SYS", [Expr(a)]) => Opcode::SYS_addr(addr(a)?),
+            ("SCD", [Expr(n)]) => Opcode::SCD_nibble(nibble(n)?),
+            ("SCU", [Expr(n)]) => Opcode::SCU_nibble(nibble(n)?),
+            ("CLS", []) => Opcode::CLS,
+            ("RET", []) => Opcode::RET,
+            ("SCR", []) => Opcode::SCR,
+            ("SCL", []) => Opcode::SCL,
+            ("EXIT", []) => Opcode::EXIT,
+            ("LOW", []) => Opcode::LOW,
+            ("HIGH", []) => Opcode::HIGH,
+            ("JP", [Expr(a)]) => Opcode::JP_addr(addr(a)?),
+            ("JP", [Reg(0), Expr(a)]) => Opcode::JP_V0_addr(addr(a)?),
+            ("CALL", [Expr(a)]) => Opcode::CALL_addr(addr(a)?),
+            ("SE", [Reg(x), Expr(kk)]) => Opcode::SE_Vx_byte(*x, byte(kk)?),
+            ("SE", [Reg(x), Reg(y)]) => Opcode::SE_Vx_Vy(*x, *y),
+            ("SNE", [Reg(x), Expr(kk)]) => Opcode::SNE_Vx_byte(*x, byte(kk)?),
+            ("SNE", [Reg(x), Reg(y)]) => Opcode::SNE_Vx_Vy(*x, *y),
+            ("SAVE", [Reg(x), Reg(y)]) => Opcode::SAVE_Vx_Vy(*x, *y),
+            ("LOAD", [Reg(x), Reg(y)]) => Opcode::LOAD_Vx_Vy(*x, *y),
+            ("LD", [Reg(x), Expr(kk)]) => Opcode::LD_Vx_byte(*x, byte(kk)?),
+            ("LD", [Reg(x), Reg(y)]) => Opcode::LD_Vx_Vy(*x, *y),
+            ("LD", [I, Expr(a)]) => Opcode::LD_I_addr(addr(a)?),
+            ("LD", [I, Long(_)]) => Opcode::LD_I_long,
+            ("LD", [Reg(x), Dt]) => Opcode::LD_Vx_DT(*x),
+            ("LD", [Reg(x), K]) => Opcode::LD_Vx_K(*x),
+            ("LD", [Dt, Reg(x)]) => Opcode::LD_DT_Vx(*x),
+            ("LD", [St, Reg(x)]) => Opcode::LD_ST_Vx(*x),
+            ("LD", [F, Reg(x)]) => Opcode::LD_F_Vx(*x),
+            ("LD", [Hf, Reg(x)]) => Opcode::LD_HF_Vx(*x),
+            ("LD", [B, Reg(x)]) => Opcode::LD_B_Vx(*x),
+            ("LD", [IndirectI, Reg(x)]) => Opcode::LD_I_Vx(*x),
+            ("LD", [Reg(x), IndirectI]) => Opcode::LD_Vx_I(*x),
+            ("LD", [R, Reg(x)]) => Opcode::LD_R_Vx(*x),
+            ("LD", [Reg(x), R]) => Opcode::LD_Vx_R(*x),
+            ("ADD", [Reg(x), Expr(kk)]) => Opcode::ADD_Vx_byte(*x, byte(kk)?),
+            ("ADD", [Reg(x), Reg(y)]) => Opcode::ADD_Vx_Vy(*x, *y),
+            ("ADD", [I, Reg(x)]) => Opcode::ADD_I_Vx(*x),
+            ("OR", [Reg(x), Reg(y)]) => Opcode::OR_Vx_Vy(*x, *y),
+            ("AND", [Reg(x), Reg(y)]) => Opcode::AND_Vx_Vy(*x, *y),
+            ("XOR", [Reg(x), Reg(y)]) => Opcode::XOR_Vx_Vy(*x, *y),
+            ("SUB", [Reg(x), Reg(y)]) => Opcode::SUB_Vx_Vy(*x, *y),
+            ("SUBN", [Reg(x), Reg(y)]) => Opcode::SUBN_Vx_Vy(*x, *y),
+            ("SHR", [Reg(x), Reg(y)]) => Opcode::SHR_Vx_Vy(*x, *y),
+            ("SHL", [Reg(x), Reg(y)]) => Opcode::SHL_Vx_Vy(*x, *y),
+            // shifting Vx into itself behaves the same with either shift quirk
+            ("SHR", [Reg(x)]) => Opcode::SHR_Vx_Vy(*x, *x),
+            ("SHL", [Reg(x)]) => Opcode::SHL_Vx_Vy(*x, *x),
+            ("RND", [Reg(x), Expr(kk)]) => Opcode::RND_Vx_byte(*x, byte(kk)?),
+            ("DRW", [Reg(x), Reg(y), Expr(n)]) => Opcode::DRW_Vx_Vy_nibble(*x, *y, nibble(n)?),
+            ("SKP", [Reg(x)]) => Opcode::SKP_Vx(*x),
+            ("SKNP", [Reg(x)]) => Opcode::SKNP_Vx(*x),
+            ("PLANE", [Expr(n)]) => Opcode::PLANE_n(nibble(n)?),
+            ("AUDIO", []) => Opcode::AUDIO,
+            ("PITCH", [Reg(x)]) => Opcode::PITCH_Vx(*x),
+            _ => return Err(loc.error(ErrorKind::InvalidOperands(mnemonic.to_string()))),
+        };
+        Ok(op)
+    }
+
+    /// Evaluates an expression, checking that the result is in range.
+    fn value(&self, expr: &Expr, min: i64, max: i64) -> Result<i64, AsmError> {
+        let value = self.eval(expr, 0)?;
+        if value < min || value > max {
+            return Err(expr.loc.error(ErrorKind::OutOfRange { value, min, max }));
+        }
+        Ok(value)
+    }
+
+    fn eval(&self, expr: &Expr, depth: usize) -> Result<i64, AsmError> {
+        let mut cursor = Cursor::new(&expr.text);
+        let column = |cursor: &Cursor| expr.loc.column + cursor.offset();
+        let mut sum = 0i64;
+        let mut sign = if cursor.eat('-') { -1 } else { 1 };
+        loop {
+            let term = match cursor.word() {
+                Some((word, word_column)) => {
+                    let loc = expr.loc.at(expr.loc.column + word_column - 1);
+                    self.term(word, &loc, depth)?
+                }
+                None => {
+                    let kind = ErrorKind::Syntax("expected a number or symbol".into());
+                    return Err(expr.loc.at(column(&cursor)).error(kind));
+                }
+            };
+            sum = sum.wrapping_add(sign * term);
+            sign = if cursor.eat('+') {
+                1
+            } else if cursor.eat('-') {
+                -1
+            } else if cursor.is_empty() {
+                return Ok(sum);
+            } else {
+                let kind = ErrorKind::Syntax("expected `+` or `-`".into());
+                return Err(expr.loc.at(column(&cursor)).error(kind));
+            };
+        }
+    }
+
+    fn term(&self, word: &str, loc: &Location, depth: usize) -> Result<i64, AsmError> {
+        if word.starts_with(|c: char| c.is_ascii_digit()) {
+            return number(word)
+                .ok_or_else(|| loc.error(ErrorKind::Syntax(format!("invalid number `{}`", word))));
+        }
+        match self.symbols.get(word) {
+            Some(Symbol::Label(addr)) => Ok(*addr as i64),
+            Some(Symbol::Const(_)) if depth >= MAX_SYMBOL_DEPTH => {
+                Err(loc.error(ErrorKind::RecursiveSymbol(word.to_string())))
+            }
+            Some(Symbol::Const(expr)) => self.eval(expr, depth + 1),
+            None => Err(loc.error(ErrorKind::UndefinedSymbol(word.to_string()))),
+        }
+    }
+}
+
+impl Data {
+    fn len(&self) -> usize {
+        match self {
+            Data::Expr(_) => 1,
+            Data::Str(bytes) => bytes.len(),
+        }
+    }
+}
+
+/// Helper to scan a line, keeping track of columns.
+#[derive(Clone)]
+struct Cursor<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(text: &'a str) -> Self {
+        Self { text, pos: 0 }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn offset(&self) -> usize {
+        self.pos
+    }
+
+    fn column(&self) -> usize {
+        self.pos + 1
+    }
+
+    fn is_empty(&mut self) -> bool {
+        self.skip_whitespace();
+        self.pos == self.text.len()
+    }
+
+    /// Consumes `c` if it's the next non-blank character.
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.text[self.pos..].starts_with(c) {
+            self.pos += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Consumes the next word (symbol, mnemonic or number) and returns it with
+    /// its column.
+    fn word(&mut self) -> Option<(&'a str, usize)> {
+        self.skip_whitespace();
+        let rest = &self.text[self.pos..];
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+            .unwrap_or(rest.len());
+        if len == 0 {
+            return None;
+        }
+        let column = self.column();
+        self.pos += len;
+        Some((&rest[..len], column))
+    }
+
+    /// Consumes the rest of the line and returns it trimmed, with its column.
+    fn rest(&mut self) -> (&'a str, usize) {
+        self.skip_whitespace();
+        let column = self.column();
+        let rest = self.text[self.pos..].trim_end();
+        self.pos = self.text.len();
+        (rest, column)
+    }
+}
+
+/// Removes the comment from a line, if any.
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Splits the operands of a statement on the commas outside of strings.
+fn split_operands<'a>(
+    cursor: &Cursor<'a>,
+    loc: &Location,
+) -> Result<Vec<(&'a str, usize)>, AsmError> {
+    let mut cursor = cursor.clone();
+    if cursor.is_empty() {
+        return Ok(Vec::new());
+    }
+    let (text, column) = cursor.rest();
+    let mut operands = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    let chars = text.char_indices().map(|(i, c)| (i, Some(c)));
+    for (i, c) in chars.chain(Some((text.len(), None))) {
+        match c {
+            Some(_) if escaped => escaped = false,
+            Some('\\') if quoted => escaped = true,
+            Some('"') => quoted = !quoted,
+            // the end of the line also ends an unterminated string
+            Some(',') | None if !quoted || c.is_none() => {
+                let operand = &text[start..i];
+                let trimmed = operand.trim_start();
+                let operand_column = column + start + operand.len() - trimmed.len();
+                if trimmed.trim_end().is_empty() {
+                    let kind = ErrorKind::Syntax("expected an operand".into());
+                    return Err(loc.at(operand_column).error(kind));
+                }
+                operands.push((trimmed.trim_end(), operand_column));
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    Ok(operands)
+}
+
+fn operand(text: &str, loc: Location) -> Operand {
+    let upper = text.to_ascii_uppercase();
+    match upper.as_str() {
+        "I" => return Operand::I,
+        "[I]" => return Operand::IndirectI,
+        "DT" => return Operand::Dt,
+        "ST" => return Operand::St,
+        "K" => return Operand::K,
+        "F" => return Operand::F,
+        "HF" => return Operand::Hf,
+        "B" => return Operand::B,
+        "R" => return Operand::R,
+        _ => {}
+    }
+    if let Some(x) = register(&upper) {
+        return Operand::Reg(x);
+    }
+    let mut cursor = Cursor::new(text);
+    if let Some((word, _)) = cursor.word() {
+        if word.eq_ignore_ascii_case("LONG") && !cursor.is_empty() {
+            let (text, column) = cursor.rest();
+            return Operand::Long(Expr {
+                text: text.to_string(),
+                loc: loc.at(loc.column + column - 1),
+            });
+        }
+    }
+    Operand::Expr(Expr {
+        text: text.to_string(),
+        loc,
+    })
+}
+
+fn register(text: &str) -> Option<u8> {
+    match text.as_bytes() {
+        [b'V', x] => (*x as char).to_digit(16).map(|x| x as u8),
+        _ => None,
+    }
+}
+
+fn data(text: &str, loc: &Location) -> Result<Data, AsmError> {
+    if !text.starts_with('"') {
+        return Ok(Data::Expr(Expr {
+            text: text.to_string(),
+            loc: loc.clone(),
+        }));
+    }
+    let mut bytes = Vec::new();
+    let mut chars = text[1..].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if chars.as_str().is_empty() => return Ok(Data::Str(bytes)),
+            '\\' => match chars.next() {
+                Some('n') => bytes.push(b'\n'),
+                Some(c @ '"') | Some(c @ '\\') => bytes.push(c as u8),
+                _ => break,
+            },
+            c => {
+                let mut buf = [0; 4];
+                bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+    }
+    Err(loc.error(ErrorKind::Syntax("malformed string".into())))
+}
+
+fn number(text: &str) -> Option<i64> {
+    let lower = text.to_ascii_lowercase();
+    let (digits, radix) = if let Some(hex) = lower.strip_prefix("0x") {
+        (hex, 16)
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        (bin, 2)
+    } else {
+        (&lower[..], 10)
+    };
+    i64::from_str_radix(digits, radix).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assemble, assemble_file, ErrorKind, ORIGIN};
+    use crate::disasm;
+    use std::{env, fs, process};
+
+    /// Return the line, column and kind of the error raised by the source.
+    fn error(source: &str) -> (usize, usize, ErrorKind) {
+        let err = assemble(source).unwrap_err();
+        assert_eq!(err.file, "<source>");
+        (err.line, err.column, err.kind)
+    }
+
+    #[test]
+    fn unknown_mnemonic() {
+        assert_eq!(
+            error("CLS\nloop: JUMP loop"),
+            (2, 7, ErrorKind::UnknownMnemonic("JUMP".into()))
+        );
+    }
+
+    #[test]
+    fn undefined_symbol() {
+        assert_eq!(
+            error("start: CLS\n  LD V0, 1 + speed"),
+            (2, 14, ErrorKind::UndefinedSymbol("speed".into()))
+        );
+    }
+
+    #[test]
+    fn duplicate_symbol() {
+        assert_eq!(
+            error("start: CLS\nSPEED = 1\n  start: RET"),
+            (3, 3, ErrorKind::DuplicateSymbol("start".into()))
+        );
+        assert_eq!(
+            error("SPEED = 1\nSPEED EQU 2"),
+            (2, 1, ErrorKind::DuplicateSymbol("SPEED".into()))
+        );
+    }
+
+    #[test]
+    fn out_of_range() {
+        let kind = ErrorKind::OutOfRange {
+            value: 256,
+            min: -0x80,
+            max: 0xFF,
+        };
+        assert_eq!(error("CLS\n  LD V1, 255 + 1"), (2, 10, kind));
+        let kind = ErrorKind::OutOfRange {
+            value: 16,
+            min: 0,
+            max: 0xF,
+        };
+        assert_eq!(error("DRW V0, V1, 16"), (1, 13, kind));
+    }
+
+    #[test]
+    fn recursive_symbol() {
+        let (line, column, kind) = error("A = B + 1\nB = A\nLD V0, A");
+        assert_eq!(kind, ErrorKind::RecursiveSymbol("A".into()));
+        // the symbol is reported where it's used by the innermost expression
+        assert_eq!((line, column), (2, 5));
+    }
+
+    #[test]
+    fn syntax() {
+        let kind = ErrorKind::Syntax("expected a number or symbol".into());
+        assert_eq!(error("LD V0, 1 +  "), (1, 11, kind.clone()));
+        assert_eq!(error("LD V0, 1 + -2"), (1, 12, kind));
+    }
+
+    #[test]
+    fn forward_references() {
+        let source = "
+            start:
+                CALL sub        ; defined below
+                JP start
+            sub:
+                LD I, sprite
+                RET
+            sprite:
+                DB 0xF0
+        ";
+        assert_eq!(
+            assemble(source).unwrap(),
+            [0x22, 0x04, 0x12, 0x00, 0xA2, 0x08, 0x00, 0xEE, 0xF0]
+        );
+    }
+
+    #[test]
+    fn constants() {
+        let source = "
+            BASE = 0x300
+            OFFSET EQU 4
+            TOP = BASE + OFFSET - 1
+            lower equ -1
+                LD I, TOP
+                LD V0, OFFSET + 0b10 - 1
+                ADD V1, lower
+                JP end - 2
+            end:
+        ";
+        assert_eq!(
+            assemble(source).unwrap(),
+            [0xA3, 0x03, 0x60, 0x05, 0x71, 0xFF, 0x12, 0x06]
+        );
+    }
+
+    #[test]
+    fn data() {
+        let source = r#"
+            text:
+                DB "Hi; \"x\"\n", 0
+                DB 1, -1, 0xFF
+                DW 0x1234, text, -2
+        "#;
+        let mut expected = b"Hi; \"x\"\n\0".to_vec();
+        expected.extend_from_slice(&[1, 0xFF, 0xFF, 0x12, 0x34, 0x02, 0x00, 0xFF, 0xFE]);
+        assert_eq!(assemble(source).unwrap(), expected);
+        assert_eq!(
+            error("DW \"no\""),
+            (
+                1,
+                1,
+                ErrorKind::Syntax("strings are only allowed in DB".into())
+            )
+        );
+    }
+
+    #[test]
+    fn long() {
+        let source = "
+                HIGH
+                LD I, LONG sprite
+                LD I, long 0x1234
+            sprite:
+                DB 0x80
+        ";
+        assert_eq!(
+            assemble(source).unwrap(),
+            [0x00, 0xFF, 0xF0, 0x00, 0x02, 0x0A, 0xF0, 0x00, 0x12, 0x34, 0x80]
+        );
+    }
+
+    #[test]
+    fn include() {
+        let dir = env::temp_dir().join(format!("chip8-asm-{}", process::id()));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(
+            dir.join("main.asm"),
+            "INCLUDE \"lib/consts.asm\"\nLD V0, SPEED\nCALL sub\n",
+        )
+        .unwrap();
+        // relative to the file that includes it
+        fs::write(
+            dir.join("lib/consts.asm"),
+            "SPEED = 3\nINCLUDE \"sub.asm\"\n",
+        )
+        .unwrap();
+        fs::write(dir.join("lib/sub.asm"), "sub: RET\n").unwrap();
+        let result = assemble_file(dir.join("main.asm"));
+        fs::write(dir.join("main.asm"), "CLS\n  INCLUDE \"missing.asm\"\n").unwrap();
+        let missing = assemble_file(dir.join("main.asm")).unwrap_err();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(result.unwrap(), [0x00, 0xEE, 0x60, 0x03, 0x22, 0x00]);
+        assert_eq!((missing.line, missing.column), (2, 3));
+        assert!(matches!(missing.kind, ErrorKind::Include(_)));
+    }
+
+    #[test]
+    fn disassemble_assemble_roms() {
+        let roms: [&[u8]; 2] = [
+            include_bytes!("../roms/Pong (1 player).ch8"),
+            include_bytes!("../roms/Trip8 Demo (2008) [Revival Studios].ch8"),
+        ];
+        for rom in roms.iter() {
+            let source = disasm::disassemble(rom, ORIGIN).to_string();
+            assert_eq!(assemble(&source).unwrap(), *rom);
+        }
+    }
+}
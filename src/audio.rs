@@ -0,0 +1,404 @@
+//! Sound output.
+//!
+//! The buzzer sounds while the sound timer (ST) is non-zero. A [`Generator`]
+//! turns that into audio samples, so every frontend sounds the same.
+//!
+//! Samples are rendered in emulated time: once handed to a [`Clock`] (see
+//! [`Clock::set_audio`]), the generator renders the samples of every slice
+//! of time the clock runs, and follows the cpu after every instruction and
+//! timer update, so the buzzer starts and stops on the exact sample. Hosts
+//! collect the samples with [`Generator::take_samples`] and queue them for
+//! playback (or write them to a file), whether or not there is an audio
+//! device.
+//!
+//! CHIP-8 and SUPER-CHIP programs get a beep with the selected [`Waveform`].
+//! XO-CHIP programs play the 1-bit audio pattern buffer, looped at the rate
+//! set by the pitch register (see [`Cpu::pattern_rate`]). XO-CHIP programs
+//! that never load a pattern (the buffer is all zeroes) get the beep too.
+//!
+//! To avoid clicks, the output fades in and out over short attack and
+//! release times, and the oscillator keeps running while the buzzer is off,
+//! so its phase is continuous.
+//!
+//! [`WavWriter`] records the samples into a WAV file.
+//!
+//! [`Clock`]: crate::clock::Clock
+//! [`Clock::set_audio`]: crate::clock::Clock::set_audio
+use crate::cpu::{Cpu, Mode};
+use std::{f64::consts::PI, fmt, mem, str::FromStr, time::Duration};
+
+mod wav;
+
+pub use wav::WavWriter;
+
+/// Default beep frequency, in Hz.
+pub const DEFAULT_FREQ: f64 = 500.0;
+
+/// Default volume, from 0 to 1.
+pub const DEFAULT_VOLUME: f32 = 0.25;
+
+/// Default time to fade in when the buzzer starts.
+pub const DEFAULT_ATTACK: Duration = Duration::from_millis(2);
+
+/// Default time to fade out when the buzzer stops.
+pub const DEFAULT_RELEASE: Duration = Duration::from_millis(10);
+
+/// Number of 1-bit samples in the XO-CHIP audio pattern buffer.
+const PATTERN_LEN: usize = 128;
+
+/// Longest time rendered samples are kept until
+/// [`Generator::take_samples`] is called. Older samples are dropped.
+const MAX_BUFFERED: Duration = Duration::from_secs(1);
+
+/// Shape of the beep.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub enum Waveform {
+    #[default]
+    Square,
+    Sine,
+    Triangle,
+    /// Random levels, held for half a period of the beep.
+    Noise,
+}
+
+impl Waveform {
+    pub const ALL: [Waveform; 4] = [
+        Waveform::Square,
+        Waveform::Sine,
+        Waveform::Triangle,
+        Waveform::Noise,
+    ];
+}
+
+impl FromStr for Waveform {
+    type Err = String;
+
+    /// Parses `square`, `sine`, `triangle` or `noise`.
+    fn from_str(waveform: &str) -> Result<Self, Self::Err> {
+        match waveform {
+            "square" => Ok(Waveform::Square),
+            "sine" => Ok(Waveform::Sine),
+            "triangle" => Ok(Waveform::Triangle),
+            "noise" => Ok(Waveform::Noise),
+            _ => Err(format!("unknown waveform = {}", waveform)),
+        }
+    }
+}
+
+impl fmt::Display for Waveform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Waveform::Square => "square",
+            Waveform::Sine => "sine",
+            Waveform::Triangle => "triangle",
+            Waveform::Noise => "noise",
+        })
+    }
+}
+
+/// Sound of the buzzer, as last seen on the cpu.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Source {
+    Beep,
+    /// XO-CHIP pattern, and its playback rate in samples per second.
+    Pattern([u8; 16], f64),
+}
+
+/// Audio sample generator driven by the sound timer.
+#[derive(Debug, Clone)]
+pub struct Generator {
+    rate: f64,
+    waveform: Waveform,
+    freq: f64,
+    volume: f32,
+    /// Change of the envelope per sample, while fading in and out.
+    attack: f32,
+    release: f32,
+    gate: bool,
+    source: Source,
+    /// Position in the current period of the beep, or in the pattern, from
+    /// 0 to 1.
+    phase: f64,
+    /// Level of the envelope, from 0 to 1.
+    level: f32,
+    noise: f32,
+    seed: u32,
+    samples: Vec<f32>,
+    max_samples: usize,
+    dropped: u64,
+}
+
+impl Generator {
+    /// Creates a generator of `rate` samples per second, playing a square
+    /// beep at the default frequency, volume and envelope.
+    pub fn new(rate: u32) -> Self {
+        let mut generator = Self {
+            rate: f64::from(rate.max(1)),
+            waveform: Waveform::default(),
+            freq: DEFAULT_FREQ,
+            volume: DEFAULT_VOLUME,
+            attack: 1.0,
+            release: 1.0,
+            gate: false,
+            source: Source::Beep,
+            phase: 0.0,
+            level: 0.0,
+            noise: 0.0,
+            seed: 0x2545_F491,
+            samples: Vec::new(),
+            max_samples: (MAX_BUFFERED.as_secs_f64() * f64::from(rate.max(1))) as usize,
+            dropped: 0,
+        };
+        generator.set_envelope(DEFAULT_ATTACK, DEFAULT_RELEASE);
+        generator
+    }
+
+    /// Return the output rate, in samples per second.
+    pub fn rate(&self) -> u32 {
+        self.rate as u32
+    }
+
+    pub fn waveform(&self) -> Waveform {
+        self.waveform
+    }
+
+    pub fn set_waveform(&mut self, waveform: Waveform) {
+        self.waveform = waveform;
+    }
+
+    /// Return the beep frequency, in Hz.
+    pub fn frequency(&self) -> f64 {
+        self.freq
+    }
+
+    /// Sets the beep frequency, in Hz. It is kept below the Nyquist
+    /// frequency of the output.
+    pub fn set_frequency(&mut self, freq: f64) {
+        self.freq = freq.max(0.0).min(self.rate / 2.0);
+    }
+
+    /// Return the volume, from 0 (silence) to 1 (full scale).
+    pub fn volume(&self) -> f32 {
+        self.volume
+    }
+
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 1.0);
+    }
+
+    /// Sets how long the output takes to fade in when the buzzer starts
+    /// (`attack`) and to fade out when it stops (`release`).
+    pub fn set_envelope(&mut self, attack: Duration, release: Duration) {
+        let rate = self.rate;
+        let step = |time: Duration| (1.0 / (time.as_secs_f64() * rate)).min(1.0) as f32;
+        self.attack = step(attack);
+        self.release = step(release);
+    }
+
+    /// Return true if the buzzer is sounding.
+    pub fn is_playing(&self) -> bool {
+        self.gate
+    }
+
+    /// Takes the state of the buzzer from the cpu. The clock calls it after
+    /// every instruction and timer update.
+    pub fn update(&mut self, cpu: &Cpu) {
+        self.gate = cpu.sound_timer() > 0;
+        let pattern = cpu.audio_pattern();
+        self.source = if cpu.mode() == Mode::XoChip && pattern.iter().any(|b| *b != 0) {
+            Source::Pattern(*pattern, cpu.pattern_rate())
+        } else {
+            Source::Beep
+        };
+    }
+
+    /// Renders the next `count` samples.
+    pub fn render(&mut self, count: usize) {
+        for _ in 0..count {
+            let sample = self.next();
+            self.samples.push(sample);
+        }
+        if self.samples.len() > self.max_samples {
+            let excess = self.samples.len() - self.max_samples;
+            self.samples.drain(..excess);
+            self.dropped += excess as u64;
+        }
+    }
+
+    /// Return the number of samples dropped so far because they weren't
+    /// taken within a second of being rendered. Hosts that take the samples
+    /// after every [`Clock::advance`](crate::clock::Clock::advance) never
+    /// lose any.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Return the samples rendered since the last call.
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        mem::take(&mut self.samples)
+    }
+
+    /// Return the next sample.
+    fn next(&mut self) -> f32 {
+        let (value, step) = match self.source {
+            Source::Beep => {
+                let value = match self.waveform {
+                    Waveform::Square if self.phase < 0.5 => 1.0,
+                    Waveform::Square => -1.0,
+                    Waveform::Sine => (2.0 * PI * self.phase).sin() as f32,
+                    Waveform::Triangle => (1.0 - 4.0 * (self.phase - 0.5).abs()) as f32,
+                    Waveform::Noise => self.noise,
+                };
+                (value, self.freq / self.rate)
+            }
+            Source::Pattern(pattern, rate) => {
+                let bit = (self.phase * PATTERN_LEN as f64) as usize % PATTERN_LEN;
+                let value = if pattern[bit / 8] & (0x80 >> (bit % 8)) != 0 {
+                    1.0
+                } else {
+                    -1.0
+                };
+                (value, rate / PATTERN_LEN as f64 / self.rate)
+            }
+        };
+        let phase = self.phase + step;
+        // new noise level every half period
+        if (phase * 2.0).floor() != (self.phase * 2.0).floor() {
+            self.noise = self.random();
+        }
+        self.phase = phase.fract();
+
+        self.level = if self.gate {
+            (self.level + self.attack).min(1.0)
+        } else {
+            (self.level - self.release).max(0.0)
+        };
+        value * self.level * self.volume
+    }
+
+    /// Return a random level, from -1 to 1.
+    fn random(&mut self) -> f32 {
+        // xorshift32
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        (f64::from(self.seed) / f64::from(u32::MAX) * 2.0 - 1.0) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Generator, Waveform, DEFAULT_VOLUME};
+    use crate::cpu::Cpu;
+    use std::time::Duration;
+
+    /// Return a generator at 48 kHz that sounds right away, with no
+    /// envelope, and a period of 128 samples (so the phase is exact).
+    fn gated(waveform: Waveform) -> Generator {
+        let mut generator = Generator::new(48000);
+        generator.set_envelope(Duration::default(), Duration::default());
+        generator.set_waveform(waveform);
+        generator.set_volume(1.0);
+        generator.set_frequency(375.0);
+        generator.gate = true;
+        generator
+    }
+
+    fn render(generator: &mut Generator, count: usize) -> Vec<f32> {
+        generator.render(count);
+        generator.take_samples()
+    }
+
+    #[test]
+    fn gate_follows_sound_timer() {
+        let mut cpu = Cpu::new();
+        // LD V0, 2; LD ST, V0
+        cpu.load([0x60, 0x02, 0xF0, 0x18]).unwrap();
+        let mut generator = Generator::new(48000);
+        generator.update(&cpu);
+        assert!(!generator.is_playing());
+        assert!(render(&mut generator, 100).iter().all(|s| *s == 0.0));
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        generator.update(&cpu);
+        assert!(generator.is_playing());
+        assert!(render(&mut generator, 100).iter().any(|s| *s != 0.0));
+        cpu.update_timers();
+        cpu.update_timers();
+        generator.update(&cpu);
+        assert!(!generator.is_playing());
+    }
+
+    #[test]
+    fn envelope() {
+        // 2 ms attack and 10 ms release, 96 and 480 samples at 48 kHz
+        let mut generator = Generator::new(48000);
+        generator.gate = true;
+        let attack = render(&mut generator, 200);
+        assert!(attack[0].abs() < DEFAULT_VOLUME / 50.0);
+        assert!(attack.windows(2).take(40).all(|w| w[1].abs() > w[0].abs()));
+        assert!(attack[96..].iter().all(|s| s.abs() == DEFAULT_VOLUME));
+
+        generator.gate = false;
+        let release = render(&mut generator, 600);
+        assert!(release[..470].iter().all(|s| *s != 0.0));
+        assert!(release[480..].iter().all(|s| *s == 0.0));
+    }
+
+    #[test]
+    fn square() {
+        let samples = render(&mut gated(Waveform::Square), 256);
+        assert!(samples[..64].iter().all(|s| *s == 1.0));
+        assert!(samples[64..128].iter().all(|s| *s == -1.0));
+        assert_eq!(samples[..128], samples[128..]);
+    }
+
+    #[test]
+    fn sine() {
+        let samples = render(&mut gated(Waveform::Sine), 128);
+        assert_eq!(samples[0], 0.0);
+        assert_eq!(samples[32], 1.0);
+        assert_eq!(samples[96], -1.0);
+    }
+
+    #[test]
+    fn triangle() {
+        let samples = render(&mut gated(Waveform::Triangle), 128);
+        assert_eq!(samples[0], -1.0);
+        assert_eq!(samples[32], 0.0);
+        assert_eq!(samples[64], 1.0);
+        assert!(samples[..64].windows(2).all(|w| w[1] > w[0]));
+        assert!(samples[64..].windows(2).all(|w| w[1] < w[0]));
+    }
+
+    #[test]
+    fn noise() {
+        // a new level every half period
+        let samples = render(&mut gated(Waveform::Noise), 640);
+        assert!(samples.iter().all(|s| (-1.0..=1.0).contains(s)));
+        for half in samples.chunks(64) {
+            assert!(half.iter().all(|s| *s == half[0]));
+        }
+        assert_ne!(samples[64], samples[128]);
+    }
+
+    #[test]
+    fn volume() {
+        let mut generator = gated(Waveform::Square);
+        generator.set_volume(0.5);
+        assert!(render(&mut generator, 128).iter().all(|s| s.abs() == 0.5));
+        generator.set_volume(2.0);
+        assert_eq!(generator.volume(), 1.0);
+    }
+
+    #[test]
+    fn overflow() {
+        let mut generator = Generator::new(1000);
+        generator.render(600);
+        generator.render(600);
+        assert_eq!(generator.dropped(), 200);
+        assert_eq!(generator.take_samples().len(), 1000);
+        generator.render(1000);
+        assert_eq!(generator.dropped(), 200);
+    }
+}
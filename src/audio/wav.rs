@@ -0,0 +1,148 @@
+//! WAV encoder.
+use std::io::{self, Seek, SeekFrom, Write};
+
+/// Bytes of the header before the samples.
+const HEADER_LEN: u32 = 44;
+
+/// Bytes per sample (16 bit signed PCM).
+const SAMPLE_LEN: u32 = 2;
+
+/// Records samples into a mono, 16 bit PCM WAV file.
+///
+/// The header is written up front with empty sizes, which are filled in by
+/// [`WavWriter::finish`]. A file that isn't finished still holds the
+/// samples, but most players will think it is empty.
+pub struct WavWriter<W: Write + Seek> {
+    out: W,
+    samples: u64,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    /// Writes the header of a WAV of `rate` samples per second.
+    pub fn new(mut out: W, rate: u32) -> io::Result<Self> {
+        out.write_all(b"RIFF")?;
+        out.write_all(&0u32.to_le_bytes())?;
+        out.write_all(b"WAVEfmt ")?;
+        // format chunk: PCM, 1 channel
+        out.write_all(&16u32.to_le_bytes())?;
+        out.write_all(&1u16.to_le_bytes())?;
+        out.write_all(&1u16.to_le_bytes())?;
+        out.write_all(&rate.to_le_bytes())?;
+        out.write_all(&(rate * SAMPLE_LEN).to_le_bytes())?;
+        out.write_all(&(SAMPLE_LEN as u16).to_le_bytes())?;
+        out.write_all(&(SAMPLE_LEN as u16 * 8).to_le_bytes())?;
+        out.write_all(b"data")?;
+        out.write_all(&0u32.to_le_bytes())?;
+        Ok(Self { out, samples: 0 })
+    }
+
+    /// Return the number of samples written so far.
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    /// Writes samples, from -1 to 1 (louder samples are clipped).
+    pub fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        let bytes: Vec<_> = samples
+            .iter()
+            .flat_map(|sample| {
+                let sample = (sample.clamp(-1.0, 1.0) * f32::from(i16::MAX)) as i16;
+                sample.to_le_bytes()
+            })
+            .collect();
+        self.out.write_all(&bytes)?;
+        self.samples += samples.len() as u64;
+        Ok(())
+    }
+
+    /// Fills in the sizes of the header.
+    pub fn finish(mut self) -> io::Result<W> {
+        // sizes are 32 bits, so files past 4 GB are truncated
+        let data = (self.samples * u64::from(SAMPLE_LEN)).min(u64::from(u32::MAX - HEADER_LEN));
+        let data = data as u32;
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out.write_all(&(HEADER_LEN - 8 + data).to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(u64::from(HEADER_LEN) - 4))?;
+        self.out.write_all(&data.to_le_bytes())?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WavWriter;
+    use crate::{
+        audio::Generator,
+        clock::{Clock, MAX_ELAPSED},
+        cpu::Cpu,
+    };
+    use std::io::Cursor;
+
+    fn u16_at(wav: &[u8], pos: usize) -> u16 {
+        u16::from_le_bytes([wav[pos], wav[pos + 1]])
+    }
+
+    fn u32_at(wav: &[u8], pos: usize) -> u32 {
+        u32::from_le_bytes([wav[pos], wav[pos + 1], wav[pos + 2], wav[pos + 3]])
+    }
+
+    #[test]
+    fn header() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), 44100).unwrap();
+        wav.write(&[0.0, 1.0, -1.0]).unwrap();
+        wav.write(&[2.0]).unwrap();
+        assert_eq!(wav.samples(), 4);
+        let wav = wav.finish().unwrap().into_inner();
+        assert_eq!(wav.len(), 44 + 8);
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(u32_at(&wav, 4), 36 + 8);
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(u32_at(&wav, 16), 16);
+        // PCM, mono
+        assert_eq!(u16_at(&wav, 20), 1);
+        assert_eq!(u16_at(&wav, 22), 1);
+        assert_eq!(u32_at(&wav, 24), 44100);
+        assert_eq!(u32_at(&wav, 28), 88200);
+        assert_eq!(u16_at(&wav, 32), 2);
+        assert_eq!(u16_at(&wav, 34), 16);
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(u32_at(&wav, 40), 8);
+        let samples: Vec<_> = wav[44..]
+            .chunks(2)
+            .map(|s| i16::from_le_bytes([s[0], s[1]]))
+            .collect();
+        assert_eq!(samples, [0, 32767, -32767, 32767]);
+    }
+
+    #[test]
+    fn empty() {
+        let wav = WavWriter::new(Cursor::new(Vec::new()), 8000).unwrap();
+        let wav = wav.finish().unwrap().into_inner();
+        assert_eq!(wav.len(), 44);
+        assert_eq!(u32_at(&wav, 4), 36);
+        assert_eq!(u32_at(&wav, 40), 0);
+    }
+
+    #[test]
+    fn no_gaps() {
+        // a high rate, with the longest time slices a clock runs
+        let rate = 384_000;
+        let mut cpu = Cpu::new();
+        // LD V0, 0xFF; LD ST, V0; JP 0x204
+        cpu.load([0x60, 0xFF, 0xF0, 0x18, 0x12, 0x04]).unwrap();
+        let mut clock = Clock::new(500);
+        clock.set_audio(Some(Generator::new(rate)));
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), rate).unwrap();
+        for _ in 0..8 {
+            clock.advance(&mut cpu, MAX_ELAPSED).unwrap();
+            wav.write(&clock.audio_mut().unwrap().take_samples())
+                .unwrap();
+        }
+        assert_eq!(clock.audio().unwrap().dropped(), 0);
+        assert_eq!(wav.samples(), 2 * u64::from(rate));
+        let wav = wav.finish().unwrap().into_inner();
+        assert_eq!(u32_at(&wav, 40), 4 * rate);
+    }
+}
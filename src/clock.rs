@@ -0,0 +1,266 @@
+//! Fixed rate scheduling of instructions and timers.
+//!
+//! Hosts usually drive the emulation from their render loop, whose rate
+//! depends on the refresh rate of the monitor. The [`Clock`] decouples both:
+//! given the wall time elapsed since the previous call, it runs as many
+//! instructions as the configured rate requires, and updates the timers at
+//! exactly 60 Hz, interleaving both in the order they'd happen in real time.
+//!
+//! The rate is measured in cycles of the cpu's [`Timing`] model: instructions
+//! per second by default, or machine cycles per second (see [`VIP_HZ`]) with
+//! [`Timing::CosmacVip`], where each instruction takes as long as its cost.
+//! An idle cpu (halted, or waiting for input or for the display) sleeps until
+//! the next timer update.
+//!
+//! A clock can also drive an audio [`Generator`], rendering the sound of the
+//! emulated time it runs (see [`Clock::set_audio`]).
+//!
+//! [`Timing`]: crate::cpu::Timing
+//! [`Timing::CosmacVip`]: crate::cpu::Timing::CosmacVip
+//! [`VIP_HZ`]: crate::cpu::VIP_HZ
+use crate::{
+    audio::Generator,
+    cpu::{Cpu, CpuError},
+};
+use std::time::Duration;
+
+/// Rate of the delay and sound timers.
+pub const TIMER_HZ: u32 = 60;
+
+/// Longest wall time accounted for in a single call to [`Clock::advance`].
+/// Longer pauses (a window being dragged, a debugger breakpoint) are skipped
+/// instead of being caught up in a burst.
+pub const MAX_ELAPSED: Duration = Duration::from_millis(250);
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// Emulated time scheduler.
+///
+/// Time is tracked in integer units of `1 / (hz * 60)` seconds, so a cycle
+/// lasts exactly 60 units and a timer period `hz` units, and
+/// fractions of a cycle carry over between calls without drifting.
+#[derive(Debug, Clone)]
+pub struct Clock {
+    hz: u32,
+    remainder: u128,
+    now: u64,
+    next_instruction: u64,
+    next_timer: u64,
+    audio: Option<Generator>,
+}
+
+/// Work done by a call to [`Clock::advance`].
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct Elapsed {
+    /// Number of instructions run.
+    pub instructions: usize,
+    /// Number of cycles run, according to the cpu's timing model.
+    pub cycles: u64,
+    /// Number of timer updates (60 Hz frames).
+    pub frames: usize,
+}
+
+impl Clock {
+    /// Creates a clock running `hz` cycles per second.
+    pub fn new(hz: u32) -> Self {
+        let hz = hz.max(1);
+        Self {
+            hz,
+            remainder: 0,
+            now: 0,
+            next_instruction: 0,
+            next_timer: u64::from(hz),
+            audio: None,
+        }
+    }
+
+    /// Return the number of cycles per second.
+    pub fn hz(&self) -> u32 {
+        self.hz
+    }
+
+    /// Changes the number of cycles per second. Any fraction of a
+    /// cycle accumulated so far is dropped.
+    pub fn set_hz(&mut self, hz: u32) {
+        let audio = self.audio.take();
+        *self = Self::new(hz);
+        self.audio = audio;
+    }
+
+    /// Sets the generator rendering the sound of the emulated time, from now
+    /// on, or stops rendering sound.
+    pub fn set_audio(&mut self, audio: Option<Generator>) {
+        self.audio = audio;
+    }
+
+    /// Return the audio generator, if any.
+    pub fn audio(&self) -> Option<&Generator> {
+        self.audio.as_ref()
+    }
+
+    pub fn audio_mut(&mut self) -> Option<&mut Generator> {
+        self.audio.as_mut()
+    }
+
+    /// Removes the audio generator, to hand it over to another clock.
+    pub fn take_audio(&mut self) -> Option<Generator> {
+        self.audio.take()
+    }
+
+    /// Return the number of time units per second. Fast clocks overflow a
+    /// `u32`.
+    fn units_per_sec(&self) -> u64 {
+        u64::from(self.hz) * u64::from(TIMER_HZ)
+    }
+
+    /// Runs the instructions and timer updates that fit in `elapsed` wall
+    /// time (capped to [`MAX_ELAPSED`]).
+    ///
+    /// If an instruction fails the rest of the time slice is dropped and the
+    /// error is returned.
+    pub fn advance(&mut self, cpu: &mut Cpu, elapsed: Duration) -> Result<Elapsed, CpuError> {
+        let elapsed = elapsed.min(MAX_ELAPSED);
+        let scaled = elapsed.as_nanos() * u128::from(self.units_per_sec()) + self.remainder;
+        self.remainder = scaled % NANOS_PER_SEC;
+        let target = self.now + (scaled / NANOS_PER_SEC) as u64;
+        self.run_until(cpu, target)
+    }
+
+    /// Runs exactly one timer period (1/60 s) of emulated time, ending with
+    /// a timer update. Headless hosts use this to step the emulation frame by
+    /// frame, independently of wall time.
+    pub fn advance_frame(&mut self, cpu: &mut Cpu) -> Result<Elapsed, CpuError> {
+        let target = self.next_timer;
+        let mut done = self.run_until(cpu, target)?;
+        cpu.update_timers();
+        if let Some(audio) = &mut self.audio {
+            audio.update(cpu);
+        }
+        self.next_timer += u64::from(self.hz);
+        done.frames += 1;
+        Ok(done)
+    }
+
+    /// Runs the instructions and timer updates scheduled before `target`.
+    fn run_until(&mut self, cpu: &mut Cpu, target: u64) -> Result<Elapsed, CpuError> {
+        let mut done = Elapsed::default();
+        let result = loop {
+            let next = self.next_instruction.min(self.next_timer);
+            if next >= target {
+                break Ok(done);
+            }
+            self.render_audio(next);
+            self.now = next;
+            if self.next_timer <= self.next_instruction {
+                cpu.update_timers();
+                self.next_timer += u64::from(self.hz);
+                done.frames += 1;
+            } else {
+                match cpu.step_cycles() {
+                    // idle until the next timer update
+                    Ok(0) => self.next_instruction = self.next_timer,
+                    Ok(cycles) => {
+                        self.next_instruction += u64::from(cycles * TIMER_HZ);
+                        done.instructions += 1;
+                        done.cycles += u64::from(cycles);
+                    }
+                    Err(err) => break Err(err),
+                }
+            }
+            if let Some(audio) = &mut self.audio {
+                audio.update(cpu);
+            }
+        };
+
+        if result.is_err() {
+            self.next_instruction = self.next_instruction.max(target);
+            self.next_timer = self.next_timer.max(target);
+        }
+        self.render_audio(target);
+        self.now = target;
+        result
+    }
+
+    /// Renders the sound from now until `until`.
+    fn render_audio(&mut self, until: u64) {
+        let units = u128::from(self.units_per_sec());
+        if let Some(audio) = &mut self.audio {
+            // sample boundaries are computed from the start of the clock, so
+            // rounding errors don't accumulate
+            let rate = u128::from(audio.rate());
+            let sample = |time: u64| u128::from(time) * rate / units;
+            audio.render((sample(until) - sample(self.now)) as usize);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Clock, Elapsed, NANOS_PER_SEC};
+    use crate::cpu::Cpu;
+    use std::time::Duration;
+
+    /// Return a cpu stuck in a `JP 0x200` loop.
+    fn spin() -> Cpu {
+        let mut cpu = Cpu::new();
+        cpu.load([0x12, 0x00]).unwrap();
+        cpu
+    }
+
+    /// Runs one emulated second from a host calling `advance` `fps` times
+    /// per second, and returns the work done.
+    fn second(hz: u32, fps: u32) -> Elapsed {
+        let mut cpu = spin();
+        let mut clock = Clock::new(hz);
+        let mut total = Elapsed::default();
+        // time each frame like a host reading a monotonic clock, so the
+        // frames add up to exactly one second
+        let at = |frame: u32| u64::from(frame) * NANOS_PER_SEC as u64 / u64::from(fps);
+        for frame in 0..fps {
+            let elapsed = Duration::from_nanos(at(frame + 1) - at(frame));
+            let done = clock.advance(&mut cpu, elapsed).unwrap();
+            total.instructions += done.instructions;
+            total.cycles += done.cycles;
+            total.frames += done.frames;
+        }
+        total
+    }
+
+    #[test]
+    fn rates() {
+        for &hz in &[1, 500, 700, 1000] {
+            let done = second(hz, 60);
+            assert_eq!(done.instructions, hz as usize);
+            assert_eq!(done.cycles, u64::from(hz));
+            // the 60th update is due right at the end of the second, so it
+            // runs in the next call
+            assert_eq!(done.frames, 59);
+        }
+    }
+
+    #[test]
+    fn host_rate_independent() {
+        for &hz in &[500, 1000] {
+            assert_eq!(second(hz, 60), second(hz, 144));
+            assert_eq!(second(hz, 60), second(hz, 75));
+        }
+    }
+
+    #[test]
+    fn advance_frame() {
+        let mut cpu = spin();
+        let mut clock = Clock::new(600);
+        let done = clock.advance_frame(&mut cpu).unwrap();
+        assert_eq!(done.instructions, 10);
+        assert_eq!(done.frames, 1);
+    }
+
+    #[test]
+    fn fast_clock() {
+        // hz * 60 doesn't fit in a u32
+        let mut cpu = spin();
+        let mut clock = Clock::new(100_000_000);
+        let done = clock.advance(&mut cpu, Duration::from_millis(1)).unwrap();
+        assert_eq!(done.instructions, 100_000);
+    }
+}
@@ -1,15 +1,32 @@
+pub use breakpoint::{Access, Breakpoint, Breakpoints, Cmp, Stop, Trigger};
+pub use display::{Display, MAX_HEIGHT, MAX_WIDTH};
+pub use error::CpuError;
 pub use opcode::Opcode;
-use std::{io, mem};
+pub use quirks::{LoadStore, Quirks};
+pub use rng::{Rng, XorShift};
+pub use savestate::StateError;
+use std::{convert::TryFrom, mem, str::FromStr};
+pub use timing::{Timing, VIP_HZ};
 
+mod breakpoint;
+mod display;
+mod error;
 mod interpreter;
 mod opcode;
+mod quirks;
+mod rng;
+pub mod savestate;
+mod timing;
 
-const DISPLAY_SIZE: usize = 64 * 32;
+/// Address where programs are loaded.
+const ROM_ADDR: usize = 0x200;
 const STACK_SIZE: usize = 16;
 const MEMORY_SIZE: usize = 4096;
+const XO_MEMORY_SIZE: usize = 65536;
 
 #[repr(u8)]
 #[derive(Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PixelState {
     Off = 0x0,
     On = 0xFF,
@@ -36,21 +53,67 @@ impl From<u8> for PixelState {
 
 #[repr(u8)]
 #[derive(Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum KeyState {
     Up = 0,
     Down = 1,
 }
 
+/// Instruction set supported by the cpu.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Mode {
+    /// Original CHIP-8 instructions.
+    #[default]
+    Chip8,
+    /// CHIP-8 plus the SUPER-CHIP 1.1 extensions: high resolution mode,
+    /// scrolling, 16x16 sprites, big font and RPL user flags.
+    SuperChip,
+    /// SUPER-CHIP plus the XO-CHIP extensions: 64 KiB of memory, two display
+    /// planes, audio pattern buffer and a few extra instructions.
+    XoChip,
+}
+
+impl Mode {
+    /// Return the size of the address space.
+    pub fn memory_size(&self) -> usize {
+        match self {
+            Mode::Chip8 | Mode::SuperChip => MEMORY_SIZE,
+            Mode::XoChip => XO_MEMORY_SIZE,
+        }
+    }
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    /// Parses `chip8`, `schip` or `xochip`.
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "chip8" => Ok(Mode::Chip8),
+            "schip" => Ok(Mode::SuperChip),
+            "xochip" => Ok(Mode::XoChip),
+            _ => Err(format!("unknown mode = {}", mode)),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CpuState {
     /// Not running
     Halt,
     /// Running
     Running,
     /// Waiting for input
-    WaitInput(usize),
+    WaitInput(u8),
+    /// Stopped by an error
+    Fault(CpuError),
+    /// Stopped by a breakpoint, until [`Cpu::resume`] is called
+    Break(Trigger),
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Cpu {
     registers: [u8; 16],
     i: u16,
@@ -59,11 +122,27 @@ pub struct Cpu {
     dt: usize,
     st: usize,
     stack: [u16; 16],
-    memory: [u8; MEMORY_SIZE],
-    display: [PixelState; DISPLAY_SIZE],
+    memory: Vec<u8>,
+    display: Display,
+    rpl: [u8; 16],
+    pattern: [u8; 16],
+    pitch: u8,
     keypad: [KeyState; 16],
     keypad_event: [KeyState; 16],
     state: CpuState,
+    mode: Mode,
+    quirks: Quirks,
+    timing: Timing,
+    cycles: u64,
+    vblank_wait: bool,
+    #[cfg_attr(feature = "serde", serde(with = "rng::serde_seed"))]
+    rng: Box<dyn Rng>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    breakpoints: Breakpoints,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    resumed: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    watch_hit: Option<Trigger>,
 }
 
 impl Default for Cpu {
@@ -76,11 +155,23 @@ impl Default for Cpu {
             dt: 0,
             st: 0,
             stack: [0; STACK_SIZE],
-            memory: [0; MEMORY_SIZE],
-            display: [PixelState::Off; DISPLAY_SIZE],
+            memory: vec![0; MEMORY_SIZE],
+            display: Display::default(),
+            rpl: [0; 16],
+            pattern: [0; 16],
+            pitch: 64,
             keypad: [KeyState::Up; 16],
             keypad_event: [KeyState::Up; 16],
             state: CpuState::Halt,
+            mode: Mode::default(),
+            quirks: Quirks::default(),
+            timing: Timing::default(),
+            cycles: 0,
+            vblank_wait: false,
+            rng: Box::new(XorShift::default()),
+            breakpoints: Breakpoints::default(),
+            resumed: false,
+            watch_hit: None,
         }
     }
 }
@@ -91,6 +182,69 @@ impl Cpu {
         Self::default()
     }
 
+    /// Creates a CHIP8 cpu whose `RND` instruction draws from a [`XorShift`]
+    /// generator with the given seed.
+    pub fn with_seed(seed: u64) -> Self {
+        let mut cpu = Self::default();
+        cpu.set_rng(Box::new(XorShift::new(seed)));
+        cpu
+    }
+
+    /// Creates a CHIP8 cpu that interprets ambiguous instructions according to
+    /// the given quirks.
+    pub fn with_quirks(quirks: Quirks) -> Self {
+        Self::with_mode(Mode::Chip8, quirks)
+    }
+
+    /// Creates a cpu supporting the given instruction set, and interpreting
+    /// ambiguous instructions according to the given quirks.
+    pub fn with_mode(mode: Mode, quirks: Quirks) -> Self {
+        Self {
+            mode,
+            quirks,
+            memory: vec![0; mode.memory_size()],
+            ..Self::default()
+        }
+    }
+
+    /// Replaces the random number source used by `RND`.
+    pub fn set_rng(&mut self, rng: Box<dyn Rng>) {
+        self.rng = rng;
+    }
+
+    /// Return the current seed of the random number source. Reseeding it
+    /// with this value reproduces the upcoming `RND` results.
+    pub fn seed(&self) -> u64 {
+        self.rng.seed()
+    }
+
+    /// Changes the timing model used to account for the cost of each
+    /// instruction.
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing = timing;
+    }
+
+    /// Return the timing model.
+    pub fn timing(&self) -> Timing {
+        self.timing
+    }
+
+    /// Return the number of cycles run since the program was loaded,
+    /// according to the timing model.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Return the supported instruction set.
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Return the quirks used to interpret ambiguous instructions.
+    pub fn quirks(&self) -> &Quirks {
+        &self.quirks
+    }
+
     pub fn halt(&mut self) {
         self.state = CpuState::Halt
     }
@@ -99,6 +253,26 @@ impl Cpu {
         &self.state
     }
 
+    /// Return the breakpoints.
+    pub fn breakpoints(&self) -> &Breakpoints {
+        &self.breakpoints
+    }
+
+    /// Return the breakpoints, to add, remove or toggle them.
+    pub fn breakpoints_mut(&mut self) -> &mut Breakpoints {
+        &mut self.breakpoints
+    }
+
+    /// Continues after a breakpoint. The breakpoints checked before an
+    /// instruction runs are ignored for the next one, so the cpu doesn't stop
+    /// again at the same place.
+    pub fn resume(&mut self) {
+        if let CpuState::Break(_) = self.state {
+            self.state = CpuState::Running;
+            self.resumed = true;
+        }
+    }
+
     pub fn registers(&self) -> &[u8; 16] {
         &self.registers
     }
@@ -132,34 +306,92 @@ impl Cpu {
         self.i
     }
 
-    /// Initialize the cpu
-    pub fn load<R: AsRef<[u8]>>(&mut self, rom: R) {
+    /// Initialize the cpu, with the ROM loaded at `0x200`.
+    ///
+    /// Fails without touching the cpu if the ROM doesn't fit in memory.
+    pub fn load<R: AsRef<[u8]>>(&mut self, rom: R) -> Result<(), CpuError> {
+        let rom = rom.as_ref();
+        let capacity = self.memory.len() - ROM_ADDR;
+        if rom.len() > capacity {
+            return Err(CpuError::RomTooLarge {
+                size: rom.len(),
+                capacity,
+            });
+        }
         self.reset();
         self.load_interpreter();
-        dump(rom.as_ref(), &mut self.memory[0x200..]);
-        self.pc = 0x200;
+        self.memory[ROM_ADDR..ROM_ADDR + rom.len()].copy_from_slice(rom);
+        self.pc = ROM_ADDR;
         self.state = CpuState::Running;
+        Ok(())
     }
 
     fn load_interpreter(&mut self) {
-        dump(interpreter::FONT, &mut self.memory[..]);
+        self.memory[..interpreter::FONT.len()].copy_from_slice(interpreter::FONT);
+        let big_font =
+            interpreter::BIG_FONT_ADDR..interpreter::BIG_FONT_ADDR + interpreter::BIG_FONT.len();
+        self.memory[big_font].copy_from_slice(interpreter::BIG_FONT);
     }
 
-    /// Resets the cpu.
+    /// Resets the cpu. The mode, quirks, timing model, random number source,
+    /// breakpoints and RPL user flags are preserved.
     pub fn reset(&mut self) {
-        mem::replace(self, Default::default());
+        let rpl = self.rpl;
+        let rng = mem::replace(&mut self.rng, Box::new(XorShift::default()));
+        let breakpoints = mem::take(&mut self.breakpoints);
+        *self = Self {
+            timing: self.timing,
+            ..Self::with_mode(self.mode, self.quirks)
+        };
+        self.rpl = rpl;
+        self.rng = rng;
+        self.breakpoints = breakpoints;
     }
 
-    /// Return memory
-    pub fn memory(&self) -> &[u8; 4096] {
+    /// Captures the whole state of the cpu in the versioned binary format
+    /// described in the [`savestate`] module.
+    pub fn save_state(&self) -> Vec<u8> {
+        savestate::save(self)
+    }
+
+    /// Restores a state captured with [`Cpu::save_state`]. On error, the cpu
+    /// is left untouched.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        savestate::load(self, state)
+    }
+
+    /// Return memory. Its size depends on the [`Mode`].
+    pub fn memory(&self) -> &[u8] {
         &self.memory
     }
 
-    /// Returns the current state of the display.
-    pub fn display(&self) -> &[PixelState; DISPLAY_SIZE] {
+    /// Return the XO-CHIP audio pattern buffer: 128 1-bit samples, most
+    /// significant bit first.
+    pub fn audio_pattern(&self) -> &[u8; 16] {
+        &self.pattern
+    }
+
+    /// Return the XO-CHIP pitch register.
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    /// Return the playback rate of the audio pattern buffer, in samples per
+    /// second, as set by the pitch register.
+    pub fn pattern_rate(&self) -> f64 {
+        4000.0 * 2f64.powf((f64::from(self.pitch) - 64.0) / 48.0)
+    }
+
+    /// Returns the current state of the display, including its resolution.
+    pub fn display(&self) -> &Display {
         &self.display
     }
 
+    /// Return the state of every key.
+    pub fn keypad(&self) -> &[KeyState; 16] {
+        &self.keypad
+    }
+
     /// Set key state.
     pub fn set_key(&mut self, key: usize, state: KeyState) {
         self.keypad[key] = state;
@@ -168,28 +400,110 @@ impl Cpu {
         }
     }
 
-    /// If the cpu is not halted or waiting for use input, decodes the next
-    /// instruction, runs it, and updates the timer registers.
-    pub fn step(&mut self) {
+    /// If the cpu is not halted, faulted, stopped at a breakpoint or waiting
+    /// for use input (or for the display, see [`Quirks::display_wait`]),
+    /// decodes the next instruction and runs it.
+    ///
+    /// If the instruction fails, the cpu moves into the [`CpuState::Fault`]
+    /// state and the error is returned. Stepping a faulted cpu does nothing.
+    ///
+    /// If a breakpoint fires, the cpu moves into the [`CpuState::Break`]
+    /// state, either before running the instruction or, for watchpoints,
+    /// right after it.
+    pub fn step(&mut self) -> Result<(), CpuError> {
+        self.step_cycles().map(|_| ())
+    }
+
+    /// Same as [`Cpu::step`], but returns the cost of the instruction
+    /// according to the timing model. Returns zero if no instruction ran
+    /// because the cpu is idle.
+    pub fn step_cycles(&mut self) -> Result<u32, CpuError> {
         let key_down = self.any_key_down();
-        match (&self.state, key_down) {
+        let result = match (&self.state, key_down) {
             (CpuState::WaitInput(x), Some(key)) => {
-                self.registers[*x] = key as _;
+                self.registers[usize::from(*x)] = key as _;
                 self.state = CpuState::Running;
+                Ok(0)
             }
-            (CpuState::Halt, _) | (CpuState::WaitInput(_), _) => {}
-            (CpuState::Running, _) => self.fetch_execute(),
-        }
+            (CpuState::Halt, _)
+            | (CpuState::WaitInput(_), _)
+            | (CpuState::Fault(_), _)
+            | (CpuState::Break(_), _) => Ok(0),
+            (CpuState::Running, _) if self.vblank_wait => Ok(0),
+            (CpuState::Running, _) => match self.check_breakpoints() {
+                Some(trigger) => {
+                    self.state = CpuState::Break(trigger);
+                    Ok(0)
+                }
+                None => {
+                    let result = self.fetch_execute_cycles();
+                    if let Some(trigger) = self.watch_hit.take() {
+                        if result.is_ok() {
+                            self.state = CpuState::Break(trigger);
+                        }
+                    }
+                    result
+                }
+            },
+        };
         // clear dynamic key events
-        mem::replace(&mut self.keypad_event, [KeyState::Up; 16]);
+        self.keypad_event = [KeyState::Up; 16];
+        result
+    }
+
+    fn check_breakpoints(&mut self) -> Option<Trigger> {
+        if mem::replace(&mut self.resumed, false) || self.breakpoints.is_empty() {
+            return None;
+        }
+        let opcode = self.fetch().ok();
+        self.breakpoints
+            .check(self.pc, &self.registers, opcode.as_ref())
+    }
+
+    /// Runs instructions until `condition` holds, a breakpoint fires or the
+    /// cpu becomes idle, for at most `max_steps` instructions. The condition
+    /// is checked before every instruction and after the last one.
+    ///
+    /// Timers aren't updated, so a cpu waiting for the display (see
+    /// [`Quirks::display_wait`]) is considered idle. A cpu already stopped at
+    /// a breakpoint stops right away, see [`Cpu::resume`].
+    pub fn run_until<F>(&mut self, mut condition: F, max_steps: usize) -> Result<Stop, CpuError>
+    where
+        F: FnMut(&Cpu) -> bool,
+    {
+        for _ in 0..max_steps {
+            if condition(self) {
+                return Ok(Stop::Condition);
+            }
+            let cycles = self.step_cycles()?;
+            match self.state {
+                CpuState::Break(trigger) => return Ok(Stop::Breakpoint(trigger)),
+                CpuState::Running if cycles > 0 || !self.vblank_wait => {}
+                _ => return Ok(Stop::Idle),
+            }
+        }
+        if condition(self) {
+            Ok(Stop::Condition)
+        } else {
+            Ok(Stop::MaxSteps)
+        }
     }
 
     /// Decodes the instruction located at the current position of the program
     /// counter without running it.
-    pub fn fetch(&self) -> Opcode {
-        let hi = self.memory[self.pc] as u16;
-        let lo = self.memory[self.pc + 1] as u16;
-        Opcode::from(hi << 8 | lo)
+    pub fn fetch(&self) -> Result<Opcode, CpuError> {
+        let opcode = self.fetch_word()?;
+        Opcode::try_from(opcode).map_err(|opcode| CpuError::InvalidOpcode {
+            pc: self.pc,
+            opcode,
+        })
+    }
+
+    fn fetch_word(&self) -> Result<u16, CpuError> {
+        match self.memory.get(self.pc..self.pc + 2) {
+            Some(&[hi, lo]) => Ok(u16::from(hi) << 8 | u16::from(lo)),
+            _ => Err(CpuError::PcOutOfBounds { pc: self.pc }),
+        }
     }
 
     fn any_key_down(&self) -> Option<usize> {
@@ -202,14 +516,59 @@ impl Cpu {
         })
     }
 
-    /// Decodes the next instruction and runs it.
-    pub fn fetch_execute(&mut self) {
-        let instruction = self.fetch();
-        self.execute(instruction);
+    /// Decodes the next instruction and runs it. On error, the cpu moves into
+    /// the [`CpuState::Fault`] state.
+    pub fn fetch_execute(&mut self) -> Result<(), CpuError> {
+        self.fetch_execute_cycles().map(|_| ())
+    }
+
+    fn fetch_execute_cycles(&mut self) -> Result<u32, CpuError> {
+        let pc = self.pc;
+        self.watch_hit = None;
+        let result = self.fetch().and_then(|instruction| {
+            let x = match instruction {
+                Opcode::DRW_Vx_Vy_nibble(x, _, _) => self.registers[usize::from(x)],
+                _ => 0,
+            };
+            self.execute(&instruction)?;
+            let skipped = self.pc == pc + 4 && !matches!(instruction, Opcode::LD_I_long);
+            Ok(self.timing.cycles(&instruction, x, skipped))
+        });
+        match result {
+            Ok(cycles) => self.cycles += u64::from(cycles),
+            Err(err) => {
+                // leave the program counter pointing at the faulting instruction
+                self.pc = err.pc();
+                self.state = CpuState::Fault(err);
+            }
+        }
+        result
+    }
+
+    /// Runs instructions until they add up to at least `budget` cycles, and
+    /// returns the number of cycles used. The last instruction may go over
+    /// budget, so hosts running frames back to back should deduct the excess
+    /// from the next budget.
+    ///
+    /// When the cpu becomes idle (halted, waiting for input or for the
+    /// display) the rest of the budget is considered spent.
+    pub fn run_cycles(&mut self, budget: u64) -> Result<u64, CpuError> {
+        let mut used = 0;
+        while used < budget {
+            match self.step_cycles()? {
+                0 => return Ok(budget),
+                cycles => used += u64::from(cycles),
+            }
+        }
+        Ok(used)
     }
 
     /// Decrement sound timer (ST) and delay timer (DT) registers.
+    ///
+    /// Timers are updated at the vertical blank, which also releases a cpu
+    /// waiting for the display.
     pub fn update_timers(&mut self) {
+        self.vblank_wait = false;
         if self.dt > 0 {
             self.dt -= 1;
         }
@@ -218,153 +577,616 @@ impl Cpu {
         }
     }
 
-    fn execute(&mut self, instruction: Opcode) {
-        match instruction {
-            Opcode::SYS_addr(_addr) => unimplemented!(),
-            Opcode::CLS => self.clear_display(),
+    fn execute(&mut self, instruction: &Opcode) -> Result<(), CpuError> {
+        let pc = self.pc;
+        // advance program counter
+        self.pc += 2;
+
+        let supported = match self.mode {
+            Mode::Chip8 => !instruction.is_super_chip() && !instruction.is_xo_chip(),
+            Mode::SuperChip => !instruction.is_xo_chip(),
+            Mode::XoChip => true,
+        };
+        if !supported {
+            return Err(self.fault_invalid(pc));
+        }
+
+        match *instruction {
+            Opcode::SYS_addr(_addr) => return Err(self.fault_invalid(pc)),
+            Opcode::SCD_nibble(n) => self.display.scroll_down(n as usize),
+            Opcode::SCU_nibble(n) => self.display.scroll_up(n as usize),
+            Opcode::CLS => self.display.clear(),
             Opcode::RET => {
-                self.pc = self.stack[self.sp] as usize;
+                if self.sp == 0 {
+                    return Err(CpuError::StackUnderflow {
+                        pc,
+                        opcode: self.word_at(pc),
+                    });
+                }
                 self.sp -= 1;
+                self.pc = self.stack[self.sp] as usize;
             }
-            Opcode::JP_addr(addr) => self.pc = addr as usize - 2,
+            Opcode::SCR => self.display.scroll_right(4),
+            Opcode::SCL => self.display.scroll_left(4),
+            Opcode::EXIT => self.state = CpuState::Halt,
+            Opcode::LOW => self.display.set_hires(false),
+            Opcode::HIGH => self.display.set_hires(true),
+            Opcode::JP_addr(addr) => self.pc = addr,
             Opcode::CALL_addr(addr) => {
+                if self.sp == STACK_SIZE {
+                    return Err(CpuError::StackOverflow {
+                        pc,
+                        opcode: self.word_at(pc),
+                    });
+                }
+                self.stack[self.sp] = self.pc as u16;
                 self.sp += 1;
-                self.stack[self.sp as usize] = self.pc as u16;
-                self.pc = addr - 2;
+                self.pc = addr;
             }
             Opcode::SE_Vx_byte(x, b) => {
-                if self.registers[x] == b {
-                    self.pc += 2;
+                if self.registers[usize::from(x)] == b {
+                    self.skip();
                 }
             }
             Opcode::SNE_Vx_byte(x, b) => {
-                if self.registers[x] != b {
-                    self.pc += 2;
+                if self.registers[usize::from(x)] != b {
+                    self.skip();
                 }
             }
             Opcode::SE_Vx_Vy(x, y) => {
-                if self.registers[x] == self.registers[y] {
-                    self.pc += 2;
+                if self.registers[usize::from(x)] == self.registers[usize::from(y)] {
+                    self.skip();
                 }
             }
-            Opcode::LD_Vx_byte(x, b) => self.registers[x] = b,
+            Opcode::SAVE_Vx_Vy(x, y) => {
+                let range = register_range(x, y);
+                let registers = self.registers;
+                let len = range.len();
+                let memory = self.memory_mut(pc, self.i as usize, len)?;
+                for (addr, &r) in range.iter().enumerate() {
+                    memory[addr] = registers[r];
+                }
+            }
+            Opcode::LOAD_Vx_Vy(x, y) => {
+                let range = register_range(x, y);
+                let mut registers = self.registers;
+                let memory = self.memory_ref(pc, self.i as usize, range.len())?;
+                for (addr, &r) in range.iter().enumerate() {
+                    registers[r] = memory[addr];
+                }
+                self.registers = registers;
+            }
+            Opcode::LD_Vx_byte(x, b) => self.registers[usize::from(x)] = b,
             Opcode::ADD_Vx_byte(x, b) => {
-                let tmp = self.registers[x] as u64;
-                self.registers[x] = ((tmp + b as u64) & 0xFF) as u8;
-                //self.registers[x] += b
-            }
-            Opcode::LD_Vx_Vy(x, y) => self.registers[x] = self.registers[y],
-            Opcode::OR_Vx_Vy(x, y) => self.registers[x] |= self.registers[y],
-            Opcode::AND_Vx_Vy(x, y) => self.registers[x] &= self.registers[y],
-            Opcode::XOR_Vx_Vy(x, y) => self.registers[x] ^= self.registers[y],
+                self.registers[usize::from(x)] = self.registers[usize::from(x)].wrapping_add(b)
+            }
+            Opcode::LD_Vx_Vy(x, y) => {
+                self.registers[usize::from(x)] = self.registers[usize::from(y)]
+            }
+            Opcode::OR_Vx_Vy(x, y) => {
+                self.registers[usize::from(x)] |= self.registers[usize::from(y)];
+                self.vf_reset();
+            }
+            Opcode::AND_Vx_Vy(x, y) => {
+                self.registers[usize::from(x)] &= self.registers[usize::from(y)];
+                self.vf_reset();
+            }
+            Opcode::XOR_Vx_Vy(x, y) => {
+                self.registers[usize::from(x)] ^= self.registers[usize::from(y)];
+                self.vf_reset();
+            }
             Opcode::ADD_Vx_Vy(x, y) => {
-                let sum = self.registers[x] as u16 + self.registers[y] as u16;
+                let sum =
+                    self.registers[usize::from(x)] as u16 + self.registers[usize::from(y)] as u16;
                 if sum > 0xFF {
                     self.registers[0xF] = 1;
                 }
-                self.registers[x] = (sum & 0xFF) as u8;
+                self.registers[usize::from(x)] = (sum & 0xFF) as u8;
             }
             Opcode::SUB_Vx_Vy(x, y) => {
-                if self.registers[x] > self.registers[y] {
+                if self.registers[usize::from(x)] > self.registers[usize::from(y)] {
                     self.registers[0xF] = 1;
-                    self.registers[x] -= self.registers[y];
+                    self.registers[usize::from(x)] -= self.registers[usize::from(y)];
                 } else {
                     self.registers[0xF] = 0;
                 }
             }
-            Opcode::SHR_Vx_Vy(x, _y) => {
-                self.registers[0xF] = self.registers[x] & 1;
-                self.registers[x] >>= 1;
+            Opcode::SHR_Vx_Vy(x, y) => {
+                let src = if self.quirks.shift_uses_vy { y } else { x };
+                let value = self.registers[usize::from(src)];
+                self.registers[usize::from(x)] = value >> 1;
+                self.registers[0xF] = value & 1;
             }
             Opcode::SUBN_Vx_Vy(x, y) => {
-                if self.registers[y] > self.registers[x] {
+                if self.registers[usize::from(y)] > self.registers[usize::from(x)] {
                     self.registers[0xF] = 1;
-                    self.registers[y] -= self.registers[x];
+                    self.registers[usize::from(y)] -= self.registers[usize::from(x)];
                 } else {
                     self.registers[0xF] = 0;
                 }
             }
-            Opcode::SHL_Vx_Vy(x, _y) => {
-                self.registers[0xF] = self.registers[x] >> 7;
-                self.registers[x] <<= 1;
+            Opcode::SHL_Vx_Vy(x, y) => {
+                let src = if self.quirks.shift_uses_vy { y } else { x };
+                let value = self.registers[usize::from(src)];
+                self.registers[usize::from(x)] = value << 1;
+                self.registers[0xF] = value >> 7;
             }
             Opcode::SNE_Vx_Vy(x, y) => {
-                if self.registers[x] != self.registers[y] {
-                    self.pc += 2;
+                if self.registers[usize::from(x)] != self.registers[usize::from(y)] {
+                    self.skip();
                 }
             }
             Opcode::LD_I_addr(addr) => self.i = addr as u16,
-            Opcode::JP_V0_addr(addr) => self.pc = addr + self.registers[0x0] as usize - 2,
-            Opcode::RND_Vx_byte(x, b) => self.registers[x] = rand_u8() & b,
-            Opcode::DRW_Vx_Vy_nibble(x, y, nibble) => self.drw_x_y_nibble(x, y, nibble),
+            Opcode::LD_I_long => {
+                self.i = self
+                    .memory_ref(pc, self.pc, 2)
+                    .map(|w| u16::from(w[0]) << 8 | u16::from(w[1]))?;
+                self.pc += 2;
+            }
+            Opcode::JP_V0_addr(addr) => {
+                let x = if self.quirks.jump_uses_vx {
+                    (addr >> 8) & 0xF
+                } else {
+                    0x0
+                };
+                self.pc = addr + self.registers[x] as usize;
+            }
+            Opcode::RND_Vx_byte(x, b) => self.registers[usize::from(x)] = self.rng.next_u8() & b,
+            Opcode::DRW_Vx_Vy_nibble(x, y, nibble) => self.drw_x_y_nibble(pc, x, y, nibble)?,
             Opcode::SKP_Vx(x) => {
-                if self.keypad[self.registers[x] as usize] == KeyState::Down {
-                    self.pc += 2;
+                if self.keypad[self.registers[usize::from(x)] as usize & 0xF] == KeyState::Down {
+                    self.skip();
                 }
             }
             Opcode::SKNP_Vx(x) => {
-                if self.keypad[self.registers[x] as usize] == KeyState::Up {
-                    self.pc += 2;
+                if self.keypad[self.registers[usize::from(x)] as usize & 0xF] == KeyState::Up {
+                    self.skip();
                 }
             }
-            Opcode::LD_Vx_DT(x) => self.registers[x] = self.dt as _,
+            Opcode::LD_Vx_DT(x) => self.registers[usize::from(x)] = self.dt as _,
             Opcode::LD_Vx_K(x) => self.state = CpuState::WaitInput(x),
-            Opcode::LD_DT_Vx(x) => self.dt = self.registers[x] as _,
-            Opcode::LD_ST_Vx(x) => self.st = self.registers[x] as _,
-            Opcode::ADD_I_Vx(x) => self.i += self.registers[x] as u16,
-            Opcode::LD_F_Vx(x) => self.i = interpreter::sprite_addr(self.registers[x]),
+            Opcode::PLANE_n(n) => self.display.select_planes(n),
+            Opcode::AUDIO => {
+                let mut pattern = [0; 16];
+                pattern.copy_from_slice(self.memory_ref(pc, self.i as usize, 16)?);
+                self.pattern = pattern;
+            }
+            Opcode::PITCH_Vx(x) => self.pitch = self.registers[usize::from(x)],
+            Opcode::LD_DT_Vx(x) => self.dt = self.registers[usize::from(x)] as _,
+            Opcode::LD_ST_Vx(x) => self.st = self.registers[usize::from(x)] as _,
+            Opcode::ADD_I_Vx(x) => {
+                self.i = self.i.wrapping_add(self.registers[usize::from(x)] as u16)
+            }
+            Opcode::LD_F_Vx(x) => self.i = interpreter::sprite_addr(self.registers[usize::from(x)]),
+            Opcode::LD_HF_Vx(x) => {
+                self.i = interpreter::big_sprite_addr(self.registers[usize::from(x)])
+            }
             Opcode::LD_B_Vx(x) => {
-                let digit = self.registers[x] as u32;
-                self.memory[self.i as usize + 0] = (digit % 1000) as u8;
-                self.memory[self.i as usize + 1] = (digit % 100) as u8;
-                self.memory[self.i as usize + 2] = (digit % 10) as u8;
+                let value = self.registers[usize::from(x)];
+                let bcd = [value / 100, value / 10 % 10, value % 10];
+                self.memory_mut(pc, self.i as usize, 3)?
+                    .copy_from_slice(&bcd);
             }
             Opcode::LD_I_Vx(x) => {
-                let total = x as usize + 1;
-                let offset = self.i as usize;
-                dump(&self.registers[..total], &mut self.memory[offset..]);
+                let total = usize::from(x) + 1;
+                let registers = self.registers;
+                self.memory_mut(pc, self.i as usize, total)?
+                    .copy_from_slice(&registers[..total]);
+                self.load_store_increment(total);
             }
             Opcode::LD_Vx_I(x) => {
-                let start = self.i as usize;
-                let total = x as usize + 1;
-                dump(
-                    &self.memory[start..start + total],
-                    &mut self.registers[..total],
-                );
+                let total = usize::from(x) + 1;
+                let mut registers = self.registers;
+                registers[..total].copy_from_slice(self.memory_ref(pc, self.i as usize, total)?);
+                self.registers = registers;
+                self.load_store_increment(total);
             }
+            Opcode::LD_R_Vx(x) => {
+                let x = usize::from(x);
+                self.rpl[..=x].copy_from_slice(&self.registers[..=x]);
+            }
+            Opcode::LD_Vx_R(x) => {
+                let x = usize::from(x);
+                self.registers[..=x].copy_from_slice(&self.rpl[..=x]);
+            }
+        }
+        Ok(())
+    }
+
+    /// Skips the next instruction. In XO-CHIP mode the 4-byte `LD I, long`
+    /// instruction is skipped as a whole.
+    fn skip(&mut self) {
+        let long = self.mode == Mode::XoChip
+            && self.memory.get(self.pc..self.pc + 2) == Some(&[0xF0, 0x00]);
+        self.pc += if long { 4 } else { 2 };
+    }
+
+    fn vf_reset(&mut self) {
+        if self.quirks.vf_reset {
+            self.registers[0xF] = 0;
         }
+    }
 
-        // advance program counter
-        self.pc += 2;
+    fn load_store_increment(&mut self, total: usize) {
+        let total = match self.quirks.load_store {
+            LoadStore::Unchanged => return,
+            LoadStore::IncrementX => total - 1,
+            LoadStore::IncrementX1 => total,
+        };
+        self.i = self.i.wrapping_add(total as u16);
     }
 
-    fn clear_display(&mut self) {
-        mem::replace(&mut self.display, [PixelState::Off; 64 * 32]);
+    /// Returns the raw instruction at the given address.
+    fn word_at(&self, addr: usize) -> u16 {
+        u16::from(self.memory[addr]) << 8 | u16::from(self.memory[addr + 1])
     }
 
-    fn drw_x_y_nibble(&mut self, x: usize, y: usize, nibble: u8) {
-        let x = self.registers[x] as usize;
-        let y = self.registers[y] as usize;
-        let rows = nibble as usize;
-        self.registers[0xF] = 0;
-        for row in 0..rows {
-            let byte = self.memory[self.i as usize + row];
-            for bit in 0..8 {
-                if byte & (0x80 >> bit as u8) != 0 {
-                    let row = (y + row) % 32;
-                    let col = (x + bit) % 64;
-                    self.display[64 * row + col].toggle();
-                    self.registers[0xF] = 1;
-                }
-            }
+    fn fault_invalid(&self, pc: usize) -> CpuError {
+        CpuError::InvalidOpcode {
+            pc,
+            opcode: self.word_at(pc),
+        }
+    }
+
+    fn fault_memory(&self, pc: usize, addr: usize) -> CpuError {
+        CpuError::MemoryOutOfBounds {
+            pc,
+            opcode: self.word_at(pc),
+            addr,
+        }
+    }
+
+    /// Returns `len` bytes of memory starting at `addr`, or a fault raised by
+    /// the instruction at `pc` if the range doesn't fit in memory.
+    fn memory_ref(&mut self, pc: usize, addr: usize, len: usize) -> Result<&[u8], CpuError> {
+        self.watch(pc, addr, len, false);
+        match self.memory.get(addr..addr + len) {
+            Some(bytes) => Ok(bytes),
+            None => Err(self.fault_memory(pc, addr + len - 1)),
+        }
+    }
+
+    fn memory_mut(&mut self, pc: usize, addr: usize, len: usize) -> Result<&mut [u8], CpuError> {
+        if addr + len > self.memory.len() {
+            return Err(self.fault_memory(pc, addr + len - 1));
+        }
+        self.watch(pc, addr, len, true);
+        Ok(&mut self.memory[addr..addr + len])
+    }
+
+    /// Records the first watchpoint hit by an access of the instruction at
+    /// `pc`.
+    fn watch(&mut self, pc: usize, addr: usize, len: usize, write: bool) {
+        if self.watch_hit.is_none() {
+            self.watch_hit = self.breakpoints.check_access(pc, addr, len, write);
         }
     }
+
+    fn drw_x_y_nibble(&mut self, pc: usize, x: u8, y: u8, nibble: u8) -> Result<(), CpuError> {
+        let x = self.registers[usize::from(x)] as usize;
+        let y = self.registers[usize::from(y)] as usize;
+        // Dxy0 draws a 16x16 sprite in SUPER-CHIP mode
+        let wide = nibble == 0 && self.mode != Mode::Chip8;
+        let planes = self.display.selected_planes().count_ones() as usize;
+        let len = if wide { 32 } else { nibble as usize } * planes;
+        let mut sprite = [0; 64];
+        sprite[..len].copy_from_slice(self.memory_ref(pc, self.i as usize, len)?);
+        let collision = self
+            .display
+            .draw(x, y, &sprite[..len], wide, self.quirks.clip_sprites);
+        self.registers[0xF] = collision as u8;
+        self.vblank_wait = self.quirks.display_wait || self.timing == Timing::CosmacVip;
+        Ok(())
+    }
 }
 
-fn dump(src: &[u8], dst: &mut [u8]) {
-    io::copy(&mut io::Cursor::new(src), &mut io::Cursor::new(dst)).expect("Out of bounds");
+/// Registers accessed by `5xy2` and `5xy3`, in the order they map to memory.
+/// The range is reversed if `x > y`.
+fn register_range(x: u8, y: u8) -> Vec<usize> {
+    let (x, y) = (usize::from(x), usize::from(y));
+    if x <= y {
+        (x..=y).collect()
+    } else {
+        (y..=x).rev().collect()
+    }
 }
 
-fn rand_u8() -> u8 {
-    rand::random()
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Return a cpu with the given instructions loaded.
+    fn cpu_with(mode: Mode, quirks: Quirks, program: &[u16]) -> Cpu {
+        let rom: Vec<u8> = program
+            .iter()
+            .flat_map(|op| op.to_be_bytes().to_vec())
+            .collect();
+        let mut cpu = Cpu::with_mode(mode, quirks);
+        cpu.load(&rom).unwrap();
+        cpu
+    }
+
+    fn chip8(program: &[u16]) -> Cpu {
+        cpu_with(Mode::Chip8, Quirks::default(), program)
+    }
+
+    fn steps(cpu: &mut Cpu, count: usize) -> Result<(), CpuError> {
+        (0..count).try_for_each(|_| cpu.step())
+    }
+
+    /// Runs the program with the quirk off and on, and returns both cpus.
+    fn quirk<F>(set: F, program: &[u16], count: usize) -> (Cpu, Cpu)
+    where
+        F: Fn(&mut Quirks),
+    {
+        let mut quirks = Quirks::default();
+        let mut off = cpu_with(Mode::Chip8, quirks, program);
+        set(&mut quirks);
+        let mut on = cpu_with(Mode::Chip8, quirks, program);
+        steps(&mut off, count).unwrap();
+        steps(&mut on, count).unwrap();
+        (off, on)
+    }
+
+    fn lit(cpu: &Cpu, x: usize, y: usize) -> bool {
+        let display = cpu.display();
+        display.pixels()[y * display.width() + x] == PixelState::On
+    }
+
+    #[test]
+    fn invalid_opcode() {
+        let mut cpu = chip8(&[0x0123]);
+        let err = CpuError::InvalidOpcode {
+            pc: 0x200,
+            opcode: 0x0123,
+        };
+        assert_eq!(cpu.step(), Err(err));
+        assert_eq!(*cpu.state(), CpuState::Fault(err));
+        // a faulted cpu stays put
+        assert_eq!(cpu.step(), Ok(()));
+        assert_eq!(cpu.program_counter(), 0x200);
+    }
+
+    #[test]
+    fn stack_overflow() {
+        let mut cpu = chip8(&[0x2200]);
+        assert_eq!(steps(&mut cpu, STACK_SIZE), Ok(()));
+        assert_eq!(
+            cpu.step(),
+            Err(CpuError::StackOverflow {
+                pc: 0x200,
+                opcode: 0x2200
+            })
+        );
+    }
+
+    #[test]
+    fn stack_underflow() {
+        let mut cpu = chip8(&[0x00EE]);
+        assert_eq!(
+            cpu.step(),
+            Err(CpuError::StackUnderflow {
+                pc: 0x200,
+                opcode: 0x00EE
+            })
+        );
+    }
+
+    #[test]
+    fn memory_out_of_bounds() {
+        // LD I, 0xFFF; LD [I], V1
+        let mut cpu = chip8(&[0xAFFF, 0xF155]);
+        assert_eq!(
+            steps(&mut cpu, 2),
+            Err(CpuError::MemoryOutOfBounds {
+                pc: 0x202,
+                opcode: 0xF155,
+                addr: 0x1000
+            })
+        );
+    }
+
+    #[test]
+    fn pc_out_of_bounds() {
+        let mut cpu = chip8(&[0x1FFF]);
+        assert_eq!(
+            steps(&mut cpu, 2),
+            Err(CpuError::PcOutOfBounds { pc: 0xFFF })
+        );
+    }
+
+    #[test]
+    fn rom_too_large() {
+        let mut cpu = Cpu::new();
+        let capacity = MEMORY_SIZE - ROM_ADDR;
+        assert_eq!(cpu.load(vec![0; capacity]), Ok(()));
+        assert_eq!(
+            cpu.load(vec![0; capacity + 1]),
+            Err(CpuError::RomTooLarge {
+                size: capacity + 1,
+                capacity
+            })
+        );
+        let mut cpu = Cpu::with_mode(Mode::XoChip, Quirks::default());
+        assert_eq!(cpu.load(vec![0; capacity + 1]), Ok(()));
+    }
+
+    #[test]
+    fn bcd() {
+        // LD V0, 254; LD I, 0x300; LD B, V0
+        let mut cpu = chip8(&[0x60FE, 0xA300, 0xF033]);
+        steps(&mut cpu, 3).unwrap();
+        assert_eq!(cpu.memory()[0x300..0x303], [2, 5, 4]);
+    }
+
+    #[test]
+    fn same_seed_same_rnd() {
+        /// Return the first values drawn by `RND V0, 0xFF` with the given seed.
+        fn draws(seed: u64) -> Vec<u8> {
+            let rom = [0xC0, 0xFF, 0x12, 0x00];
+            let mut cpu = Cpu::with_seed(seed);
+            cpu.load(rom).unwrap();
+            (0..64)
+                .map(|_| {
+                    steps(&mut cpu, 2).unwrap();
+                    cpu.registers()[0]
+                })
+                .collect()
+        }
+        assert_eq!(draws(1234), draws(1234));
+        assert_ne!(draws(1234), draws(4321));
+    }
+
+    #[test]
+    fn quirk_shift_uses_vy() {
+        // LD V1, 3; SHR V0, V1
+        let (off, on) = quirk(|q| q.shift_uses_vy = true, &[0x6103, 0x8016], 2);
+        assert_eq!(off.registers()[0], 0);
+        assert_eq!(on.registers()[0], 1);
+    }
+
+    #[test]
+    fn quirk_load_store() {
+        // LD I, 0x300; LD [I], V1
+        let program = [0xA300, 0xF155];
+        let (off, on) = quirk(|q| q.load_store = LoadStore::IncrementX1, &program, 2);
+        assert_eq!(off.i(), 0x300);
+        assert_eq!(on.i(), 0x302);
+        let (_, at_last) = quirk(|q| q.load_store = LoadStore::IncrementX, &program, 2);
+        assert_eq!(at_last.i(), 0x301);
+    }
+
+    #[test]
+    fn quirk_jump_uses_vx() {
+        // LD V0, 0x10; LD V1, 5; JP V0, 0x100 (or JP V1, 0x100)
+        let (off, on) = quirk(|q| q.jump_uses_vx = true, &[0x6010, 0x6105, 0xB100], 3);
+        assert_eq!(off.program_counter(), 0x110);
+        assert_eq!(on.program_counter(), 0x105);
+    }
+
+    #[test]
+    fn quirk_clip_sprites() {
+        // LD V0, 62; LD I, 0x000; DRW V0, V1, 1 (the top row of "0" is 4
+        // pixels wide)
+        let (off, on) = quirk(|q| q.clip_sprites = true, &[0x603E, 0xA000, 0xD011], 3);
+        assert!(lit(&off, 63, 0) && lit(&off, 0, 0));
+        assert!(lit(&on, 63, 0) && !lit(&on, 0, 0));
+    }
+
+    #[test]
+    fn quirk_vf_reset() {
+        // LD VF, 5; OR V0, V1
+        let (off, on) = quirk(|q| q.vf_reset = true, &[0x6F05, 0x8011], 2);
+        assert_eq!(off.registers()[0xF], 5);
+        assert_eq!(on.registers()[0xF], 0);
+    }
+
+    #[test]
+    fn quirk_display_wait() {
+        // DRW V0, V0, 1; LD V0, 1
+        let (off, mut on) = quirk(|q| q.display_wait = true, &[0xD001, 0x6001], 2);
+        assert_eq!(off.program_counter(), 0x204);
+        assert_eq!(on.program_counter(), 0x202);
+        on.update_timers();
+        on.step().unwrap();
+        assert_eq!(on.program_counter(), 0x204);
+    }
+
+    fn schip(program: &[u16]) -> Cpu {
+        cpu_with(Mode::SuperChip, Quirks::SUPER_CHIP, program)
+    }
+
+    /// Return the coordinates of the lit pixels.
+    fn lit_pixels(cpu: &Cpu) -> Vec<(usize, usize)> {
+        let width = cpu.display().width();
+        (0..width * cpu.display().height())
+            .filter(|i| cpu.display().pixels()[*i] == PixelState::On)
+            .map(|i| (i % width, i / width))
+            .collect()
+    }
+
+    #[test]
+    fn schip_resolution() {
+        // HIGH; LOW
+        let mut cpu = schip(&[0x00FF, 0x00FE]);
+        cpu.step().unwrap();
+        assert_eq!((cpu.display().width(), cpu.display().height()), (128, 64));
+        cpu.step().unwrap();
+        assert_eq!((cpu.display().width(), cpu.display().height()), (64, 32));
+    }
+
+    #[test]
+    fn schip_scroll() {
+        let mut cpu = schip(&[
+            0x00FF, // HIGH
+            0xA20E, // LD I, 0x20E
+            0xD011, // DRW V0, V1, 1
+            0x00C2, // SCD 2
+            0x00FB, // SCR
+            0x00FC, // SCL
+            0x120C, // JP 0x20C
+            0x8000, // sprite
+        ]);
+        steps(&mut cpu, 3).unwrap();
+        assert_eq!(lit_pixels(&cpu), [(0, 0)]);
+        cpu.step().unwrap();
+        assert_eq!(lit_pixels(&cpu), [(0, 2)]);
+        cpu.step().unwrap();
+        assert_eq!(lit_pixels(&cpu), [(4, 2)]);
+        cpu.step().unwrap();
+        assert_eq!(lit_pixels(&cpu), [(0, 2)]);
+    }
+
+    #[test]
+    fn schip_big_sprite() {
+        // HIGH; LD I, 0x300; DRW V0, V1, 0; DRW V0, V1, 0
+        let mut cpu = schip(&[0x00FF, 0xA300, 0xD010, 0xD010]);
+        cpu.memory[0x300..0x320].copy_from_slice(&[0xFF; 32]);
+        steps(&mut cpu, 3).unwrap();
+        let square: Vec<_> = (0..16).flat_map(|y| (0..16).map(move |x| (x, y))).collect();
+        assert_eq!(lit_pixels(&cpu), square);
+        assert_eq!(cpu.registers()[0xF], 0);
+        cpu.step().unwrap();
+        assert!(lit_pixels(&cpu).is_empty());
+        assert_eq!(cpu.registers()[0xF], 1);
+    }
+
+    #[test]
+    fn schip_big_font() {
+        // LD V0, 9; LD HF, V0
+        let mut cpu = schip(&[0x6009, 0xF030]);
+        steps(&mut cpu, 2).unwrap();
+        assert_eq!(usize::from(cpu.i()), interpreter::BIG_FONT_ADDR + 90);
+    }
+
+    #[test]
+    fn schip_rpl_flags() {
+        // LD V0, 1; LD V1, 2; LD R, V1; LD V0, 0; LD V1, 0; LD V1, R
+        let mut cpu = schip(&[0x6001, 0x6102, 0xF175, 0x6000, 0x6100, 0xF185]);
+        steps(&mut cpu, 6).unwrap();
+        assert_eq!(cpu.registers()[..2], [1, 2]);
+    }
+
+    #[test]
+    fn schip_exit() {
+        let mut cpu = schip(&[0x00FD]);
+        cpu.step().unwrap();
+        assert_eq!(*cpu.state(), CpuState::Halt);
+    }
+
+    #[test]
+    fn schip_opcodes_need_schip_mode() {
+        for &op in &[
+            0x00C1, 0x00FB, 0x00FC, 0x00FD, 0x00FE, 0x00FF, 0xF030, 0xF075,
+        ] {
+            let mut cpu = chip8(&[op]);
+            assert_eq!(
+                cpu.step(),
+                Err(CpuError::InvalidOpcode {
+                    pc: 0x200,
+                    opcode: op
+                })
+            );
+        }
+    }
 }
@@ -0,0 +1,365 @@
+use super::Opcode;
+use std::{fmt, mem, ops::Range};
+
+/// Condition that stops execution.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Breakpoint {
+    /// The program counter reaches the address.
+    Pc(usize),
+    /// A register compares to a value, checked before every instruction.
+    Register { register: u8, cmp: Cmp, value: u8 },
+    /// An instruction accesses memory in the range. Execution stops after
+    /// the accessing instruction.
+    Watch { range: Range<usize>, access: Access },
+    /// An instruction of the same kind is about to run. Operands are ignored,
+    /// so `Opcode(Opcode::DRW_Vx_Vy_nibble(0, 0, 0))` stops on every `DRW`.
+    Opcode(Opcode),
+}
+
+/// Comparison of a register breakpoint.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Cmp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// Kind of memory access of a watchpoint.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+/// Breakpoint that stopped execution.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Trigger {
+    /// Id of the breakpoint, as returned by [`Breakpoints::add`].
+    pub id: usize,
+    /// Address of the instruction that fired it.
+    pub pc: usize,
+    /// Accessed address, for watchpoints.
+    pub addr: Option<usize>,
+}
+
+/// Result of [`Cpu::run_until`](super::Cpu::run_until).
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Stop {
+    /// The condition holds.
+    Condition,
+    /// A breakpoint fired.
+    Breakpoint(Trigger),
+    /// The cpu is idle (halted, or waiting for input or for the display).
+    Idle,
+    /// The maximum number of steps was reached.
+    MaxSteps,
+}
+
+impl Cmp {
+    /// Return the result of comparing `lhs` against `rhs`.
+    pub fn eval(&self, lhs: u8, rhs: u8) -> bool {
+        match self {
+            Cmp::Eq => lhs == rhs,
+            Cmp::Ne => lhs != rhs,
+            Cmp::Lt => lhs < rhs,
+            Cmp::Le => lhs <= rhs,
+            Cmp::Gt => lhs > rhs,
+            Cmp::Ge => lhs >= rhs,
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Breakpoint::Pc(addr) => write!(f, "PC == {:03X}", addr),
+            Breakpoint::Register {
+                register,
+                cmp,
+                value,
+            } => write!(f, "V{:X} {} {:02X}", register, cmp, value),
+            Breakpoint::Watch { range, access } => {
+                let access = match access {
+                    Access::Read => "read",
+                    Access::Write => "write",
+                    Access::ReadWrite => "access",
+                };
+                write!(f, "{} {:03X}..{:03X}", access, range.start, range.end)
+            }
+            Breakpoint::Opcode(kind) => {
+                // only the name of the variant, operands are ignored
+                let name = format!("{:?}", kind);
+                write!(f, "opcode {}", name.split('(').next().unwrap_or_default())
+            }
+        }
+    }
+}
+
+impl fmt::Display for Cmp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Cmp::Eq => "==",
+            Cmp::Ne => "!=",
+            Cmp::Lt => "<",
+            Cmp::Le => "<=",
+            Cmp::Gt => ">",
+            Cmp::Ge => ">=",
+        })
+    }
+}
+
+/// Breakpoint and its id, as listed by [`Breakpoints::iter`].
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub id: usize,
+    pub breakpoint: Breakpoint,
+    pub enabled: bool,
+}
+
+/// Set of breakpoints of a cpu.
+#[derive(Debug, Default, Clone)]
+pub struct Breakpoints {
+    entries: Vec<Entry>,
+    next_id: usize,
+}
+
+impl Breakpoints {
+    /// Adds an enabled breakpoint and returns its id.
+    pub fn add(&mut self, breakpoint: Breakpoint) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.entries.push(Entry {
+            id,
+            breakpoint,
+            enabled: true,
+        });
+        id
+    }
+
+    /// Removes a breakpoint. Returns false if there was none with that id.
+    pub fn remove(&mut self, id: usize) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|e| e.id != id);
+        self.entries.len() != len
+    }
+
+    /// Enables or disables a breakpoint.
+    pub fn set_enabled(&mut self, id: usize, enabled: bool) {
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.id == id) {
+            entry.enabled = enabled;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.iter()
+    }
+
+    fn enabled(&self) -> impl Iterator<Item = &Entry> {
+        self.entries.iter().filter(|e| e.enabled)
+    }
+
+    /// Checks the breakpoints evaluated before running an instruction.
+    pub(crate) fn check(
+        &self,
+        pc: usize,
+        registers: &[u8; 16],
+        opcode: Option<&Opcode>,
+    ) -> Option<Trigger> {
+        let trigger = |id| Trigger { id, pc, addr: None };
+        self.enabled()
+            .find(|e| match &e.breakpoint {
+                Breakpoint::Pc(addr) => *addr == pc,
+                Breakpoint::Register {
+                    register,
+                    cmp,
+                    value,
+                } => cmp.eval(registers[usize::from(*register & 0xF)], *value),
+                Breakpoint::Opcode(kind) => matches!(
+                    opcode,
+                    Some(op) if mem::discriminant(op) == mem::discriminant(kind)
+                ),
+                Breakpoint::Watch { .. } => false,
+            })
+            .map(|e| trigger(e.id))
+    }
+
+    /// Checks the watchpoints against a memory access of `len` bytes.
+    pub(crate) fn check_access(
+        &self,
+        pc: usize,
+        addr: usize,
+        len: usize,
+        write: bool,
+    ) -> Option<Trigger> {
+        self.enabled().find_map(|e| match &e.breakpoint {
+            Breakpoint::Watch { range, access } => {
+                let kind = match access {
+                    Access::Read => !write,
+                    Access::Write => write,
+                    Access::ReadWrite => true,
+                };
+                let start = addr.max(range.start);
+                if kind && start < (addr + len).min(range.end) {
+                    Some(Trigger {
+                        id: e.id,
+                        pc,
+                        addr: Some(start),
+                    })
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Access, Breakpoint, Cmp, Trigger};
+    use crate::cpu::{Cpu, CpuState, Opcode};
+
+    fn cpu(rom: &[u8], breakpoint: Breakpoint) -> (Cpu, usize) {
+        let mut cpu = Cpu::new();
+        cpu.load(rom).unwrap();
+        let id = cpu.breakpoints_mut().add(breakpoint);
+        (cpu, id)
+    }
+
+    /// Steps until the cpu stops, and returns what stopped it.
+    fn run(cpu: &mut Cpu) -> Trigger {
+        for _ in 0..32 {
+            cpu.step().unwrap();
+            if let CpuState::Break(trigger) = cpu.state() {
+                return *trigger;
+            }
+        }
+        panic!("no breakpoint fired");
+    }
+
+    #[test]
+    fn pc() {
+        // LD V0, 1; LD V0, 2; JP 0x200
+        let rom = [0x60, 0x01, 0x60, 0x02, 0x12, 0x00];
+        let (mut cpu, id) = cpu(&rom, Breakpoint::Pc(0x202));
+        let trigger = Trigger {
+            id,
+            pc: 0x202,
+            addr: None,
+        };
+        assert_eq!(run(&mut cpu), trigger);
+        // stops before the instruction
+        assert_eq!(cpu.program_counter(), 0x202);
+        assert_eq!(cpu.registers()[0], 1);
+        // resuming runs it, then stops again on the next lap
+        cpu.resume();
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers()[0], 2);
+        assert_eq!(run(&mut cpu), trigger);
+    }
+
+    #[test]
+    fn watch_read() {
+        // LD I, 0x300; LD [I], V1; LD V1, [I]
+        let rom = [0xA3, 0x00, 0xF1, 0x55, 0xF1, 0x65];
+        let watch = Breakpoint::Watch {
+            range: 0x301..0x308,
+            access: Access::Read,
+        };
+        let (mut cpu, id) = cpu(&rom, watch);
+        let trigger = run(&mut cpu);
+        assert_eq!(trigger.id, id);
+        assert_eq!(trigger.pc, 0x204);
+        assert_eq!(trigger.addr, Some(0x301));
+        // stops after the instruction
+        assert_eq!(cpu.program_counter(), 0x206);
+    }
+
+    #[test]
+    fn watch_write() {
+        // LD I, 0x300; LD V1, [I]; LD [I], V1
+        let rom = [0xA3, 0x00, 0xF1, 0x65, 0xF1, 0x55];
+        let watch = Breakpoint::Watch {
+            range: 0x2F0..0x301,
+            access: Access::Write,
+        };
+        let (mut cpu, id) = cpu(&rom, watch);
+        let trigger = run(&mut cpu);
+        assert_eq!(trigger.id, id);
+        assert_eq!(trigger.pc, 0x204);
+        assert_eq!(trigger.addr, Some(0x300));
+    }
+
+    #[test]
+    fn watch_fault() {
+        // LD I, 0xFFF; LD [I], V1
+        let rom = [0xAF, 0xFF, 0xF1, 0x55];
+        let watch = Breakpoint::Watch {
+            range: 0xFFF..0x1000,
+            access: Access::ReadWrite,
+        };
+        let (mut cpu, _) = cpu(&rom, watch);
+        cpu.step().unwrap();
+        assert!(cpu.step().is_err());
+        assert!(matches!(cpu.state(), CpuState::Fault(_)));
+    }
+
+    #[test]
+    fn register() {
+        // ADD V3, 1; JP 0x200
+        let rom = [0x73, 0x01, 0x12, 0x00];
+        let breakpoint = Breakpoint::Register {
+            register: 3,
+            cmp: Cmp::Ge,
+            value: 5,
+        };
+        let (mut cpu, id) = cpu(&rom, breakpoint);
+        assert_eq!(run(&mut cpu).id, id);
+        assert_eq!(cpu.registers()[3], 5);
+    }
+
+    #[test]
+    fn opcode() {
+        // LD V0, 1; ADD V0, 1; DRW V0, V0, 3
+        let rom = [0x60, 0x01, 0x70, 0x01, 0xD0, 0x03];
+        let (mut cpu, id) = cpu(&rom, Breakpoint::Opcode(Opcode::DRW_Vx_Vy_nibble(0, 0, 0)));
+        let trigger = run(&mut cpu);
+        assert_eq!(trigger.id, id);
+        assert_eq!(trigger.pc, 0x204);
+    }
+
+    #[test]
+    fn disabled() {
+        // LD V0, 1; JP 0x200
+        let rom = [0x60, 0x01, 0x12, 0x00];
+        let (mut cpu, id) = cpu(&rom, Breakpoint::Pc(0x202));
+        cpu.breakpoints_mut().set_enabled(id, false);
+        for _ in 0..8 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(*cpu.state(), CpuState::Running);
+    }
+
+    #[test]
+    fn cmp() {
+        assert!(Cmp::Eq.eval(1, 1) && !Cmp::Eq.eval(1, 2));
+        assert!(Cmp::Ne.eval(1, 2) && !Cmp::Ne.eval(1, 1));
+        assert!(Cmp::Lt.eval(1, 2) && !Cmp::Lt.eval(2, 2));
+        assert!(Cmp::Le.eval(2, 2) && !Cmp::Le.eval(3, 2));
+        assert!(Cmp::Gt.eval(3, 2) && !Cmp::Gt.eval(2, 2));
+        assert!(Cmp::Ge.eval(2, 2) && !Cmp::Ge.eval(1, 2));
+    }
+}
@@ -0,0 +1,224 @@
+use super::PixelState;
+
+/// Width of the display in high resolution mode.
+pub const MAX_WIDTH: usize = 128;
+/// Height of the display in high resolution mode.
+pub const MAX_HEIGHT: usize = 64;
+/// Number of bitplanes (XO-CHIP).
+pub const PLANES: usize = 2;
+
+/// Display, either in the original 64x32 resolution or in the 128x64
+/// SUPER-CHIP high resolution mode.
+///
+/// The display is made of two bitplanes. CHIP-8 and SUPER-CHIP programs only
+/// ever draw to the first one, while XO-CHIP programs can select any
+/// combination of them, giving 4 colours per pixel.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Display {
+    pub(crate) planes: [Vec<PixelState>; PLANES],
+    pub(crate) hires: bool,
+    pub(crate) selected: u8,
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Self {
+            planes: [
+                vec![PixelState::Off; MAX_WIDTH * MAX_HEIGHT],
+                vec![PixelState::Off; MAX_WIDTH * MAX_HEIGHT],
+            ],
+            hires: false,
+            selected: 0b01,
+        }
+    }
+}
+
+impl Display {
+    /// Return the width of the current resolution.
+    pub fn width(&self) -> usize {
+        if self.hires {
+            MAX_WIDTH
+        } else {
+            MAX_WIDTH / 2
+        }
+    }
+
+    /// Return the height of the current resolution.
+    pub fn height(&self) -> usize {
+        if self.hires {
+            MAX_HEIGHT
+        } else {
+            MAX_HEIGHT / 2
+        }
+    }
+
+    /// Return true in high resolution mode.
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    /// Returns the pixels of the first plane in the current resolution, in
+    /// row-major order.
+    pub fn pixels(&self) -> &[PixelState] {
+        self.plane(0)
+    }
+
+    /// Returns the pixels of the given plane in the current resolution, in
+    /// row-major order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `plane` is not lower than [`PLANES`].
+    pub fn plane(&self, plane: usize) -> &[PixelState] {
+        &self.planes[plane][..self.width() * self.height()]
+    }
+
+    /// Returns the colour index of every pixel in the current resolution, in
+    /// row-major order. Bit `n` of the index is set if the pixel is on in
+    /// plane `n`.
+    pub fn colors(&self) -> impl Iterator<Item = u8> + '_ {
+        self.plane(0)
+            .iter()
+            .zip(self.plane(1))
+            .map(|(p0, p1)| (*p0 == PixelState::On) as u8 | ((*p1 == PixelState::On) as u8) << 1)
+    }
+
+    /// Returns a pointer to the first pixel of the first plane.
+    pub fn as_ptr(&self) -> *const PixelState {
+        self.planes[0].as_ptr()
+    }
+
+    /// Return the bitmask of planes affected by drawing, clearing and
+    /// scrolling.
+    pub fn selected_planes(&self) -> u8 {
+        self.selected
+    }
+
+    pub(crate) fn select_planes(&mut self, mask: u8) {
+        self.selected = mask & 0b11;
+    }
+
+    /// Switches the resolution. All planes are cleared.
+    pub(crate) fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        for plane in self.planes.iter_mut() {
+            clear(plane);
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        for plane in self.selected_mut() {
+            clear(plane);
+        }
+    }
+
+    /// XORs a sprite into the selected planes with its top-left corner at
+    /// `(x, y)`. Sprites are 8 pixels wide (one byte per row), or 16 pixels
+    /// wide (two bytes per row) if `wide` is set. When several planes are
+    /// selected, `sprite` holds the data of each plane one after the other.
+    ///
+    /// Returns true if any pixel was turned off.
+    pub(crate) fn draw(
+        &mut self,
+        x: usize,
+        y: usize,
+        sprite: &[u8],
+        wide: bool,
+        clip: bool,
+    ) -> bool {
+        let (width, height) = (self.width(), self.height());
+        let (x, y) = (x % width, y % height);
+        let planes = self.selected.count_ones() as usize;
+        if planes == 0 || sprite.is_empty() {
+            return false;
+        }
+        let mut collision = false;
+        let chunk = sprite.len() / planes;
+        for (plane, sprite) in self.selected_mut().zip(sprite.chunks(chunk)) {
+            let (cols, rows): (usize, Vec<u16>) = if wide {
+                let rows = sprite
+                    .chunks(2)
+                    .map(|row| u16::from(row[0]) << 8 | u16::from(row[1]))
+                    .collect();
+                (16, rows)
+            } else {
+                (8, sprite.iter().map(|&b| u16::from(b) << 8).collect())
+            };
+            for (row, bits) in rows.into_iter().enumerate() {
+                for col in 0..cols {
+                    if bits & (0x8000 >> col) == 0 {
+                        continue;
+                    }
+                    let (row, col) = (y + row, x + col);
+                    if clip && (row >= height || col >= width) {
+                        continue;
+                    }
+                    let pixel = &mut plane[width * (row % height) + col % width];
+                    collision |= *pixel == PixelState::On;
+                    pixel.toggle();
+                }
+            }
+        }
+        collision
+    }
+
+    /// Scrolls the selected planes down by `n` rows.
+    pub(crate) fn scroll_down(&mut self, n: usize) {
+        let (width, height) = (self.width(), self.height());
+        let n = n.min(height);
+        for plane in self.selected_mut() {
+            plane.copy_within(..width * (height - n), width * n);
+            clear(&mut plane[..width * n]);
+        }
+    }
+
+    /// Scrolls the selected planes up by `n` rows.
+    pub(crate) fn scroll_up(&mut self, n: usize) {
+        let (width, height) = (self.width(), self.height());
+        let n = n.min(height);
+        for plane in self.selected_mut() {
+            plane.copy_within(width * n..width * height, 0);
+            clear(&mut plane[width * (height - n)..width * height]);
+        }
+    }
+
+    /// Scrolls the selected planes right by `n` columns.
+    pub(crate) fn scroll_right(&mut self, n: usize) {
+        let (width, height) = (self.width(), self.height());
+        let n = n.min(width);
+        for plane in self.selected_mut() {
+            for row in plane[..width * height].chunks_mut(width) {
+                row.copy_within(..width - n, n);
+                clear(&mut row[..n]);
+            }
+        }
+    }
+
+    /// Scrolls the selected planes left by `n` columns.
+    pub(crate) fn scroll_left(&mut self, n: usize) {
+        let (width, height) = (self.width(), self.height());
+        let n = n.min(width);
+        for plane in self.selected_mut() {
+            for row in plane[..width * height].chunks_mut(width) {
+                row.copy_within(n.., 0);
+                clear(&mut row[width - n..]);
+            }
+        }
+    }
+
+    fn selected_mut(&mut self) -> impl Iterator<Item = &mut Vec<PixelState>> {
+        let selected = self.selected;
+        self.planes
+            .iter_mut()
+            .enumerate()
+            .filter(move |(i, _)| selected & (1 << i) != 0)
+            .map(|(_, plane)| plane)
+    }
+}
+
+fn clear(pixels: &mut [PixelState]) {
+    for pixel in pixels {
+        *pixel = PixelState::Off;
+    }
+}
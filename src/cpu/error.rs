@@ -0,0 +1,82 @@
+use std::{error::Error, fmt};
+
+/// Errors raised while loading or executing a program.
+///
+/// When one of these is returned from [`Cpu::step`](super::Cpu::step), the cpu
+/// is left in the [`CpuState::Fault`](super::CpuState::Fault) state until it is
+/// reset or a new program is loaded.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CpuError {
+    /// The instruction doesn't decode into a known opcode. `SYS addr` also
+    /// raises this error, since machine code routines can't be emulated.
+    InvalidOpcode { pc: usize, opcode: u16 },
+    /// `CALL addr` with every stack entry already in use.
+    StackOverflow { pc: usize, opcode: u16 },
+    /// `RET` with an empty stack.
+    StackUnderflow { pc: usize, opcode: u16 },
+    /// The instruction tried to access memory past the end of the address
+    /// space.
+    MemoryOutOfBounds { pc: usize, opcode: u16, addr: usize },
+    /// The program counter points outside of memory, so no instruction could
+    /// be fetched.
+    PcOutOfBounds { pc: usize },
+    /// [`Cpu::load`](super::Cpu::load) was given a ROM of `size` bytes, but
+    /// only `capacity` fit in memory.
+    RomTooLarge { size: usize, capacity: usize },
+}
+
+impl CpuError {
+    /// Return the address of the faulting instruction, or the address the
+    /// ROM is loaded at for [`CpuError::RomTooLarge`].
+    pub fn pc(&self) -> usize {
+        match *self {
+            CpuError::InvalidOpcode { pc, .. }
+            | CpuError::StackOverflow { pc, .. }
+            | CpuError::StackUnderflow { pc, .. }
+            | CpuError::MemoryOutOfBounds { pc, .. }
+            | CpuError::PcOutOfBounds { pc } => pc,
+            CpuError::RomTooLarge { .. } => super::ROM_ADDR,
+        }
+    }
+
+    /// Return the raw faulting instruction, if it could be fetched.
+    pub fn opcode(&self) -> Option<u16> {
+        match *self {
+            CpuError::InvalidOpcode { opcode, .. }
+            | CpuError::StackOverflow { opcode, .. }
+            | CpuError::StackUnderflow { opcode, .. }
+            | CpuError::MemoryOutOfBounds { opcode, .. } => Some(opcode),
+            CpuError::PcOutOfBounds { .. } | CpuError::RomTooLarge { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CpuError::InvalidOpcode { pc, opcode } => {
+                write!(f, "invalid opcode {:04X} at {:03X}", opcode, pc)
+            }
+            CpuError::StackOverflow { pc, opcode } => {
+                write!(f, "stack overflow ({:04X} at {:03X})", opcode, pc)
+            }
+            CpuError::StackUnderflow { pc, opcode } => {
+                write!(f, "stack underflow ({:04X} at {:03X})", opcode, pc)
+            }
+            CpuError::MemoryOutOfBounds { pc, opcode, addr } => write!(
+                f,
+                "memory access out of bounds at {:X} ({:04X} at {:03X})",
+                addr, opcode, pc
+            ),
+            CpuError::PcOutOfBounds { pc } => write!(f, "program counter out of bounds = {:X}", pc),
+            CpuError::RomTooLarge { size, capacity } => write!(
+                f,
+                "ROM is {} bytes, but only {} fit in memory",
+                size, capacity
+            ),
+        }
+    }
+}
+
+impl Error for CpuError {}
@@ -1,86 +1,334 @@
-#[derive(Debug)]
+use std::{convert::TryFrom, fmt};
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 #[allow(non_camel_case_types)]
 pub enum Opcode {
     // 0nnn - SYS addr
     SYS_addr(usize),
+    // 00Cn - SCD nibble (SUPER-CHIP)
+    SCD_nibble(u8),
+    // 00Dn - SCU nibble (XO-CHIP)
+    SCU_nibble(u8),
     // 00E0 - CLS
     CLS,
     // 00EE - RET
     RET,
+    // 00FB - SCR (SUPER-CHIP)
+    SCR,
+    // 00FC - SCL (SUPER-CHIP)
+    SCL,
+    // 00FD - EXIT (SUPER-CHIP)
+    EXIT,
+    // 00FE - LOW (SUPER-CHIP)
+    LOW,
+    // 00FF - HIGH (SUPER-CHIP)
+    HIGH,
     // 1nnn - JP addr
     JP_addr(usize),
     // 2nnn - CALL addr
     CALL_addr(usize),
     // 3xkk - SE Vx, byte
-    SE_Vx_byte(usize, u8),
+    SE_Vx_byte(u8, u8),
     // 4xkk - SNE Vx, byte
-    SNE_Vx_byte(usize, u8),
+    SNE_Vx_byte(u8, u8),
     // 5xy0 - SE Vx, Vy
-    SE_Vx_Vy(usize, usize),
+    SE_Vx_Vy(u8, u8),
+    // 5xy2 - SAVE Vx, Vy (XO-CHIP)
+    SAVE_Vx_Vy(u8, u8),
+    // 5xy3 - LOAD Vx, Vy (XO-CHIP)
+    LOAD_Vx_Vy(u8, u8),
     // 6xkk - LD Vx, byte
-    LD_Vx_byte(usize, u8),
+    LD_Vx_byte(u8, u8),
     // 7xkk - ADD Vx, byte
-    ADD_Vx_byte(usize, u8),
+    ADD_Vx_byte(u8, u8),
     // 8xy0 - LD Vx, Vy
-    LD_Vx_Vy(usize, usize),
+    LD_Vx_Vy(u8, u8),
     // 8xy1 - OR Vx, Vy
-    OR_Vx_Vy(usize, usize),
+    OR_Vx_Vy(u8, u8),
     // 8xy2 - AND Vx, Vy
-    AND_Vx_Vy(usize, usize),
+    AND_Vx_Vy(u8, u8),
     // 8xy3 - XOR Vx, Vy
-    XOR_Vx_Vy(usize, usize),
+    XOR_Vx_Vy(u8, u8),
     // 8xy4 - ADD Vx, Vy
-    ADD_Vx_Vy(usize, usize),
+    ADD_Vx_Vy(u8, u8),
     // 8xy5 - SUB Vx, Vy
-    SUB_Vx_Vy(usize, usize),
+    SUB_Vx_Vy(u8, u8),
     // 8xy6 - SHR Vx {, Vy}
-    SHR_Vx_Vy(usize, usize),
+    SHR_Vx_Vy(u8, u8),
     // 8xy7 - SUBN Vx, Vy
-    SUBN_Vx_Vy(usize, usize),
+    SUBN_Vx_Vy(u8, u8),
     // 8xyE - SHL Vx {, Vy}
-    SHL_Vx_Vy(usize, usize),
+    SHL_Vx_Vy(u8, u8),
     // 9xy0 - SNE Vx, Vy
-    SNE_Vx_Vy(usize, usize),
+    SNE_Vx_Vy(u8, u8),
     // Annn - LD I, addr
     LD_I_addr(usize),
     // Bnnn - JP V0, addr
     JP_V0_addr(usize),
     // Cxkk - RND Vx, byte
-    RND_Vx_byte(usize, u8),
-    // Dxyn - DRW Vx, Vy, nibble
-    DRW_Vx_Vy_nibble(usize, usize, u8),
+    RND_Vx_byte(u8, u8),
+    // Dxyn - DRW Vx, Vy, nibble (Dxy0 draws a 16x16 sprite in SUPER-CHIP)
+    DRW_Vx_Vy_nibble(u8, u8, u8),
     // Ex9E - SKP Vx
-    SKP_Vx(usize),
+    SKP_Vx(u8),
     // ExA1 - SKNP Vx
-    SKNP_Vx(usize),
+    SKNP_Vx(u8),
+    // F000 nnnn - LD I, long (XO-CHIP). The address is the next word.
+    LD_I_long,
+    // Fn01 - PLANE n (XO-CHIP)
+    PLANE_n(u8),
+    // F002 - AUDIO (XO-CHIP)
+    AUDIO,
     // Fx07 - LD Vx, DT
-    LD_Vx_DT(usize),
+    LD_Vx_DT(u8),
     // Fx0A - LD Vx, K
-    LD_Vx_K(usize),
+    LD_Vx_K(u8),
     // Fx15 - LD DT, Vx
-    LD_DT_Vx(usize),
+    LD_DT_Vx(u8),
     // Fx18 - LD ST, Vx
-    LD_ST_Vx(usize),
+    LD_ST_Vx(u8),
     // Fx1E - ADD I, Vx
-    ADD_I_Vx(usize),
+    ADD_I_Vx(u8),
     // Fx29 - LD F, Vx
-    LD_F_Vx(usize),
+    LD_F_Vx(u8),
+    // Fx30 - LD HF, Vx (SUPER-CHIP)
+    LD_HF_Vx(u8),
+    // Fx3A - PITCH Vx (XO-CHIP)
+    PITCH_Vx(u8),
     // Fx33 - LD B, Vx
-    LD_B_Vx(usize),
+    LD_B_Vx(u8),
     // Fx55 - LD [I], Vx
-    LD_I_Vx(usize),
+    LD_I_Vx(u8),
     // Fx65 - LD Vx, [I]
-    LD_Vx_I(usize),
+    LD_Vx_I(u8),
+    // Fx75 - LD R, Vx (SUPER-CHIP)
+    LD_R_Vx(u8),
+    // Fx85 - LD Vx, R (SUPER-CHIP)
+    LD_Vx_R(u8),
 }
 
-// #[inline]
-// fn dec_xy_(op: u16) -> (usize, usize) {
-//     (((op >> 8) & 0xF) as usize, ((op >> 4) / 0xF) as usize)
-// }
+impl Opcode {
+    /// Returns true if the instruction belongs to the SUPER-CHIP extensions.
+    pub fn is_super_chip(&self) -> bool {
+        matches!(
+            self,
+            Opcode::SCD_nibble(_)
+                | Opcode::SCR
+                | Opcode::SCL
+                | Opcode::EXIT
+                | Opcode::LOW
+                | Opcode::HIGH
+                | Opcode::LD_HF_Vx(_)
+                | Opcode::LD_R_Vx(_)
+                | Opcode::LD_Vx_R(_)
+        )
+    }
+
+    /// Returns true if the instruction belongs to the XO-CHIP extensions.
+    pub fn is_xo_chip(&self) -> bool {
+        matches!(
+            self,
+            Opcode::SCU_nibble(_)
+                | Opcode::SAVE_Vx_Vy(_, _)
+                | Opcode::LOAD_Vx_Vy(_, _)
+                | Opcode::LD_I_long
+                | Opcode::PLANE_n(_)
+                | Opcode::AUDIO
+                | Opcode::PITCH_Vx(_)
+        )
+    }
+
+    /// Returns true if every operand fits in its field: 4 bits for registers
+    /// and nibbles, and 12 bits for addresses.
+    pub fn is_valid(&self) -> bool {
+        let nibble = |n: u8| n <= 0xF;
+        let addr = |nnn: usize| nnn <= 0xFFF;
+        match *self {
+            Opcode::SYS_addr(nnn)
+            | Opcode::JP_addr(nnn)
+            | Opcode::CALL_addr(nnn)
+            | Opcode::LD_I_addr(nnn)
+            | Opcode::JP_V0_addr(nnn) => addr(nnn),
+            Opcode::SCD_nibble(n) | Opcode::SCU_nibble(n) | Opcode::PLANE_n(n) => nibble(n),
+            Opcode::SE_Vx_byte(x, _)
+            | Opcode::SNE_Vx_byte(x, _)
+            | Opcode::LD_Vx_byte(x, _)
+            | Opcode::ADD_Vx_byte(x, _)
+            | Opcode::RND_Vx_byte(x, _)
+            | Opcode::SKP_Vx(x)
+            | Opcode::SKNP_Vx(x)
+            | Opcode::LD_Vx_DT(x)
+            | Opcode::LD_Vx_K(x)
+            | Opcode::LD_DT_Vx(x)
+            | Opcode::LD_ST_Vx(x)
+            | Opcode::ADD_I_Vx(x)
+            | Opcode::LD_F_Vx(x)
+            | Opcode::LD_HF_Vx(x)
+            | Opcode::PITCH_Vx(x)
+            | Opcode::LD_B_Vx(x)
+            | Opcode::LD_I_Vx(x)
+            | Opcode::LD_Vx_I(x)
+            | Opcode::LD_R_Vx(x)
+            | Opcode::LD_Vx_R(x) => nibble(x),
+            Opcode::SE_Vx_Vy(x, y)
+            | Opcode::SAVE_Vx_Vy(x, y)
+            | Opcode::LOAD_Vx_Vy(x, y)
+            | Opcode::LD_Vx_Vy(x, y)
+            | Opcode::OR_Vx_Vy(x, y)
+            | Opcode::AND_Vx_Vy(x, y)
+            | Opcode::XOR_Vx_Vy(x, y)
+            | Opcode::ADD_Vx_Vy(x, y)
+            | Opcode::SUB_Vx_Vy(x, y)
+            | Opcode::SHR_Vx_Vy(x, y)
+            | Opcode::SUBN_Vx_Vy(x, y)
+            | Opcode::SHL_Vx_Vy(x, y)
+            | Opcode::SNE_Vx_Vy(x, y) => nibble(x) && nibble(y),
+            Opcode::DRW_Vx_Vy_nibble(x, y, n) => nibble(x) && nibble(y) && nibble(n),
+            Opcode::CLS
+            | Opcode::RET
+            | Opcode::SCR
+            | Opcode::SCL
+            | Opcode::EXIT
+            | Opcode::LOW
+            | Opcode::HIGH
+            | Opcode::LD_I_long
+            | Opcode::AUDIO => true,
+        }
+    }
+
+    /// Encodes the instruction. The inverse of decoding it with
+    /// [`Opcode::try_from`]. `LD I, LONG` encodes to `F000`, without the
+    /// address that follows it.
+    ///
+    /// Returns the instruction back if an operand doesn't fit in its field
+    /// (see [`Opcode::is_valid`]).
+    pub fn encode(&self) -> Result<u16, Opcode> {
+        if !self.is_valid() {
+            return Err(*self);
+        }
+        let x = |x: u8| u16::from(x) << 8;
+        let xy = |x: u8, y: u8| u16::from(x) << 8 | u16::from(y) << 4;
+        let xkk = |x: u8, kk: u8| u16::from(x) << 8 | u16::from(kk);
+        Ok(match *self {
+            Opcode::SYS_addr(nnn) => nnn as u16,
+            Opcode::SCD_nibble(n) => 0x00C0 | u16::from(n),
+            Opcode::SCU_nibble(n) => 0x00D0 | u16::from(n),
+            Opcode::CLS => 0x00E0,
+            Opcode::RET => 0x00EE,
+            Opcode::SCR => 0x00FB,
+            Opcode::SCL => 0x00FC,
+            Opcode::EXIT => 0x00FD,
+            Opcode::LOW => 0x00FE,
+            Opcode::HIGH => 0x00FF,
+            Opcode::JP_addr(nnn) => 0x1000 | nnn as u16,
+            Opcode::CALL_addr(nnn) => 0x2000 | nnn as u16,
+            Opcode::SE_Vx_byte(vx, kk) => 0x3000 | xkk(vx, kk),
+            Opcode::SNE_Vx_byte(vx, kk) => 0x4000 | xkk(vx, kk),
+            Opcode::SE_Vx_Vy(vx, vy) => 0x5000 | xy(vx, vy),
+            Opcode::SAVE_Vx_Vy(vx, vy) => 0x5002 | xy(vx, vy),
+            Opcode::LOAD_Vx_Vy(vx, vy) => 0x5003 | xy(vx, vy),
+            Opcode::LD_Vx_byte(vx, kk) => 0x6000 | xkk(vx, kk),
+            Opcode::ADD_Vx_byte(vx, kk) => 0x7000 | xkk(vx, kk),
+            Opcode::LD_Vx_Vy(vx, vy) => 0x8000 | xy(vx, vy),
+            Opcode::OR_Vx_Vy(vx, vy) => 0x8001 | xy(vx, vy),
+            Opcode::AND_Vx_Vy(vx, vy) => 0x8002 | xy(vx, vy),
+            Opcode::XOR_Vx_Vy(vx, vy) => 0x8003 | xy(vx, vy),
+            Opcode::ADD_Vx_Vy(vx, vy) => 0x8004 | xy(vx, vy),
+            Opcode::SUB_Vx_Vy(vx, vy) => 0x8005 | xy(vx, vy),
+            Opcode::SHR_Vx_Vy(vx, vy) => 0x8006 | xy(vx, vy),
+            Opcode::SUBN_Vx_Vy(vx, vy) => 0x8007 | xy(vx, vy),
+            Opcode::SHL_Vx_Vy(vx, vy) => 0x800E | xy(vx, vy),
+            Opcode::SNE_Vx_Vy(vx, vy) => 0x9000 | xy(vx, vy),
+            Opcode::LD_I_addr(nnn) => 0xA000 | nnn as u16,
+            Opcode::JP_V0_addr(nnn) => 0xB000 | nnn as u16,
+            Opcode::RND_Vx_byte(vx, kk) => 0xC000 | xkk(vx, kk),
+            Opcode::DRW_Vx_Vy_nibble(vx, vy, n) => 0xD000 | xy(vx, vy) | u16::from(n),
+            Opcode::SKP_Vx(vx) => 0xE09E | x(vx),
+            Opcode::SKNP_Vx(vx) => 0xE0A1 | x(vx),
+            Opcode::LD_I_long => 0xF000,
+            Opcode::PLANE_n(n) => 0xF001 | x(n),
+            Opcode::AUDIO => 0xF002,
+            Opcode::LD_Vx_DT(vx) => 0xF007 | x(vx),
+            Opcode::LD_Vx_K(vx) => 0xF00A | x(vx),
+            Opcode::LD_DT_Vx(vx) => 0xF015 | x(vx),
+            Opcode::LD_ST_Vx(vx) => 0xF018 | x(vx),
+            Opcode::ADD_I_Vx(vx) => 0xF01E | x(vx),
+            Opcode::LD_F_Vx(vx) => 0xF029 | x(vx),
+            Opcode::LD_HF_Vx(vx) => 0xF030 | x(vx),
+            Opcode::PITCH_Vx(vx) => 0xF03A | x(vx),
+            Opcode::LD_B_Vx(vx) => 0xF033 | x(vx),
+            Opcode::LD_I_Vx(vx) => 0xF055 | x(vx),
+            Opcode::LD_Vx_I(vx) => 0xF065 | x(vx),
+            Opcode::LD_R_Vx(vx) => 0xF075 | x(vx),
+            Opcode::LD_Vx_R(vx) => 0xF085 | x(vx),
+        })
+    }
+}
+
+impl fmt::Display for Opcode {
+    /// Formats the instruction in the usual assembler syntax, such as
+    /// `LD V3, 0x0A`. The operand of `LD I, LONG` is the word that follows
+    /// the instruction, so it's left out.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Opcode::SYS_addr(nnn) => write!(f, "SYS 0x{:03X}", nnn),
+            Opcode::SCD_nibble(n) => write!(f, "SCD {}", n),
+            Opcode::SCU_nibble(n) => write!(f, "SCU {}", n),
+            Opcode::CLS => write!(f, "CLS"),
+            Opcode::RET => write!(f, "RET"),
+            Opcode::SCR => write!(f, "SCR"),
+            Opcode::SCL => write!(f, "SCL"),
+            Opcode::EXIT => write!(f, "EXIT"),
+            Opcode::LOW => write!(f, "LOW"),
+            Opcode::HIGH => write!(f, "HIGH"),
+            Opcode::JP_addr(nnn) => write!(f, "JP 0x{:03X}", nnn),
+            Opcode::CALL_addr(nnn) => write!(f, "CALL 0x{:03X}", nnn),
+            Opcode::SE_Vx_byte(x, kk) => write!(f, "SE V{:X}, 0x{:02X}", x, kk),
+            Opcode::SNE_Vx_byte(x, kk) => write!(f, "SNE V{:X}, 0x{:02X}", x, kk),
+            Opcode::SE_Vx_Vy(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Opcode::SAVE_Vx_Vy(x, y) => write!(f, "SAVE V{:X}, V{:X}", x, y),
+            Opcode::LOAD_Vx_Vy(x, y) => write!(f, "LOAD V{:X}, V{:X}", x, y),
+            Opcode::LD_Vx_byte(x, kk) => write!(f, "LD V{:X}, 0x{:02X}", x, kk),
+            Opcode::ADD_Vx_byte(x, kk) => write!(f, "ADD V{:X}, 0x{:02X}", x, kk),
+            Opcode::LD_Vx_Vy(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Opcode::OR_Vx_Vy(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            Opcode::AND_Vx_Vy(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Opcode::XOR_Vx_Vy(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Opcode::ADD_Vx_Vy(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Opcode::SUB_Vx_Vy(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Opcode::SHR_Vx_Vy(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Opcode::SUBN_Vx_Vy(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Opcode::SHL_Vx_Vy(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Opcode::SNE_Vx_Vy(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Opcode::LD_I_addr(nnn) => write!(f, "LD I, 0x{:03X}", nnn),
+            Opcode::JP_V0_addr(nnn) => write!(f, "JP V0, 0x{:03X}", nnn),
+            Opcode::RND_Vx_byte(x, kk) => write!(f, "RND V{:X}, 0x{:02X}", x, kk),
+            Opcode::DRW_Vx_Vy_nibble(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Opcode::SKP_Vx(x) => write!(f, "SKP V{:X}", x),
+            Opcode::SKNP_Vx(x) => write!(f, "SKNP V{:X}", x),
+            Opcode::LD_I_long => write!(f, "LD I, LONG"),
+            Opcode::PLANE_n(n) => write!(f, "PLANE {}", n),
+            Opcode::AUDIO => write!(f, "AUDIO"),
+            Opcode::LD_Vx_DT(x) => write!(f, "LD V{:X}, DT", x),
+            Opcode::LD_Vx_K(x) => write!(f, "LD V{:X}, K", x),
+            Opcode::LD_DT_Vx(x) => write!(f, "LD DT, V{:X}", x),
+            Opcode::LD_ST_Vx(x) => write!(f, "LD ST, V{:X}", x),
+            Opcode::ADD_I_Vx(x) => write!(f, "ADD I, V{:X}", x),
+            Opcode::LD_F_Vx(x) => write!(f, "LD F, V{:X}", x),
+            Opcode::LD_HF_Vx(x) => write!(f, "LD HF, V{:X}", x),
+            Opcode::PITCH_Vx(x) => write!(f, "PITCH V{:X}", x),
+            Opcode::LD_B_Vx(x) => write!(f, "LD B, V{:X}", x),
+            Opcode::LD_I_Vx(x) => write!(f, "LD [I], V{:X}", x),
+            Opcode::LD_Vx_I(x) => write!(f, "LD V{:X}, [I]", x),
+            Opcode::LD_R_Vx(x) => write!(f, "LD R, V{:X}", x),
+            Opcode::LD_Vx_R(x) => write!(f, "LD V{:X}, R", x),
+        }
+    }
+}
 
 #[inline]
-fn dec_xkk(op: u16) -> (usize, u8) {
-    (((op >> 8) & 0xF) as usize, (op & 0xFF) as u8)
+fn dec_xkk(op: u16) -> (u8, u8) {
+    (((op >> 8) & 0xF) as u8, (op & 0xFF) as u8)
 }
 
 #[inline]
@@ -88,34 +336,42 @@ fn dec_nnn(op: u16) -> usize {
     (op & 0xFFF) as usize
 }
 
-// #[inline]
-// fn dec_x__(op: u16) -> usize {
-//     ((op >> 8) & 0xF) as usize
-// }
-
 #[inline]
-fn dec_xyn(op: u16) -> (usize, usize, u8) {
+fn dec_xyn(op: u16) -> (u8, u8, u8) {
     (
-        ((op >> 8) & 0xF) as usize,
-        ((op >> 4) & 0xF) as usize,
+        ((op >> 8) & 0xF) as u8,
+        ((op >> 4) & 0xF) as u8,
         (op & 0xF) as u8,
     )
 }
 
-impl From<u16> for Opcode {
-    fn from(op: u16) -> Self {
+impl TryFrom<u16> for Opcode {
+    type Error = u16;
+
+    /// Decodes an instruction, returning it back as the error if it doesn't
+    /// correspond to any known opcode.
+    fn try_from(op: u16) -> Result<Self, Self::Error> {
         let (x, y, n) = dec_xyn(op);
         let (_, kk) = dec_xkk(op);
         let nnn = dec_nnn(op);
-        match op {
+        let opcode = match op {
+            op if op & 0xFFF0 == 0x00C0 => Opcode::SCD_nibble(n),
+            op if op & 0xFFF0 == 0x00D0 => Opcode::SCU_nibble(n),
             0x00E0 => Opcode::CLS,
             0x00EE => Opcode::RET,
+            0x00FB => Opcode::SCR,
+            0x00FC => Opcode::SCL,
+            0x00FD => Opcode::EXIT,
+            0x00FE => Opcode::LOW,
+            0x00FF => Opcode::HIGH,
             op if op & 0xF000 == 0x0000 => Opcode::SYS_addr(nnn),
             op if op & 0xF000 == 0x1000 => Opcode::JP_addr(nnn),
             op if op & 0xF000 == 0x2000 => Opcode::CALL_addr(nnn),
             op if op & 0xF000 == 0x3000 => Opcode::SE_Vx_byte(x, kk),
             op if op & 0xF000 == 0x4000 => Opcode::SNE_Vx_byte(x, kk),
             op if op & 0xF00F == 0x5000 => Opcode::SE_Vx_Vy(x, y),
+            op if op & 0xF00F == 0x5002 => Opcode::SAVE_Vx_Vy(x, y),
+            op if op & 0xF00F == 0x5003 => Opcode::LOAD_Vx_Vy(x, y),
             op if op & 0xF000 == 0x6000 => Opcode::LD_Vx_byte(x, kk),
             op if op & 0xF000 == 0x7000 => Opcode::ADD_Vx_byte(x, kk),
             op if op & 0xF00F == 0x8000 => Opcode::LD_Vx_Vy(x, y),
@@ -134,21 +390,60 @@ impl From<u16> for Opcode {
             op if op & 0xF000 == 0xD000 => Opcode::DRW_Vx_Vy_nibble(x, y, n),
             op if op & 0xF0FF == 0xE09E => Opcode::SKP_Vx(x),
             op if op & 0xF0FF == 0xE0A1 => Opcode::SKNP_Vx(x),
+            0xF000 => Opcode::LD_I_long,
+            op if op & 0xF0FF == 0xF001 => Opcode::PLANE_n(x),
+            0xF002 => Opcode::AUDIO,
             op if op & 0xF0FF == 0xF007 => Opcode::LD_Vx_DT(x),
             op if op & 0xF0FF == 0xF00A => Opcode::LD_Vx_K(x),
             op if op & 0xF0FF == 0xF015 => Opcode::LD_DT_Vx(x),
             op if op & 0xF0FF == 0xF018 => Opcode::LD_ST_Vx(x),
             op if op & 0xF0FF == 0xF01E => Opcode::ADD_I_Vx(x),
             op if op & 0xF0FF == 0xF029 => Opcode::LD_F_Vx(x),
+            op if op & 0xF0FF == 0xF030 => Opcode::LD_HF_Vx(x),
+            op if op & 0xF0FF == 0xF03A => Opcode::PITCH_Vx(x),
             op if op & 0xF0FF == 0xF033 => Opcode::LD_B_Vx(x),
             op if op & 0xF0FF == 0xF055 => Opcode::LD_I_Vx(x),
             op if op & 0xF0FF == 0xF065 => Opcode::LD_Vx_I(x),
-            _ => panic!("Unknown opcode = {:X?}", op),
-        }
+            op if op & 0xF0FF == 0xF075 => Opcode::LD_R_Vx(x),
+            op if op & 0xF0FF == 0xF085 => Opcode::LD_Vx_R(x),
+            _ => return Err(op),
+        };
+        Ok(opcode)
     }
 }
 
 #[cfg(test)]
 mod test {
-    // TODO some much needed tests.
+    use super::Opcode;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn encode_decode() {
+        for word in 0..=0xFFFF {
+            if let Ok(op) = Opcode::try_from(word) {
+                assert!(op.is_valid(), "{:04X} = {:?}", word, op);
+                assert_eq!(op.encode(), Ok(word), "{:?}", op);
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_operands() {
+        assert!(!Opcode::LD_Vx_byte(16, 0).is_valid());
+        assert!(!Opcode::DRW_Vx_Vy_nibble(0, 0, 16).is_valid());
+        assert!(!Opcode::JP_addr(0x1000).is_valid());
+        assert!(Opcode::JP_addr(0xFFF).is_valid());
+    }
+
+    #[test]
+    fn encode_invalid() {
+        assert_eq!(
+            Opcode::JP_addr(0x1000).encode(),
+            Err(Opcode::JP_addr(0x1000))
+        );
+        assert_eq!(
+            Opcode::LD_Vx_byte(16, 0).encode(),
+            Err(Opcode::LD_Vx_byte(16, 0))
+        );
+    }
 }
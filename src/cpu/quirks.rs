@@ -0,0 +1,99 @@
+use std::str::FromStr;
+
+/// Interpretation of the instructions that behave differently across CHIP-8
+/// implementations.
+///
+/// The default value keeps the behaviour of the original version of this
+/// interpreter: shifts ignore Vy, load/store leave I untouched, `JP V0, addr`
+/// uses V0, sprites wrap around the display and there is no VF reset or
+/// display wait.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Quirks {
+    /// `SHR Vx, Vy` and `SHL Vx, Vy` shift Vy and store the result in Vx,
+    /// instead of shifting Vx in place.
+    pub shift_uses_vy: bool,
+    /// How `LD [I], Vx` and `LD Vx, [I]` update I.
+    pub load_store: LoadStore,
+    /// `Bxnn` jumps to `xnn + Vx` instead of `xnn + V0`.
+    pub jump_uses_vx: bool,
+    /// Sprites drawn across the edge of the display are clipped instead of
+    /// wrapping around to the opposite side.
+    pub clip_sprites: bool,
+    /// `OR`, `AND` and `XOR` reset VF to zero.
+    pub vf_reset: bool,
+    /// `DRW` waits for the next timer update (vertical blank) before the
+    /// program resumes.
+    pub display_wait: bool,
+}
+
+/// How `LD [I], Vx` and `LD Vx, [I]` update I.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LoadStore {
+    /// I is left untouched.
+    #[default]
+    Unchanged,
+    /// I is left pointing at the last accessed address (`I + x`).
+    IncrementX,
+    /// I is left pointing past the last accessed address (`I + x + 1`).
+    IncrementX1,
+}
+
+impl Quirks {
+    /// Original COSMAC VIP interpreter.
+    pub const COSMAC_VIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store: LoadStore::IncrementX1,
+        jump_uses_vx: false,
+        clip_sprites: true,
+        vf_reset: true,
+        display_wait: true,
+    };
+
+    /// CHIP-48 interpreter for the HP-48 calculators.
+    pub const CHIP_48: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store: LoadStore::IncrementX,
+        jump_uses_vx: true,
+        clip_sprites: true,
+        vf_reset: false,
+        display_wait: false,
+    };
+
+    /// SUPER-CHIP 1.1. Same as CHIP-48, except that load/store leave I
+    /// untouched.
+    pub const SUPER_CHIP: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store: LoadStore::Unchanged,
+        jump_uses_vx: true,
+        clip_sprites: true,
+        vf_reset: false,
+        display_wait: false,
+    };
+
+    /// Modern interpreters such as Octo (and XO-CHIP).
+    pub const MODERN: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store: LoadStore::IncrementX1,
+        jump_uses_vx: false,
+        clip_sprites: false,
+        vf_reset: false,
+        display_wait: false,
+    };
+}
+
+impl FromStr for Quirks {
+    type Err = String;
+
+    /// Parses the name of a profile: `vip`, `chip48`, `schip` or `modern`.
+    fn from_str(profile: &str) -> Result<Self, Self::Err> {
+        match profile {
+            "vip" => Ok(Quirks::COSMAC_VIP),
+            "chip48" => Ok(Quirks::CHIP_48),
+            "schip" => Ok(Quirks::SUPER_CHIP),
+            "modern" => Ok(Quirks::MODERN),
+            _ => Err(format!("unknown quirks profile = {}", profile)),
+        }
+    }
+}
@@ -0,0 +1,78 @@
+/// Source of random numbers for `RND Vx, byte`.
+///
+/// The whole state of a generator must fit in a `u64` (its seed), so that it
+/// can be captured in save states and restored to reproduce the exact same
+/// sequence of numbers.
+pub trait Rng: Send {
+    /// Returns the next random byte.
+    fn next_u8(&mut self) -> u8;
+
+    /// Returns the current state of the generator. Reseeding a generator with
+    /// it resumes the sequence from this point.
+    fn seed(&self) -> u64;
+
+    /// Restarts the sequence from the given seed.
+    fn reseed(&mut self, seed: u64);
+}
+
+/// Seed used when none is given, or when the given one is zero (which would
+/// make the generator stuck at zero forever).
+pub const DEFAULT_SEED: u64 = 0x2545_F491_4F6C_DD1D;
+
+/// Xorshift64* generator.
+#[derive(Debug, Clone)]
+pub struct XorShift {
+    state: u64,
+}
+
+impl Default for XorShift {
+    fn default() -> Self {
+        Self::new(DEFAULT_SEED)
+    }
+}
+
+impl XorShift {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = Self { state: 0 };
+        rng.reseed(seed);
+        rng
+    }
+}
+
+impl Rng for XorShift {
+    fn next_u8(&mut self) -> u8 {
+        let mut x = self.state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.state = x;
+        (x.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+
+    fn seed(&self) -> u64 {
+        self.state
+    }
+
+    fn reseed(&mut self, seed: u64) {
+        self.state = if seed == 0 { DEFAULT_SEED } else { seed };
+    }
+}
+
+/// Serializes the generator as its seed, and deserializes it back into a
+/// [`XorShift`].
+#[cfg(feature = "serde")]
+pub(crate) mod serde_seed {
+    use super::{Rng, XorShift};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    #[allow(clippy::borrowed_box)]
+    pub fn serialize<S: Serializer>(rng: &Box<dyn Rng>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(rng.seed())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Box<dyn Rng>, D::Error> {
+        Ok(Box::new(XorShift::new(u64::deserialize(deserializer)?)))
+    }
+}
@@ -0,0 +1,543 @@
+//! Versioned binary save states.
+//!
+//! A save state is laid out as follows. Multi-byte values are little endian.
+//!
+//! | Size     | Contents                                                   |
+//! |----------|------------------------------------------------------------|
+//! | 4        | Magic header `CH8S`                                        |
+//! | 1        | Format version (currently 1)                               |
+//! | 1        | Mode (0 = CHIP-8, 1 = SUPER-CHIP, 2 = XO-CHIP)             |
+//! | 1        | Quirks bitmask (see below)                                 |
+//! | 16       | V0 to VF                                                   |
+//! | 2        | I                                                          |
+//! | 4        | PC                                                         |
+//! | 1        | SP                                                         |
+//! | 1        | DT                                                         |
+//! | 1        | ST                                                         |
+//! | 32       | Stack (16 entries)                                         |
+//! | 13       | Cpu state (tag byte and payload, see below)                |
+//! | 1        | Waiting for the display (0 or 1)                           |
+//! | 16       | Keypad (0 = up, 1 = down)                                  |
+//! | 16       | Keypad events since the last step                          |
+//! | 16       | RPL user flags                                             |
+//! | 16       | Audio pattern buffer                                       |
+//! | 1        | Pitch                                                      |
+//! | 1        | High resolution (0 or 1)                                   |
+//! | 1        | Selected planes bitmask                                    |
+//! | 2 * 1024 | Display planes, one bit per pixel, row-major, MSB first    |
+//! | 8        | Random number generator seed                               |
+//! | 4        | Memory size `n`                                            |
+//! | n        | Memory                                                     |
+//! | 4        | CRC-32 (IEEE) of every preceding byte                      |
+//!
+//! The cpu state tag is `0` for halt, `1` for running, `2` for waiting for
+//! input (followed by the register index) and `3` for a fault, followed by
+//! the error kind (0 = invalid opcode, 1 = stack overflow, 2 = stack
+//! underflow, 3 = memory out of bounds, 4 = PC out of bounds, 5 = ROM too
+//! large), and the PC, opcode and faulting address as 4, 2 and 4 byte values
+//! (the ROM size and capacity take the place of the PC and address for
+//! kind 5). The tag is `4` when stopped at a breakpoint, followed by the
+//! breakpoint id, the PC and the accessed address plus one (zero if none) as
+//! 4 byte values.
+//! Unused payload bytes are zero.
+//!
+//! The quirks bitmask sets bit 0 for `shift_uses_vy`, bit 2 for
+//! `jump_uses_vx`, bit 3 for `clip_sprites`, bit 4 for `vf_reset` and bit 5
+//! for `display_wait`. Bits 1 and 6 hold `load_store`: neither for
+//! `Unchanged`, bit 1 for `IncrementX1` and both for `IncrementX`. Bit 6
+//! alone and bit 7 are invalid.
+//!
+//! Breakpoints themselves belong to the host and are not saved.
+use super::{
+    display::{Display, MAX_HEIGHT, MAX_WIDTH, PLANES},
+    Cpu, CpuError, CpuState, KeyState, LoadStore, Mode, PixelState, Quirks, Timing, Trigger,
+    STACK_SIZE,
+};
+use std::{error::Error, fmt, mem};
+
+const MAGIC: &[u8; 4] = b"CH8S";
+const VERSION: u8 = 1;
+const PLANE_BYTES: usize = MAX_WIDTH * MAX_HEIGHT / 8;
+
+/// Errors raised when restoring a save state.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum StateError {
+    /// The data doesn't start with the magic header.
+    BadMagic,
+    /// The format version is not supported.
+    UnsupportedVersion(u8),
+    /// The checksum doesn't match the contents.
+    Checksum,
+    /// The data ended unexpectedly.
+    Truncated,
+    /// The data contains a value out of range.
+    Invalid,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(v) => {
+                write!(f, "unsupported save state version = {}", v)
+            }
+            StateError::Checksum => write!(f, "save state checksum mismatch"),
+            StateError::Truncated => write!(f, "truncated save state"),
+            StateError::Invalid => write!(f, "invalid save state"),
+        }
+    }
+}
+
+impl Error for StateError {}
+
+pub(super) fn save(cpu: &Cpu) -> Vec<u8> {
+    let mut out = Vec::with_capacity(cpu.memory.len() + 2 * PLANE_BYTES + 256);
+    out.extend_from_slice(MAGIC);
+    out.push(VERSION);
+    out.push(mode_to_byte(cpu.mode));
+    out.push(quirks_to_bits(&cpu.quirks));
+    out.extend_from_slice(&cpu.registers);
+    out.extend_from_slice(&cpu.i.to_le_bytes());
+    out.extend_from_slice(&(cpu.pc as u32).to_le_bytes());
+    out.push(cpu.sp as u8);
+    out.push(cpu.dt as u8);
+    out.push(cpu.st as u8);
+    for entry in cpu.stack.iter() {
+        out.extend_from_slice(&entry.to_le_bytes());
+    }
+    write_state(&mut out, &cpu.state);
+    out.push(cpu.vblank_wait as u8);
+    out.extend(cpu.keypad.iter().map(|k| *k as u8));
+    out.extend(cpu.keypad_event.iter().map(|k| *k as u8));
+    out.extend_from_slice(&cpu.rpl);
+    out.extend_from_slice(&cpu.pattern);
+    out.push(cpu.pitch);
+    out.push(cpu.display.hires as u8);
+    out.push(cpu.display.selected);
+    for plane in cpu.display.planes.iter() {
+        for byte in plane.chunks(8) {
+            out.push(
+                byte.iter()
+                    .fold(0, |acc, p| acc << 1 | (*p == PixelState::On) as u8),
+            );
+        }
+    }
+    out.extend_from_slice(&cpu.rng.seed().to_le_bytes());
+    out.extend_from_slice(&(cpu.memory.len() as u32).to_le_bytes());
+    out.extend_from_slice(&cpu.memory);
+    let crc = crc32(&out);
+    out.extend_from_slice(&crc.to_le_bytes());
+    out
+}
+
+/// Restores the cpu from a save state. The cpu is left untouched on error.
+///
+/// The timing model, breakpoints and random number source are kept, since
+/// they're chosen by the host (only the seed of the latter is restored).
+pub(super) fn load(cpu: &mut Cpu, data: &[u8]) -> Result<(), StateError> {
+    if data.len() < MAGIC.len() + 1 + 4 {
+        return Err(StateError::Truncated);
+    }
+    if &data[..4] != MAGIC {
+        return Err(StateError::BadMagic);
+    }
+    let version = data[4];
+    if version != VERSION {
+        return Err(StateError::UnsupportedVersion(version));
+    }
+    let (body, crc) = data.split_at(data.len() - 4);
+    if crc32(body).to_le_bytes() != crc {
+        return Err(StateError::Checksum);
+    }
+
+    let mut r = Reader(&body[5..]);
+    let mode = mode_from_byte(r.u8()?)?;
+    let mut new = Cpu::with_mode(mode, quirks_from_bits(r.u8()?)?);
+    new.registers.copy_from_slice(r.bytes(16)?);
+    new.i = r.u16()?;
+    new.pc = r.u32()? as usize;
+    new.sp = r.u8()? as usize;
+    new.dt = r.u8()? as usize;
+    new.st = r.u8()? as usize;
+    if new.sp > STACK_SIZE {
+        return Err(StateError::Invalid);
+    }
+    for entry in new.stack.iter_mut() {
+        *entry = r.u16()?;
+    }
+    new.state = read_state(&mut r)?;
+    new.vblank_wait = r.u8()? != 0;
+    for key in new.keypad.iter_mut().chain(new.keypad_event.iter_mut()) {
+        *key = match r.u8()? {
+            0 => KeyState::Up,
+            1 => KeyState::Down,
+            _ => return Err(StateError::Invalid),
+        };
+    }
+    new.rpl.copy_from_slice(r.bytes(16)?);
+    new.pattern.copy_from_slice(r.bytes(16)?);
+    new.pitch = r.u8()?;
+    let mut display = Display {
+        hires: r.u8()? != 0,
+        selected: r.u8()? & 0b11,
+        ..Display::default()
+    };
+    for plane in 0..PLANES {
+        let bits = r.bytes(PLANE_BYTES)?;
+        for (pixel, p) in display.planes[plane].iter_mut().enumerate() {
+            if bits[pixel / 8] & (0x80 >> (pixel % 8)) != 0 {
+                *p = PixelState::On;
+            }
+        }
+    }
+    new.display = display;
+    let seed = r.u64()?;
+    let len = r.u32()? as usize;
+    if len != mode.memory_size() {
+        return Err(StateError::Invalid);
+    }
+    new.memory.copy_from_slice(r.bytes(len)?);
+    if !r.0.is_empty() {
+        return Err(StateError::Invalid);
+    }
+
+    // the timing model, cycle counter and breakpoints belong to the host
+    new.timing = cpu.timing;
+    new.cycles = cpu.cycles;
+    new.breakpoints = mem::take(&mut cpu.breakpoints);
+    // keep the host's random number source, only restoring its seed
+    mem::swap(&mut new.rng, &mut cpu.rng);
+    new.rng.reseed(seed);
+    *cpu = new;
+    Ok(())
+}
+
+pub(crate) fn mode_to_byte(mode: Mode) -> u8 {
+    match mode {
+        Mode::Chip8 => 0,
+        Mode::SuperChip => 1,
+        Mode::XoChip => 2,
+    }
+}
+
+pub(crate) fn mode_from_byte(byte: u8) -> Result<Mode, StateError> {
+    match byte {
+        0 => Ok(Mode::Chip8),
+        1 => Ok(Mode::SuperChip),
+        2 => Ok(Mode::XoChip),
+        _ => Err(StateError::Invalid),
+    }
+}
+
+/// Encodes a timing model. Save states don't include it, but movies do.
+pub(crate) fn timing_to_byte(timing: Timing) -> u8 {
+    match timing {
+        Timing::Instructions => 0,
+        Timing::CosmacVip => 1,
+    }
+}
+
+pub(crate) fn timing_from_byte(byte: u8) -> Result<Timing, StateError> {
+    match byte {
+        0 => Ok(Timing::Instructions),
+        1 => Ok(Timing::CosmacVip),
+        _ => Err(StateError::Invalid),
+    }
+}
+
+pub(crate) fn quirks_to_bits(quirks: &Quirks) -> u8 {
+    let load_store = match quirks.load_store {
+        LoadStore::Unchanged => 0x00,
+        LoadStore::IncrementX1 => 0x02,
+        LoadStore::IncrementX => 0x42,
+    };
+    load_store
+        | quirks.shift_uses_vy as u8
+        | (quirks.jump_uses_vx as u8) << 2
+        | (quirks.clip_sprites as u8) << 3
+        | (quirks.vf_reset as u8) << 4
+        | (quirks.display_wait as u8) << 5
+}
+
+pub(crate) fn quirks_from_bits(bits: u8) -> Result<Quirks, StateError> {
+    let load_store = match bits & 0x42 {
+        0x00 => LoadStore::Unchanged,
+        0x02 => LoadStore::IncrementX1,
+        0x42 => LoadStore::IncrementX,
+        _ => return Err(StateError::Invalid),
+    };
+    if bits & 0x80 != 0 {
+        return Err(StateError::Invalid);
+    }
+    Ok(Quirks {
+        shift_uses_vy: bits & 0x01 != 0,
+        load_store,
+        jump_uses_vx: bits & 0x04 != 0,
+        clip_sprites: bits & 0x08 != 0,
+        vf_reset: bits & 0x10 != 0,
+        display_wait: bits & 0x20 != 0,
+    })
+}
+
+/// Writes the cpu state as a tag byte followed by 12 bytes of payload.
+fn write_state(out: &mut Vec<u8>, state: &CpuState) {
+    let mut payload = [0u8; 12];
+    let tag = match state {
+        CpuState::Halt => 0,
+        CpuState::Running => 1,
+        CpuState::WaitInput(x) => {
+            payload[0] = *x;
+            2
+        }
+        CpuState::Fault(err) => {
+            let (kind, pc, addr) = match *err {
+                CpuError::InvalidOpcode { pc, .. } => (0, pc, 0),
+                CpuError::StackOverflow { pc, .. } => (1, pc, 0),
+                CpuError::StackUnderflow { pc, .. } => (2, pc, 0),
+                CpuError::MemoryOutOfBounds { pc, addr, .. } => (3, pc, addr),
+                CpuError::PcOutOfBounds { pc } => (4, pc, 0),
+                // never a fault state, since loading fails before the state
+                // changes
+                CpuError::RomTooLarge { size, capacity } => (5, size, capacity),
+            };
+            payload[0] = kind;
+            payload[1..5].copy_from_slice(&(pc as u32).to_le_bytes());
+            payload[5..7].copy_from_slice(&err.opcode().unwrap_or(0).to_le_bytes());
+            payload[7..11].copy_from_slice(&(addr as u32).to_le_bytes());
+            3
+        }
+        CpuState::Break(trigger) => {
+            let addr = trigger.addr.map_or(0, |addr| addr as u32 + 1);
+            payload[0..4].copy_from_slice(&(trigger.id as u32).to_le_bytes());
+            payload[4..8].copy_from_slice(&(trigger.pc as u32).to_le_bytes());
+            payload[8..12].copy_from_slice(&addr.to_le_bytes());
+            4
+        }
+    };
+    out.push(tag);
+    out.extend_from_slice(&payload);
+}
+
+fn read_state(r: &mut Reader) -> Result<CpuState, StateError> {
+    let tag = r.u8()?;
+    let mut p = Reader(r.bytes(12)?);
+    let state = match tag {
+        0 => CpuState::Halt,
+        1 => CpuState::Running,
+        2 => match p.u8()? {
+            x if x < 16 => CpuState::WaitInput(x),
+            _ => return Err(StateError::Invalid),
+        },
+        3 => {
+            let kind = p.u8()?;
+            let pc = p.u32()? as usize;
+            let opcode = p.u16()?;
+            let addr = p.u32()? as usize;
+            CpuState::Fault(match kind {
+                0 => CpuError::InvalidOpcode { pc, opcode },
+                1 => CpuError::StackOverflow { pc, opcode },
+                2 => CpuError::StackUnderflow { pc, opcode },
+                3 => CpuError::MemoryOutOfBounds { pc, opcode, addr },
+                4 => CpuError::PcOutOfBounds { pc },
+                5 => CpuError::RomTooLarge {
+                    size: pc,
+                    capacity: addr,
+                },
+                _ => return Err(StateError::Invalid),
+            })
+        }
+        4 => {
+            let id = p.u32()? as usize;
+            let pc = p.u32()? as usize;
+            let addr = p.u32()?;
+            CpuState::Break(Trigger {
+                id,
+                pc,
+                addr: addr.checked_sub(1).map(|addr| addr as usize),
+            })
+        }
+        _ => return Err(StateError::Invalid),
+    };
+    Ok(state)
+}
+
+/// Little endian decoder of the fields of a save state (or movie), raising
+/// [`StateError::Truncated`] past the end of the data.
+pub(crate) struct Reader<'a>(pub(crate) &'a [u8]);
+
+impl<'a> Reader<'a> {
+    pub(crate) fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.0.len() < len {
+            return Err(StateError::Truncated);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, StateError> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, StateError> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, StateError> {
+        let mut b = [0; 8];
+        b.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(b))
+    }
+}
+
+/// CRC-32 (IEEE 802.3), as used by zlib and PNG.
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc32, StateError};
+    use crate::cpu::{Cpu, CpuError, CpuState, Mode, Quirks};
+
+    /// Return a cpu in the middle of a program, with a bit of everything set.
+    fn running_cpu() -> Cpu {
+        let mut cpu = Cpu::with_mode(Mode::XoChip, Quirks::MODERN);
+        cpu.set_rng(Box::new(crate::cpu::XorShift::new(42)));
+        // HIGH; LD V3, 0x42; LD I, font 0; DRW V0, V1, 5; CALL 0x20A; RND V5, 0xFF
+        let rom = [
+            0x00, 0xFF, 0x63, 0x42, 0xA0, 0x00, 0xD0, 0x15, 0x22, 0x0A, 0xC5, 0xFF,
+        ];
+        cpu.load(&rom[..]).unwrap();
+        for _ in 0..6 {
+            cpu.step().unwrap();
+            cpu.update_timers();
+        }
+        cpu
+    }
+
+    /// Replaces the checksum to match the (edited) contents.
+    fn reseal(state: &mut Vec<u8>) {
+        let len = state.len() - 4;
+        state.truncate(len);
+        let crc = crc32(state);
+        state.extend_from_slice(&crc.to_le_bytes());
+    }
+
+    #[test]
+    fn round_trip() {
+        let cpu = running_cpu();
+        let state = cpu.save_state();
+        let mut restored = Cpu::new();
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.save_state(), state);
+        assert_eq!(restored.registers(), cpu.registers());
+        assert_eq!(restored.program_counter(), cpu.program_counter());
+        assert_eq!(restored.seed(), cpu.seed());
+        assert_eq!(restored.mode(), Mode::XoChip);
+        assert_eq!(*restored.quirks(), Quirks::MODERN);
+    }
+
+    #[test]
+    fn round_trip_chip_48() {
+        let mut cpu = Cpu::with_quirks(Quirks::CHIP_48);
+        // LD I, 0x300; LD [I], V1
+        cpu.load([0xA3, 0x00, 0xF1, 0x55]).unwrap();
+        cpu.step().unwrap();
+        let state = cpu.save_state();
+        let mut restored = Cpu::new();
+        restored.load_state(&state).unwrap();
+        assert_eq!(*restored.quirks(), Quirks::CHIP_48);
+        restored.step().unwrap();
+        assert_eq!(restored.i(), 0x301);
+        assert_eq!(restored.save_state()[6] & 0x42, 0x42);
+    }
+
+    #[test]
+    fn bad_quirks() {
+        for &bits in &[0x40, 0x80] {
+            let mut bad = running_cpu().save_state();
+            bad[6] = bits;
+            reseal(&mut bad);
+            assert_eq!(Cpu::new().load_state(&bad), Err(StateError::Invalid));
+        }
+    }
+
+    #[test]
+    fn round_trip_fault() {
+        let mut cpu = Cpu::new();
+        cpu.load([0x00, 0xEE]).unwrap();
+        assert!(cpu.step().is_err());
+        let mut restored = Cpu::new();
+        restored.load_state(&cpu.save_state()).unwrap();
+        assert_eq!(
+            *restored.state(),
+            CpuState::Fault(CpuError::StackUnderflow {
+                pc: 0x200,
+                opcode: 0x00EE
+            })
+        );
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let mut state = running_cpu().save_state();
+        state[10] ^= 1;
+        assert_eq!(Cpu::new().load_state(&state), Err(StateError::Checksum));
+    }
+
+    #[test]
+    fn truncated() {
+        let mut state = running_cpu().save_state();
+        assert_eq!(
+            Cpu::new().load_state(&state[..6]),
+            Err(StateError::Truncated)
+        );
+        // with a valid checksum
+        state.drain(state.len() - 100..state.len() - 4);
+        reseal(&mut state);
+        assert_eq!(Cpu::new().load_state(&state), Err(StateError::Truncated));
+    }
+
+    #[test]
+    fn bad_magic_and_version() {
+        let state = running_cpu().save_state();
+        let mut bad = state.clone();
+        bad[0] = b'X';
+        assert_eq!(Cpu::new().load_state(&bad), Err(StateError::BadMagic));
+        for &version in &[0, 2, 99] {
+            let mut bad = state.clone();
+            bad[4] = version;
+            reseal(&mut bad);
+            assert_eq!(
+                Cpu::new().load_state(&bad),
+                Err(StateError::UnsupportedVersion(version))
+            );
+        }
+    }
+
+    #[test]
+    fn failed_load_leaves_cpu_untouched() {
+        let mut cpu = running_cpu();
+        let before = cpu.save_state();
+        let mut bad = Cpu::new().save_state();
+        bad[5] = 7;
+        reseal(&mut bad);
+        assert_eq!(cpu.load_state(&bad), Err(StateError::Invalid));
+        assert_eq!(cpu.save_state(), before);
+    }
+}
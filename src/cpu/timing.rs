@@ -0,0 +1,139 @@
+use super::Opcode;
+
+/// Machine cycles per second of the COSMAC VIP (1.76 MHz clock, 8 clock
+/// periods per machine cycle).
+pub const VIP_HZ: u32 = 1_760_640 / 8;
+
+/// Timing model used to account for the cost of each instruction.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Timing {
+    /// Every instruction costs a single cycle, so a clock rate is a number of
+    /// instructions per second.
+    #[default]
+    Instructions,
+    /// Approximate machine cycle costs of the original COSMAC VIP
+    /// interpreter. `DRW` waits for the vertical blank regardless of
+    /// [`Quirks::display_wait`](super::Quirks::display_wait).
+    CosmacVip,
+}
+
+impl Timing {
+    /// Returns the cost of an instruction that has just run.
+    ///
+    /// `x` is the horizontal coordinate `DRW` drew the sprite at (sprites not
+    /// aligned to a byte boundary are shifted at runtime, doubling the cost
+    /// of every row), and `skipped` is true if a conditional skip was taken.
+    pub fn cycles(&self, opcode: &Opcode, x: u8, skipped: bool) -> u32 {
+        if *self == Timing::Instructions {
+            return 1;
+        }
+        // Fetch and decode overhead, common to every instruction.
+        const FETCH: u32 = 10;
+        let skip = if skipped { 4 } else { 0 };
+        FETCH
+            + match *opcode {
+                Opcode::CLS => 14 + 3064,
+                Opcode::RET => 13,
+                Opcode::JP_addr(_) => 12,
+                Opcode::CALL_addr(_) => 16,
+                Opcode::SE_Vx_byte(..) | Opcode::SNE_Vx_byte(..) => 8 + skip,
+                Opcode::SE_Vx_Vy(..) | Opcode::SNE_Vx_Vy(..) => 12 + skip,
+                Opcode::LD_Vx_byte(..) => 6,
+                Opcode::ADD_Vx_byte(..) => 10,
+                Opcode::LD_Vx_Vy(..)
+                | Opcode::OR_Vx_Vy(..)
+                | Opcode::AND_Vx_Vy(..)
+                | Opcode::XOR_Vx_Vy(..)
+                | Opcode::ADD_Vx_Vy(..)
+                | Opcode::SUB_Vx_Vy(..)
+                | Opcode::SHR_Vx_Vy(..)
+                | Opcode::SUBN_Vx_Vy(..)
+                | Opcode::SHL_Vx_Vy(..) => 34,
+                Opcode::LD_I_addr(_) => 2,
+                Opcode::JP_V0_addr(_) => 12,
+                Opcode::RND_Vx_byte(..) => 26,
+                Opcode::DRW_Vx_Vy_nibble(_, _, n) => {
+                    let rows = if n == 0 { 16 } else { u32::from(n) };
+                    let row = if x & 7 == 0 { 15 } else { 31 };
+                    26 + rows * row
+                }
+                Opcode::SKP_Vx(_) | Opcode::SKNP_Vx(_) => 8 + skip,
+                Opcode::LD_Vx_DT(_) | Opcode::LD_DT_Vx(_) | Opcode::LD_ST_Vx(_) => 6,
+                Opcode::LD_Vx_K(_) => 8,
+                Opcode::ADD_I_Vx(_) => 10,
+                Opcode::LD_F_Vx(_) | Opcode::LD_HF_Vx(_) => 10,
+                Opcode::LD_B_Vx(_) => 80,
+                Opcode::LD_I_Vx(x) | Opcode::LD_Vx_I(x) => 4 + 14 * (x as u32 + 1),
+                // not part of the VIP instruction set
+                _ => 10,
+            }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Timing, VIP_HZ};
+    use crate::{
+        clock::Clock,
+        cpu::{Cpu, Opcode},
+    };
+
+    #[test]
+    fn instructions() {
+        let timing = Timing::Instructions;
+        assert_eq!(timing.cycles(&Opcode::CLS, 0, false), 1);
+        assert_eq!(
+            timing.cycles(&Opcode::DRW_Vx_Vy_nibble(0, 0, 15), 3, false),
+            1
+        );
+        assert_eq!(timing.cycles(&Opcode::LD_I_Vx(15), 0, false), 1);
+    }
+
+    #[test]
+    fn cosmac_vip() {
+        let timing = Timing::CosmacVip;
+        assert_eq!(timing.cycles(&Opcode::JP_addr(0x200), 0, false), 22);
+        assert_eq!(timing.cycles(&Opcode::CLS, 0, false), 3088);
+        // taken skips cost more
+        assert_eq!(timing.cycles(&Opcode::SE_Vx_byte(0, 0), 0, false), 18);
+        assert_eq!(timing.cycles(&Opcode::SE_Vx_byte(0, 0), 0, true), 22);
+        // load/store scale with the number of registers
+        assert_eq!(timing.cycles(&Opcode::LD_I_Vx(0), 0, false), 28);
+        assert_eq!(timing.cycles(&Opcode::LD_Vx_I(15), 0, false), 238);
+        // unaligned sprites cost twice as much per row, and n = 0 draws 16 rows
+        let drw = |n, x| timing.cycles(&Opcode::DRW_Vx_Vy_nibble(0, 0, n), x, false);
+        assert_eq!(drw(5, 8), 36 + 5 * 15);
+        assert_eq!(drw(5, 9), 36 + 5 * 31);
+        assert_eq!(drw(0, 0), 36 + 16 * 15);
+    }
+
+    #[test]
+    fn draw_waits_for_vblank() {
+        let mut cpu = Cpu::new();
+        cpu.set_timing(Timing::CosmacVip);
+        // DRW V0, V0, 1; ADD V1, 1
+        cpu.load([0xD0, 0x01, 0x71, 0x01]).unwrap();
+        assert_eq!(cpu.step_cycles(), Ok(51));
+        assert_eq!(cpu.step_cycles(), Ok(0));
+        assert_eq!(cpu.program_counter(), 0x202);
+        cpu.update_timers();
+        assert_eq!(cpu.step_cycles(), Ok(20));
+        assert_eq!(cpu.registers()[1], 1);
+    }
+
+    #[test]
+    fn one_draw_per_frame() {
+        let mut cpu = Cpu::new();
+        cpu.set_timing(Timing::CosmacVip);
+        // DRW V0, V0, 1; JP 0x200
+        cpu.load([0xD0, 0x01, 0x12, 0x00]).unwrap();
+        let mut clock = Clock::new(VIP_HZ);
+        assert_eq!(clock.advance_frame(&mut cpu).unwrap().instructions, 1);
+        for _ in 0..10 {
+            let done = clock.advance_frame(&mut cpu).unwrap();
+            assert_eq!(done.instructions, 2);
+            assert_eq!(done.cycles, 51 + 22);
+        }
+    }
+}
@@ -0,0 +1,334 @@
+//! Disassembler.
+//!
+//! Code is told apart from data by following the control flow from one or
+//! more entry points (usually `0x200`). Every reachable instruction is
+//! decoded, and whatever is left is listed as `DB` data. Labels are
+//! synthesized for the targets of `CALL` (`sub_XXX`), `JP` (`label_XXX`) and
+//! `LD I` (`data_XXX`).
+//!
+//! The [`Display`](fmt::Display) implementation of [`Listing`] produces
+//! source that the assembler turns back into the same bytes, with the address
+//! and raw bytes of every line in a trailing comment:
+//!
+//! ```text
+//! sub_2A0:
+//!     LD V3, 0x0A              ; 2A0: 630A
+//!     RET                      ; 2A2: 00EE
+//! ```
+use crate::cpu::Opcode;
+use std::{collections::BTreeMap, convert::TryFrom, fmt};
+
+/// Maximum number of bytes in a `DB` line.
+const DATA_PER_LINE: usize = 8;
+
+/// Contents of a line of a [`Listing`].
+#[derive(Debug)]
+pub enum Item {
+    /// An instruction reachable from an entry point.
+    Code(Opcode),
+    /// Bytes that aren't reached by the control flow.
+    Data,
+}
+
+/// Line of a [`Listing`].
+#[derive(Debug)]
+pub struct Line {
+    /// Address of the first byte.
+    pub addr: usize,
+    /// Raw bytes, 4 for `LD I, LONG` and 2 for any other instruction.
+    pub bytes: Vec<u8>,
+    pub item: Item,
+}
+
+impl Line {
+    /// Return the raw bytes in hexadecimal, with instructions as words.
+    pub fn hex(&self) -> String {
+        let hex: Vec<_> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        match self.item {
+            Item::Code(_) => hex
+                .chunks(2)
+                .map(|w| w.concat())
+                .collect::<Vec<_>>()
+                .join(" "),
+            Item::Data => hex.join(" "),
+        }
+    }
+}
+
+/// Kind of a synthesized label, in increasing order of precedence.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+enum Label {
+    Data,
+    Jump,
+    Sub,
+}
+
+/// Disassembled program.
+#[derive(Debug)]
+pub struct Listing {
+    lines: Vec<Line>,
+    labels: BTreeMap<usize, String>,
+}
+
+impl Listing {
+    /// Return the lines, in increasing address order.
+    pub fn lines(&self) -> &[Line] {
+        &self.lines
+    }
+
+    /// Return the label at the given address, if any.
+    pub fn label(&self, addr: usize) -> Option<&str> {
+        self.labels.get(&addr).map(String::as_str)
+    }
+
+    /// Return the index of the line containing the given address.
+    pub fn line_index(&self, addr: usize) -> Option<usize> {
+        let index = match self.lines.binary_search_by_key(&addr, |line| line.addr) {
+            Ok(index) => index,
+            Err(index) => index.checked_sub(1)?,
+        };
+        let line = &self.lines[index];
+        if addr < line.addr + line.bytes.len() {
+            Some(index)
+        } else {
+            None
+        }
+    }
+
+    /// Return the assembler source of a line, with labels in place of the
+    /// addresses that have one.
+    pub fn text(&self, line: &Line) -> String {
+        let target = |addr: usize| match self.label(addr) {
+            Some(label) => label.to_string(),
+            None => format!("0x{:03X}", addr),
+        };
+        match line.item {
+            Item::Code(Opcode::JP_addr(nnn)) => format!("JP {}", target(nnn)),
+            Item::Code(Opcode::CALL_addr(nnn)) => format!("CALL {}", target(nnn)),
+            Item::Code(Opcode::LD_I_addr(nnn)) => format!("LD I, {}", target(nnn)),
+            Item::Code(Opcode::JP_V0_addr(nnn)) => format!("JP V0, {}", target(nnn)),
+            Item::Code(Opcode::LD_I_long) => {
+                let nnnn = usize::from(line.bytes[2]) << 8 | usize::from(line.bytes[3]);
+                match self.label(nnnn) {
+                    Some(label) => format!("LD I, LONG {}", label),
+                    None => format!("LD I, LONG 0x{:04X}", nnnn),
+                }
+            }
+            Item::Code(ref op) => op.to_string(),
+            Item::Data => {
+                let bytes: Vec<_> = line.bytes.iter().map(|b| format!("0x{:02X}", b)).collect();
+                format!("DB {}", bytes.join(", "))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in &self.lines {
+            if let Some(label) = self.label(line.addr) {
+                writeln!(f, "{}:", label)?;
+            }
+            writeln!(
+                f,
+                "    {:<24} ; {:03X}: {}",
+                self.text(line),
+                line.addr,
+                line.hex()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Listing of the memory of a running program, for debuggers that show it
+/// every frame.
+///
+/// Memory is only disassembled again when it changes, or when the PC leaves
+/// the code found so far (it is then added to the entry points).
+#[derive(Debug, Default)]
+pub struct Cache {
+    memory: Vec<u8>,
+    entries: Vec<usize>,
+    listing: Option<Listing>,
+}
+
+impl Cache {
+    /// Return the listing of `memory` (loaded at address 0), with `0x200` and
+    /// `pc` as entry points.
+    pub fn listing(&mut self, memory: &[u8], pc: usize) -> &Listing {
+        if self.memory != memory {
+            self.memory = memory.to_vec();
+            self.entries = vec![0x200];
+            self.listing = None;
+        }
+        let traced = match &self.listing {
+            Some(_) if self.entries.contains(&pc) => true,
+            Some(listing) => match listing.line_index(pc) {
+                Some(index) => {
+                    let line = &listing.lines[index];
+                    line.addr == pc && matches!(line.item, Item::Code(_))
+                }
+                None => false,
+            },
+            None => false,
+        };
+        if !traced {
+            if !self.entries.contains(&pc) {
+                self.entries.push(pc);
+            }
+            self.listing = Some(disassemble_from(&self.memory, 0, &self.entries));
+        }
+        self.listing.as_ref().unwrap()
+    }
+}
+
+/// Disassembles a program loaded at `origin`, following the control flow
+/// from its first instruction.
+pub fn disassemble(program: &[u8], origin: usize) -> Listing {
+    disassemble_from(program, origin, &[origin])
+}
+
+/// Disassembles a memory range starting at `origin`, following the control
+/// flow from the given entry points. Entry points outside of the range are
+/// ignored.
+pub fn disassemble_from(memory: &[u8], origin: usize, entries: &[usize]) -> Listing {
+    let end = origin + memory.len();
+    let word = |addr: usize| {
+        if addr >= origin && addr + 2 <= end {
+            let i = addr - origin;
+            Some(u16::from(memory[i]) << 8 | u16::from(memory[i + 1]))
+        } else {
+            None
+        }
+    };
+
+    // length of the instruction starting at each address, zero for data
+    let mut code = vec![0; memory.len()];
+    let mut covered = vec![false; memory.len()];
+    let mut labels = BTreeMap::new();
+    let mut label = |addr: usize, kind: Label| {
+        let entry = labels.entry(addr).or_insert(kind);
+        *entry = kind.max(*entry);
+    };
+    let mut pending = entries.to_vec();
+    while let Some(addr) = pending.pop() {
+        if addr < origin || addr >= end || code[addr - origin] != 0 {
+            continue;
+        }
+        let op = match word(addr).map(Opcode::try_from) {
+            Some(Ok(op)) => op,
+            _ => continue,
+        };
+        let len = if let Opcode::LD_I_long = op { 4 } else { 2 };
+        let range = addr - origin..addr - origin + len;
+        if addr + len > end || covered[range.clone()].iter().any(|c| *c) {
+            continue;
+        }
+        code[addr - origin] = len;
+        covered[range].iter_mut().for_each(|c| *c = true);
+
+        let next = addr + len;
+        // skips step over `LD I, LONG` as a whole
+        let skip = next + if word(next) == Some(0xF000) { 4 } else { 2 };
+        match op {
+            Opcode::JP_addr(nnn) => {
+                label(nnn, Label::Jump);
+                pending.push(nnn);
+            }
+            Opcode::CALL_addr(nnn) => {
+                label(nnn, Label::Sub);
+                pending.extend(&[next, nnn]);
+            }
+            // the target of `JP V0` is only known at runtime
+            Opcode::RET | Opcode::EXIT | Opcode::JP_V0_addr(_) => {}
+            Opcode::SE_Vx_byte(..)
+            | Opcode::SNE_Vx_byte(..)
+            | Opcode::SE_Vx_Vy(..)
+            | Opcode::SNE_Vx_Vy(..)
+            | Opcode::SKP_Vx(_)
+            | Opcode::SKNP_Vx(_) => pending.extend(&[skip, next]),
+            Opcode::LD_I_addr(nnn) => {
+                label(nnn, Label::Data);
+                pending.push(next);
+            }
+            Opcode::LD_I_long => {
+                if let Some(nnnn) = word(addr + 2) {
+                    label(usize::from(nnnn), Label::Data);
+                }
+                pending.push(next);
+            }
+            _ => pending.push(next),
+        }
+    }
+
+    let mut lines = Vec::new();
+    let mut i = 0;
+    while i < memory.len() {
+        let addr = origin + i;
+        let (len, item) = match code[i] {
+            0 => {
+                // data runs up to the next instruction or label
+                let len = (1..DATA_PER_LINE.min(memory.len() - i))
+                    .find(|&n| code[i + n] != 0 || labels.contains_key(&(addr + n)))
+                    .unwrap_or_else(|| DATA_PER_LINE.min(memory.len() - i));
+                (len, Item::Data)
+            }
+            len => {
+                let op = Opcode::try_from(word(addr).unwrap()).unwrap();
+                (len, Item::Code(op))
+            }
+        };
+        lines.push(Line {
+            addr,
+            bytes: memory[i..i + len].to_vec(),
+            item,
+        });
+        i += len;
+    }
+
+    // only keep labels that fall at the start of a line
+    let labels = labels
+        .into_iter()
+        .filter(|(addr, _)| lines.binary_search_by_key(addr, |line| line.addr).is_ok())
+        .map(|(addr, kind)| {
+            let name = match kind {
+                Label::Data => "data",
+                Label::Jump => "label",
+                Label::Sub => "sub",
+            };
+            (addr, format!("{}_{:03X}", name, addr))
+        })
+        .collect();
+    Listing { lines, labels }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cache, Item};
+
+    /// Return true if `pc` starts an instruction of the cached listing.
+    fn is_code(cache: &mut Cache, memory: &[u8], pc: usize) -> bool {
+        let listing = cache.listing(memory, pc);
+        let line = &listing.lines()[listing.line_index(pc).unwrap()];
+        line.addr == pc && matches!(line.item, Item::Code(_))
+    }
+
+    #[test]
+    fn cache() {
+        // CLS; JP 0x200; RET (unreachable)
+        let mut memory = vec![0; 0x1000];
+        memory[0x200..0x206].copy_from_slice(&[0x00, 0xE0, 0x12, 0x00, 0x00, 0xEE]);
+        let mut cache = Cache::default();
+        assert!(is_code(&mut cache, &memory, 0x202));
+        assert!(is_code(&mut cache, &memory, 0x200));
+        assert_eq!(cache.entries, [0x200, 0x202]);
+        // the PC leaves the code found so far
+        assert!(is_code(&mut cache, &memory, 0x204));
+        assert_eq!(cache.entries, [0x200, 0x202, 0x204]);
+        // the memory changes
+        memory[0x205] = 0xE0;
+        assert!(is_code(&mut cache, &memory, 0x202));
+        assert_eq!(cache.entries, [0x200, 0x202]);
+    }
+}
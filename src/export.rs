@@ -0,0 +1,263 @@
+//! Images of the display.
+//!
+//! The display is exported at a whole multiple of its current resolution,
+//! with each colour index (see [`Display::colors`]) mapped to a colour of a
+//! [`Style`]. Images can be written as PNG, binary PPM (full colour) or
+//! binary PBM (one bit per pixel, with every lit pixel in black).
+//!
+//! [`FrameDumper`] writes one numbered file per frame, to turn a run into an
+//! image sequence, and [`GifRecorder`] turns it into an animated GIF.
+use crate::cpu::Display;
+use std::{
+    ffi::OsString,
+    fmt, fs,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+mod gif;
+mod png;
+
+pub use gif::GifRecorder;
+
+/// RGB colour.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const BLACK: Rgb = Rgb::new(0x00, 0x00, 0x00);
+    pub const WHITE: Rgb = Rgb::new(0xFF, 0xFF, 0xFF);
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    /// Blends two colours, `t` ranging from 0 (`self`) to 255 (`other`).
+    fn mix(self, other: Rgb, t: u8) -> Rgb {
+        let mix = |a: u8, b: u8| {
+            let (a, b, t) = (u32::from(a), u32::from(b), u32::from(t));
+            ((a * (255 - t) + b * t + 127) / 255) as u8
+        };
+        Rgb::new(
+            mix(self.r, other.r),
+            mix(self.g, other.g),
+            mix(self.b, other.b),
+        )
+    }
+}
+
+impl FromStr for Rgb {
+    type Err = String;
+
+    /// Parses a colour in hexadecimal, as in `#FF8000` or `ff8000`.
+    fn from_str(color: &str) -> Result<Self, Self::Err> {
+        let hex = color.strip_prefix('#').unwrap_or(color);
+        match u32::from_str_radix(hex, 16) {
+            Ok(rgb) if hex.len() == 6 => {
+                Ok(Rgb::new((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
+            }
+            _ => Err(format!("invalid colour = {}", color)),
+        }
+    }
+}
+
+impl fmt::Display for Rgb {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{:02X}{:02X}{:02X}", self.r, self.g, self.b)
+    }
+}
+
+/// Appearance of an exported image.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Style {
+    /// Size of a display pixel, in image pixels.
+    pub scale: usize,
+    /// Colour of each colour index. Index 0 is the background and index 1
+    /// the foreground; the other two are only used by XO-CHIP programs.
+    pub colors: [Rgb; 4],
+}
+
+impl Default for Style {
+    /// White on black, at the original size.
+    fn default() -> Self {
+        Self::new(Rgb::WHITE, Rgb::BLACK)
+    }
+}
+
+impl Style {
+    /// Creates a style with the given colours, at the original size. The
+    /// colours of the second plane are blends of both.
+    pub fn new(foreground: Rgb, background: Rgb) -> Self {
+        Self {
+            scale: 1,
+            colors: [
+                background,
+                foreground,
+                background.mix(foreground, 0x80),
+                background.mix(foreground, 0xC0),
+            ],
+        }
+    }
+
+    /// Return the same style with a different scale.
+    pub fn with_scale(self, scale: usize) -> Self {
+        Self { scale, ..self }
+    }
+}
+
+/// Image file format.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Format {
+    Png,
+    Ppm,
+    Pbm,
+}
+
+impl Format {
+    /// Guesses the format from the extension of a path.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?;
+        extension.to_ascii_lowercase().parse().ok()
+    }
+
+    /// Return the usual file extension.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Png => "png",
+            Format::Ppm => "ppm",
+            Format::Pbm => "pbm",
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    /// Parses `png`, `ppm` or `pbm`.
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "png" => Ok(Format::Png),
+            "ppm" => Ok(Format::Ppm),
+            "pbm" => Ok(Format::Pbm),
+            _ => Err(format!("unknown image format = {}", format)),
+        }
+    }
+}
+
+/// Return the colour index of every pixel of the scaled image, in row-major
+/// order, along with its width and height.
+fn indices(display: &Display, scale: usize) -> (usize, usize, Vec<u8>) {
+    let scale = scale.max(1);
+    let (width, height) = (display.width() * scale, display.height() * scale);
+    let colors: Vec<_> = display.colors().collect();
+    let mut pixels = Vec::with_capacity(width * height);
+    for row in colors.chunks(display.width()) {
+        for _ in 0..scale {
+            for color in row {
+                pixels.extend((0..scale).map(|_| *color));
+            }
+        }
+    }
+    (width, height, pixels)
+}
+
+/// Writes an image of the display.
+pub fn write<W: Write>(
+    mut out: W,
+    display: &Display,
+    style: &Style,
+    format: Format,
+) -> io::Result<()> {
+    let (width, height, pixels) = indices(display, style.scale);
+    match format {
+        Format::Png | Format::Ppm => {
+            let mut rgb = Vec::with_capacity(pixels.len() * 3);
+            for index in pixels {
+                let color = style.colors[usize::from(index)];
+                rgb.extend_from_slice(&[color.r, color.g, color.b]);
+            }
+            if let Format::Png = format {
+                png::write(out, width, height, &rgb)
+            } else {
+                write!(out, "P6\n{} {}\n255\n", width, height)?;
+                out.write_all(&rgb)
+            }
+        }
+        Format::Pbm => {
+            write!(out, "P4\n{} {}\n", width, height)?;
+            // rows are padded to a whole number of bytes
+            for row in pixels.chunks(width) {
+                let bytes: Vec<u8> = row
+                    .chunks(8)
+                    .map(|bits| {
+                        bits.iter()
+                            .enumerate()
+                            .fold(0, |acc, (i, c)| acc | ((*c != 0) as u8) << (7 - i))
+                    })
+                    .collect();
+                out.write_all(&bytes)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Writes an image of the display to a file, in the format given by its
+/// extension.
+pub fn save<P: AsRef<Path>>(path: P, display: &Display, style: &Style) -> io::Result<()> {
+    let path = path.as_ref();
+    let format = Format::from_path(path).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "unknown image format (expected png, ppm or pbm)",
+        )
+    })?;
+    let mut out = BufWriter::new(fs::File::create(path)?);
+    write(&mut out, display, style, format)?;
+    out.flush()
+}
+
+/// Writes every frame to its own numbered file.
+///
+/// Files are named after a prefix, followed by the frame number (six digits,
+/// counting from zero) and the extension of the format, as in
+/// `dump/frame_000042.png` for the prefix `dump/frame_`.
+pub struct FrameDumper {
+    prefix: PathBuf,
+    format: Format,
+    style: Style,
+    frame: u64,
+}
+
+impl FrameDumper {
+    pub fn new<P: Into<PathBuf>>(prefix: P, format: Format, style: Style) -> Self {
+        Self {
+            prefix: prefix.into(),
+            format,
+            style,
+            frame: 0,
+        }
+    }
+
+    /// Return the number of frames written so far.
+    pub fn frames(&self) -> u64 {
+        self.frame
+    }
+
+    /// Writes the next frame, returning the path of the file.
+    pub fn dump(&mut self, display: &Display) -> io::Result<PathBuf> {
+        let mut path = OsString::from(self.prefix.as_os_str());
+        path.push(format!("{:06}.{}", self.frame, self.format.extension()));
+        let path = PathBuf::from(path);
+        let mut out = BufWriter::new(fs::File::create(&path)?);
+        write(&mut out, display, &self.style, self.format)?;
+        out.flush()?;
+        self.frame += 1;
+        Ok(path)
+    }
+}
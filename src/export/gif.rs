@@ -0,0 +1,334 @@
+//! Animated GIF encoder.
+use super::{indices, Style};
+use crate::{
+    clock::TIMER_HZ,
+    cpu::{Display, MAX_HEIGHT, MAX_WIDTH},
+};
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
+
+/// Shortest frame delay, in hundredths of a second. Browsers play frames
+/// with shorter delays much slower than requested.
+const MIN_DELAY: u64 = 2;
+
+/// Bits per colour index.
+const MIN_CODE_SIZE: u8 = 2;
+
+/// Records frames of the display into an animated GIF that loops forever.
+///
+/// Frames are meant to be captured at 60 Hz, once per timer update. Since
+/// GIF delays are measured in hundredths of a second, frames are kept at
+/// least 2/100 s on screen: a frame replaced sooner than that is dropped.
+/// Identical consecutive frames are merged into one with a longer delay.
+///
+/// Images always have the size of the high resolution mode times the scale
+/// of the style, so low resolution frames are drawn twice as large.
+pub struct GifRecorder<W: Write> {
+    out: W,
+    scale: usize,
+    /// Frame not written yet, since its delay is still growing.
+    pending: Option<Vec<u8>>,
+    /// Timer updates at which the pending frame started.
+    start: u64,
+    /// Timer updates captured so far.
+    ticks: u64,
+    frames: usize,
+}
+
+impl<W: Write> GifRecorder<W> {
+    /// Writes the header of the GIF, with a palette made of the colours of
+    /// the style.
+    pub fn new(mut out: W, style: &Style) -> io::Result<Self> {
+        let scale = style.scale.max(1);
+        let (width, height) = (MAX_WIDTH * scale, MAX_HEIGHT * scale);
+        if width > usize::from(u16::MAX) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "image too large for a GIF",
+            ));
+        }
+        out.write_all(b"GIF89a")?;
+        out.write_all(&(width as u16).to_le_bytes())?;
+        out.write_all(&(height as u16).to_le_bytes())?;
+        // global colour table of 4 entries, 2 bits per primary colour
+        out.write_all(&[0x91, 0, 0])?;
+        for color in style.colors.iter() {
+            out.write_all(&[color.r, color.g, color.b])?;
+        }
+        // loop forever
+        out.write_all(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00")?;
+        Ok(Self {
+            out,
+            scale,
+            pending: None,
+            start: 0,
+            ticks: 0,
+            frames: 0,
+        })
+    }
+
+    /// Return the number of frames written to the GIF so far.
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Captures a frame of the display.
+    pub fn frame(&mut self, display: &Display) -> io::Result<()> {
+        let scale = self.scale * MAX_WIDTH / display.width();
+        let (_, _, pixels) = indices(display, scale);
+        match &self.pending {
+            Some(pending) if *pending == pixels => {}
+            Some(_) if self.delay(self.ticks) < MIN_DELAY => self.pending = Some(pixels),
+            Some(_) => {
+                self.flush(self.ticks)?;
+                self.pending = Some(pixels);
+                self.start = self.ticks;
+            }
+            None => self.pending = Some(pixels),
+        }
+        self.ticks += 1;
+        Ok(())
+    }
+
+    /// Writes the last frame and the trailer of the GIF.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.flush(self.ticks)?;
+        self.out.write_all(&[0x3B])?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    /// Return the delay of the pending frame if it ended after `end` timer
+    /// updates, in hundredths of a second.
+    fn delay(&self, end: u64) -> u64 {
+        let hundredths = |ticks: u64| ticks * 100 / u64::from(TIMER_HZ);
+        hundredths(end) - hundredths(self.start)
+    }
+
+    /// Writes the pending frame, which ended after `end` timer updates.
+    fn flush(&mut self, end: u64) -> io::Result<()> {
+        let pixels = match self.pending.take() {
+            Some(pixels) => pixels,
+            None => return Ok(()),
+        };
+        let delay = self.delay(end).max(MIN_DELAY).min(u64::from(u16::MAX)) as u16;
+        let (width, height) = (
+            (MAX_WIDTH * self.scale) as u16,
+            (MAX_HEIGHT * self.scale) as u16,
+        );
+        // graphic control extension (no disposal) and image descriptor
+        self.out.write_all(&[0x21, 0xF9, 0x04, 0x04])?;
+        self.out.write_all(&delay.to_le_bytes())?;
+        self.out.write_all(&[0x00, 0x00, 0x2C, 0, 0, 0, 0])?;
+        self.out.write_all(&width.to_le_bytes())?;
+        self.out.write_all(&height.to_le_bytes())?;
+        self.out.write_all(&[0x00, MIN_CODE_SIZE])?;
+        for block in lzw(&pixels).chunks(255) {
+            self.out.write_all(&[block.len() as u8])?;
+            self.out.write_all(block)?;
+        }
+        self.out.write_all(&[0x00])?;
+        self.frames += 1;
+        Ok(())
+    }
+}
+
+/// Compresses colour indices with the variable code length LZW flavour used
+/// by GIF.
+fn lzw(pixels: &[u8]) -> Vec<u8> {
+    const MAX_CODE: u16 = 4095;
+    let clear = 1u16 << MIN_CODE_SIZE;
+    let end = clear + 1;
+
+    let mut out = Vec::new();
+    let (mut acc, mut bits) = (0u32, 0u8);
+    let mut emit = |code: u16, size: u8, out: &mut Vec<u8>| {
+        acc |= u32::from(code) << bits;
+        bits += size;
+        while bits >= 8 {
+            out.push(acc as u8);
+            acc >>= 8;
+            bits -= 8;
+        }
+    };
+
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = end + 1;
+    let mut size = MIN_CODE_SIZE + 1;
+    emit(clear, size, &mut out);
+    if let Some((first, rest)) = pixels.split_first() {
+        let mut prefix = u16::from(*first);
+        for pixel in rest {
+            if let Some(code) = table.get(&(prefix, *pixel)) {
+                prefix = *code;
+                continue;
+            }
+            emit(prefix, size, &mut out);
+            if next > MAX_CODE {
+                emit(clear, size, &mut out);
+                table.clear();
+                next = end + 1;
+                size = MIN_CODE_SIZE + 1;
+            } else {
+                table.insert((prefix, *pixel), next);
+                // the decoder widens codes as soon as the table reaches a
+                // power of two
+                if next == 1 << size && size < 12 {
+                    size += 1;
+                }
+                next += 1;
+            }
+            prefix = u16::from(*pixel);
+        }
+        emit(prefix, size, &mut out);
+    }
+    emit(end, size, &mut out);
+    if bits > 0 {
+        out.push(acc as u8);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lzw, GifRecorder, MIN_CODE_SIZE};
+    use crate::{
+        cpu::{Display, MAX_HEIGHT, MAX_WIDTH},
+        export::Style,
+    };
+
+    /// Decompresses a GIF LZW stream, and returns the colour indices and the
+    /// number of clear codes found.
+    fn unlzw(data: &[u8]) -> (Vec<u8>, usize) {
+        let clear = 1usize << MIN_CODE_SIZE;
+        let end = clear + 1;
+        let reset = || -> Vec<Vec<u8>> { (0..=end).map(|code| vec![code as u8]).collect() };
+        let (mut out, mut clears) = (Vec::new(), 0);
+        let mut table = reset();
+        let mut size = MIN_CODE_SIZE + 1;
+        let mut prev: Option<Vec<u8>> = None;
+        let mut pos = 0;
+        loop {
+            let code = (0..size).fold(0, |code, bit| {
+                let set = data[(pos + bit as usize) / 8] >> ((pos + bit as usize) % 8) & 1;
+                code | usize::from(set) << bit
+            });
+            pos += size as usize;
+            if code == clear {
+                table = reset();
+                size = MIN_CODE_SIZE + 1;
+                prev = None;
+                clears += 1;
+                continue;
+            }
+            if code == end {
+                break;
+            }
+            let entry = match (table.get(code), &prev) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(prev)) if code == table.len() => {
+                    let mut entry = prev.clone();
+                    entry.push(prev[0]);
+                    entry
+                }
+                _ => panic!("invalid code = {}", code),
+            };
+            out.extend_from_slice(&entry);
+            if let Some(mut prev) = prev.take() {
+                if table.len() < 4096 {
+                    prev.push(entry[0]);
+                    table.push(prev);
+                }
+            }
+            if table.len() == 1 << size && size < 12 {
+                size += 1;
+            }
+            prev = Some(entry);
+        }
+        (out, clears)
+    }
+
+    /// Return the delay of every frame of a GIF, after checking that each
+    /// frame decompresses to a full image.
+    fn delays(gif: &[u8]) -> Vec<u16> {
+        let mut delays = Vec::new();
+        // header, screen descriptor, palette and NETSCAPE extension
+        let mut pos = 6 + 7 + 12 + 19;
+        while gif[pos] == 0x21 {
+            delays.push(u16::from_le_bytes([gif[pos + 4], gif[pos + 5]]));
+            // graphic control extension and image descriptor
+            pos += 8 + 10;
+            assert_eq!(gif[pos], MIN_CODE_SIZE);
+            pos += 1;
+            let mut data = Vec::new();
+            while gif[pos] != 0 {
+                let len = usize::from(gif[pos]);
+                data.extend_from_slice(&gif[pos + 1..pos + 1 + len]);
+                pos += 1 + len;
+            }
+            pos += 1;
+            assert_eq!(unlzw(&data).0.len(), MAX_WIDTH * MAX_HEIGHT);
+        }
+        assert_eq!(&gif[pos..], [0x3B]);
+        delays
+    }
+
+    fn recorder() -> GifRecorder<Vec<u8>> {
+        GifRecorder::new(Vec::new(), &Style::default()).unwrap()
+    }
+
+    #[test]
+    fn lzw_round_trip() {
+        for pixels in &[vec![], vec![3], vec![1; 10_000], vec![0, 1, 2, 3, 2, 1]] {
+            assert_eq!(unlzw(&lzw(pixels)), (pixels.clone(), 1));
+        }
+    }
+
+    #[test]
+    fn lzw_table_reset() {
+        // noise fills the 4096 entry table several times
+        let mut state = 0x1234_5678u32;
+        let pixels: Vec<u8> = (0..100_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                (state >> 7) as u8 & 3
+            })
+            .collect();
+        let (decoded, clears) = unlzw(&lzw(&pixels));
+        assert!(clears > 2);
+        assert!(decoded == pixels);
+    }
+
+    #[test]
+    fn identical_frames_merged() {
+        let mut gif = recorder();
+        let display = Display::default();
+        for _ in 0..30 {
+            gif.frame(&display).unwrap();
+        }
+        assert_eq!(gif.frames(), 0);
+        let gif = gif.finish().unwrap();
+        // half a second
+        assert_eq!(delays(&gif), [50]);
+    }
+
+    #[test]
+    fn short_frames_dropped() {
+        let mut gif = recorder();
+        let mut display = Display::default();
+        // a new frame every timer update, shorter than MIN_DELAY
+        for _ in 0..30 {
+            display.draw(0, 0, &[0x80], false, false);
+            gif.frame(&display).unwrap();
+        }
+        let gif = gif.finish().unwrap();
+        let delays = delays(&gif);
+        assert!(delays.len() < 30);
+        assert!(delays.iter().all(|&delay| delay >= 2));
+        assert_eq!(delays.iter().sum::<u16>(), 50);
+    }
+}
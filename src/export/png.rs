@@ -0,0 +1,138 @@
+//! Minimal PNG encoder (8-bit RGB, uncompressed deflate blocks).
+use crate::cpu::savestate::crc32;
+use std::io::{self, Write};
+
+/// Longest stored deflate block.
+const BLOCK: usize = 0xFFFF;
+
+/// Writes an RGB image, three bytes per pixel in row-major order.
+pub(super) fn write<W: Write>(
+    mut out: W,
+    width: usize,
+    height: usize,
+    rgb: &[u8],
+) -> io::Result<()> {
+    assert_eq!(rgb.len(), width * height * 3);
+
+    // every scanline starts with filter type 0 (none)
+    let mut raw = Vec::with_capacity((width * 3 + 1) * height);
+    for row in rgb.chunks(width * 3) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(BLOCK).peekable();
+    while let Some(block) = blocks.next() {
+        let len = block.len() as u16;
+        zlib.push(blocks.peek().is_none() as u8);
+        zlib.extend_from_slice(&len.to_le_bytes());
+        zlib.extend_from_slice(&(!len).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+    // bit depth, colour type (RGB), compression, filter, interlace
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    out.write_all(b"\x89PNG\r\n\x1a\n")?;
+    chunk(&mut out, b"IHDR", &ihdr)?;
+    chunk(&mut out, b"IDAT", &zlib)?;
+    chunk(&mut out, b"IEND", &[])
+}
+
+fn chunk<W: Write>(out: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    out.write_all(&(data.len() as u32).to_be_bytes())?;
+    out.write_all(kind)?;
+    out.write_all(data)?;
+    let mut crc = kind.to_vec();
+    crc.extend_from_slice(data);
+    out.write_all(&crc32(&crc).to_be_bytes())
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in bytes {
+        a = (a + u32::from(*byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{adler32, crc32, write};
+
+    /// Decodes a PNG written by [`write`], checking every checksum, and
+    /// returns the header and the decompressed scanlines.
+    fn decode(png: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        let mut rest = &png[8..];
+        let (mut ihdr, mut idat, mut kinds) = (Vec::new(), Vec::new(), Vec::new());
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            let (kind, data) = (&rest[4..8], &rest[8..8 + len]);
+            let crc = &rest[8 + len..12 + len];
+            assert_eq!(crc, crc32(&rest[4..8 + len]).to_be_bytes());
+            match kind {
+                b"IHDR" => ihdr = data.to_vec(),
+                b"IDAT" => idat.extend_from_slice(data),
+                _ => {}
+            }
+            kinds.push(kind.to_vec());
+            rest = &rest[12 + len..];
+        }
+        assert_eq!(kinds, [&b"IHDR"[..], b"IDAT", b"IEND"]);
+
+        // zlib header, stored deflate blocks and adler-32 trailer
+        assert_eq!(&idat[..2], [0x78, 0x01]);
+        assert_eq!(u16::from_be_bytes([idat[0], idat[1]]) % 31, 0);
+        let (mut raw, mut rest) = (Vec::new(), &idat[2..]);
+        loop {
+            let last = rest[0] == 1;
+            let len = u16::from_le_bytes([rest[1], rest[2]]);
+            assert_eq!(!len, u16::from_le_bytes([rest[3], rest[4]]));
+            let len = usize::from(len);
+            raw.extend_from_slice(&rest[5..5 + len]);
+            rest = &rest[5 + len..];
+            if last {
+                break;
+            }
+        }
+        assert_eq!(rest, adler32(&raw).to_be_bytes());
+        (ihdr, raw)
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn small() {
+        let rgb = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+        let mut png = Vec::new();
+        write(&mut png, 2, 2, &rgb).unwrap();
+        let (ihdr, raw) = decode(&png);
+        assert_eq!(ihdr, [0, 0, 0, 2, 0, 0, 0, 2, 8, 2, 0, 0, 0]);
+        assert_eq!(raw, [0, 1, 2, 3, 4, 5, 6, 0, 7, 8, 9, 10, 11, 12]);
+    }
+
+    #[test]
+    fn several_blocks() {
+        // more than 64 KB of scanlines
+        let (width, height) = (256, 128);
+        let rgb: Vec<u8> = (0..width * height * 3).map(|i| i as u8).collect();
+        let mut png = Vec::new();
+        write(&mut png, width, height, &rgb).unwrap();
+        let (_, raw) = decode(&png);
+        assert_eq!(raw.len(), (width * 3 + 1) * height);
+        for (row, line) in raw.chunks(width * 3 + 1).enumerate() {
+            assert_eq!(line[0], 0);
+            assert_eq!(line[1..], rgb[row * width * 3..(row + 1) * width * 3]);
+        }
+    }
+}
@@ -3,4 +3,11 @@
 #![deny(unused_must_use)]
 #![deny(unused_variables)]
 #![deny(unused_mut)]
+pub mod asm;
+pub mod audio;
+pub mod clock;
 pub mod cpu;
+pub mod disasm;
+pub mod export;
+pub mod movie;
+pub mod rewind;
@@ -0,0 +1,447 @@
+//! Input movies.
+//!
+//! A [`Movie`] records the keys pressed and released while a program runs,
+//! along with everything else needed to replay them deterministically: a
+//! hash of the ROM, the mode, quirks and timing model of the cpu, the clock
+//! rate and the seed of the random number generator. Replaying a movie into
+//! a fresh cpu reproduces the original run exactly.
+//!
+//! Key events are applied at frame boundaries, right before the instructions
+//! of a frame (1/60 s of emulated time, see [`Clock::advance_frame`]) run.
+//! Both the [`Recorder`] and the [`Player`] drive the emulation frame by
+//! frame for that reason.
+//!
+//! # Format
+//!
+//! Multi-byte values are little endian.
+//!
+//! | Size  | Contents                                                      |
+//! |-------|---------------------------------------------------------------|
+//! | 4     | Magic header `CH8M`                                           |
+//! | 1     | Format version (currently 1)                                  |
+//! | 1     | Mode (0 = CHIP-8, 1 = SUPER-CHIP, 2 = XO-CHIP)                |
+//! | 1     | Quirks bitmask, as in [save states](crate::cpu::savestate)    |
+//! | 1     | Timing model (0 = instructions, 1 = COSMAC VIP)               |
+//! | 4     | Clock rate, in cycles per second                              |
+//! | 8     | Random number generator seed                                  |
+//! | 4     | CRC-32 of the ROM                                             |
+//! | 8     | Length, in frames                                             |
+//! | 4     | Number of events `n`                                          |
+//! | 9 * n | Events: frame (8 bytes), key (low nibble) and state (bit 4)   |
+//! | 4     | CRC-32 (IEEE) of every preceding byte                         |
+use crate::{
+    clock::{Clock, Elapsed},
+    cpu::{
+        savestate::{
+            crc32, mode_from_byte, mode_to_byte, quirks_from_bits, quirks_to_bits,
+            timing_from_byte, timing_to_byte, Reader, StateError,
+        },
+        Cpu, CpuError, KeyState, Mode, Quirks, Timing, XorShift,
+    },
+};
+use std::{error::Error, fmt};
+
+const MAGIC: &[u8; 4] = b"CH8M";
+const VERSION: u8 = 1;
+
+/// Errors raised when decoding or starting a movie.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum MovieError {
+    /// The data doesn't start with the magic header.
+    BadMagic,
+    /// The format version is not supported.
+    UnsupportedVersion(u8),
+    /// The checksum doesn't match the contents.
+    Checksum,
+    /// The data ended unexpectedly.
+    Truncated,
+    /// The data contains a value out of range.
+    Invalid,
+    /// The movie was recorded with a different ROM.
+    RomMismatch,
+    /// The ROM doesn't fit in the memory of the mode the movie was recorded
+    /// in.
+    Load(CpuError),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MovieError::BadMagic => write!(f, "not a movie"),
+            MovieError::UnsupportedVersion(v) => write!(f, "unsupported movie version = {}", v),
+            MovieError::Checksum => write!(f, "movie checksum mismatch"),
+            MovieError::Truncated => write!(f, "truncated movie"),
+            MovieError::Invalid => write!(f, "invalid movie"),
+            MovieError::RomMismatch => write!(f, "movie was recorded with a different ROM"),
+            MovieError::Load(err) => write!(f, "failed to load ROM = {}", err),
+        }
+    }
+}
+
+impl Error for MovieError {}
+
+impl From<StateError> for MovieError {
+    fn from(err: StateError) -> Self {
+        match err {
+            StateError::BadMagic => MovieError::BadMagic,
+            StateError::UnsupportedVersion(v) => MovieError::UnsupportedVersion(v),
+            StateError::Checksum => MovieError::Checksum,
+            StateError::Truncated => MovieError::Truncated,
+            StateError::Invalid => MovieError::Invalid,
+        }
+    }
+}
+
+/// Key press or release.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct Event {
+    /// Frame the event is applied at, counting from zero.
+    pub frame: u64,
+    pub key: u8,
+    pub state: KeyState,
+}
+
+/// Return the hash identifying a ROM (its CRC-32).
+pub fn rom_hash(rom: &[u8]) -> u32 {
+    crc32(rom)
+}
+
+/// Recorded input, and the settings needed to replay it.
+#[derive(Clone)]
+pub struct Movie {
+    mode: Mode,
+    quirks: Quirks,
+    timing: Timing,
+    hz: u32,
+    seed: u64,
+    rom_hash: u32,
+    frames: u64,
+    events: Vec<Event>,
+}
+
+impl Movie {
+    /// Creates an empty movie for the given ROM, taking the mode, quirks,
+    /// timing model and random seed from `cpu`, and running `hz` cycles per
+    /// second.
+    pub fn new(rom: &[u8], cpu: &Cpu, hz: u32) -> Self {
+        Self {
+            mode: cpu.mode(),
+            quirks: *cpu.quirks(),
+            timing: cpu.timing(),
+            hz,
+            seed: cpu.seed(),
+            rom_hash: rom_hash(rom),
+            frames: 0,
+            events: Vec::new(),
+        }
+    }
+
+    /// Return the seed of the random number generator.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Return the CRC-32 of the ROM the movie was recorded with.
+    pub fn rom_hash(&self) -> u32 {
+        self.rom_hash
+    }
+
+    /// Return the number of recorded frames.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Return the key events, in the order they were recorded.
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    /// Creates a cpu and a clock set up like the ones the movie was recorded
+    /// with, with the ROM loaded.
+    pub fn start(&self, rom: &[u8]) -> Result<(Cpu, Clock), MovieError> {
+        if rom_hash(rom) != self.rom_hash {
+            return Err(MovieError::RomMismatch);
+        }
+        let mut cpu = Cpu::with_mode(self.mode, self.quirks);
+        cpu.set_rng(Box::new(XorShift::new(self.seed)));
+        cpu.set_timing(self.timing);
+        cpu.load(rom).map_err(MovieError::Load)?;
+        Ok((cpu, Clock::new(self.hz)))
+    }
+
+    /// Encodes the movie in the format described in the [module
+    /// documentation](self).
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(40 + 9 * self.events.len());
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        out.push(mode_to_byte(self.mode));
+        out.push(quirks_to_bits(&self.quirks));
+        out.push(timing_to_byte(self.timing));
+        out.extend_from_slice(&self.hz.to_le_bytes());
+        out.extend_from_slice(&self.seed.to_le_bytes());
+        out.extend_from_slice(&self.rom_hash.to_le_bytes());
+        out.extend_from_slice(&self.frames.to_le_bytes());
+        out.extend_from_slice(&(self.events.len() as u32).to_le_bytes());
+        for event in &self.events {
+            out.extend_from_slice(&event.frame.to_le_bytes());
+            out.push(event.key | (event.state as u8) << 4);
+        }
+        let crc = crc32(&out);
+        out.extend_from_slice(&crc.to_le_bytes());
+        out
+    }
+
+    /// Decodes a movie encoded with [`Movie::to_bytes`].
+    pub fn from_bytes(data: &[u8]) -> Result<Self, MovieError> {
+        if data.len() < MAGIC.len() + 1 + 4 {
+            return Err(MovieError::Truncated);
+        }
+        if &data[..4] != MAGIC {
+            return Err(MovieError::BadMagic);
+        }
+        if data[4] != VERSION {
+            return Err(MovieError::UnsupportedVersion(data[4]));
+        }
+        let (body, crc) = data.split_at(data.len() - 4);
+        if crc32(body).to_le_bytes() != crc {
+            return Err(MovieError::Checksum);
+        }
+
+        let mut r = Reader(&body[5..]);
+        let mode = mode_from_byte(r.u8()?)?;
+        let quirks = quirks_from_bits(r.u8()?)?;
+        let timing = timing_from_byte(r.u8()?)?;
+        let hz = r.u32()?;
+        let seed = r.u64()?;
+        let rom_hash = r.u32()?;
+        let frames = r.u64()?;
+        let len = r.u32()? as usize;
+        if len.checked_mul(9) != Some(r.0.len()) {
+            return Err(MovieError::Invalid);
+        }
+        let mut events: Vec<Event> = Vec::with_capacity(len);
+        for _ in 0..len {
+            let frame = r.u64()?;
+            let byte = r.u8()?;
+            let state = match byte >> 4 {
+                0 => KeyState::Up,
+                1 => KeyState::Down,
+                _ => return Err(MovieError::Invalid),
+            };
+            if frame >= frames || matches!(events.last(), Some(e) if e.frame > frame) {
+                return Err(MovieError::Invalid);
+            }
+            events.push(Event {
+                frame,
+                key: byte & 0xF,
+                state,
+            });
+        }
+        Ok(Self {
+            mode,
+            quirks,
+            timing,
+            hz,
+            seed,
+            rom_hash,
+            frames,
+            events,
+        })
+    }
+}
+
+/// Records the input of a running program into a [`Movie`].
+pub struct Recorder {
+    movie: Movie,
+    pending: Vec<(u8, KeyState)>,
+}
+
+impl Recorder {
+    /// Starts recording a movie. The cpu and clock returned by
+    /// [`Movie::start`] must be used for the rest of the recording.
+    pub fn new(movie: Movie) -> Self {
+        Self {
+            movie,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Return the number of frames recorded so far.
+    pub fn frames(&self) -> u64 {
+        self.movie.frames
+    }
+
+    /// Presses or releases a key. The event is recorded and forwarded to the
+    /// cpu at the start of the next frame.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key is not lower than 16.
+    pub fn set_key(&mut self, key: usize, state: KeyState) {
+        assert!(key < 16, "invalid key = {}", key);
+        self.pending.push((key as u8, state));
+    }
+
+    /// Applies the pending key events and runs a frame.
+    pub fn advance_frame(&mut self, cpu: &mut Cpu, clock: &mut Clock) -> Result<Elapsed, CpuError> {
+        let frame = self.movie.frames;
+        for (key, state) in self.pending.drain(..) {
+            cpu.set_key(usize::from(key), state);
+            self.movie.events.push(Event { frame, key, state });
+        }
+        self.movie.frames += 1;
+        clock.advance_frame(cpu)
+    }
+
+    /// Stops recording. Key events still pending are dropped.
+    pub fn finish(self) -> Movie {
+        self.movie
+    }
+}
+
+/// Replays the input of a [`Movie`].
+pub struct Player {
+    movie: Movie,
+    frame: u64,
+    next: usize,
+}
+
+impl Player {
+    /// Starts playing a movie back. The cpu and clock must be the ones
+    /// returned by [`Movie::start`].
+    pub fn new(movie: Movie) -> Self {
+        Self {
+            movie,
+            frame: 0,
+            next: 0,
+        }
+    }
+
+    /// Return the movie being played.
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    /// Return the number of frames played so far.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Return true once every recorded frame has been played.
+    pub fn is_finished(&self) -> bool {
+        self.frame >= self.movie.frames
+    }
+
+    /// Applies the key events recorded for the current frame and runs it.
+    /// Frames past the end of the movie run without input.
+    pub fn advance_frame(&mut self, cpu: &mut Cpu, clock: &mut Clock) -> Result<Elapsed, CpuError> {
+        let events = &self.movie.events[self.next..];
+        let count = events
+            .iter()
+            .take_while(|event| event.frame == self.frame)
+            .count();
+        for event in &events[..count] {
+            cpu.set_key(usize::from(event.key), event.state);
+        }
+        self.next += count;
+        self.frame += 1;
+        clock.advance_frame(cpu)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Movie, MovieError, Player, Recorder};
+    use crate::cpu::savestate::crc32;
+    use crate::cpu::{Cpu, KeyState};
+
+    // LD V2, K; RND V1, 0xFF; ADD V3, V1; ADD V3, V2; JP 0x200
+    const ROM: [u8; 10] = [0xF2, 0x0A, 0xC1, 0xFF, 0x83, 0x14, 0x83, 0x24, 0x12, 0x00];
+
+    /// Records a few key presses, and returns the movie and the final state.
+    fn record() -> (Movie, Vec<u8>) {
+        let movie = Movie::new(&ROM, &Cpu::with_seed(99), 600);
+        let (mut cpu, mut clock) = movie.start(&ROM).unwrap();
+        let mut recorder = Recorder::new(movie);
+        for frame in 0..40 {
+            match frame {
+                3 => recorder.set_key(5, KeyState::Down),
+                5 => recorder.set_key(5, KeyState::Up),
+                10 => {
+                    recorder.set_key(0xA, KeyState::Down);
+                    recorder.set_key(1, KeyState::Down);
+                }
+                20 => recorder.set_key(0xA, KeyState::Up),
+                _ => {}
+            }
+            recorder.advance_frame(&mut cpu, &mut clock).unwrap();
+        }
+        assert_ne!(cpu.registers()[3], 0);
+        (recorder.finish(), cpu.save_state())
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let (movie, _) = record();
+        let bytes = movie.to_bytes();
+        let decoded = Movie::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.to_bytes(), bytes);
+        assert_eq!(decoded.frames(), 40);
+        assert_eq!(decoded.seed(), 99);
+        assert!(decoded.events() == movie.events());
+        assert_eq!(decoded.events().len(), 5);
+    }
+
+    #[test]
+    fn bad_bytes() {
+        let (movie, _) = record();
+        let bytes = movie.to_bytes();
+        assert_eq!(
+            Movie::from_bytes(&bytes[..8]).err(),
+            Some(MovieError::Truncated)
+        );
+        let mut bad = bytes.clone();
+        bad[0] = b'X';
+        assert_eq!(Movie::from_bytes(&bad).err(), Some(MovieError::BadMagic));
+        let mut bad = bytes.clone();
+        bad[4] = 2;
+        assert_eq!(
+            Movie::from_bytes(&bad).err(),
+            Some(MovieError::UnsupportedVersion(2))
+        );
+        let mut bad = bytes;
+        bad[20] ^= 1;
+        assert_eq!(Movie::from_bytes(&bad).err(), Some(MovieError::Checksum));
+    }
+
+    #[test]
+    fn huge_event_count() {
+        let (movie, _) = record();
+        let mut bad = movie.to_bytes();
+        bad.truncate(bad.len() - 4);
+        bad[32..36].copy_from_slice(&u32::MAX.to_le_bytes());
+        let crc = crc32(&bad);
+        bad.extend_from_slice(&crc.to_le_bytes());
+        assert_eq!(Movie::from_bytes(&bad).err(), Some(MovieError::Invalid));
+    }
+
+    #[test]
+    fn replay() {
+        let (movie, state) = record();
+        let movie = Movie::from_bytes(&movie.to_bytes()).unwrap();
+        let (mut cpu, mut clock) = movie.start(&ROM).unwrap();
+        let mut player = Player::new(movie);
+        while !player.is_finished() {
+            player.advance_frame(&mut cpu, &mut clock).unwrap();
+        }
+        assert_eq!(player.frame(), 40);
+        assert_eq!(cpu.save_state(), state);
+    }
+
+    #[test]
+    fn wrong_rom() {
+        let (movie, _) = record();
+        assert_eq!(movie.start(&ROM[..8]).err(), Some(MovieError::RomMismatch));
+    }
+}
@@ -0,0 +1,236 @@
+//! Rewind buffer built on periodic save states.
+//!
+//! Only the most recent snapshot is kept in full. Every older snapshot is
+//! stored as a delta against the next (newer) one, so stepping backwards only
+//! has to undo one delta at a time. Since most frames only touch a handful of
+//! bytes of memory and display, deltas are usually tiny.
+use crate::cpu::Cpu;
+use std::collections::VecDeque;
+
+/// Ring buffer of cpu snapshots.
+pub struct Rewind {
+    interval: usize,
+    capacity: usize,
+    frames: usize,
+    latest: Option<Vec<u8>>,
+    history: VecDeque<Delta>,
+}
+
+/// Changes needed to turn a snapshot into the one recorded before it.
+enum Delta {
+    /// Runs of bytes to XOR into the newer snapshot.
+    Xor(Vec<u8>),
+    /// The snapshots have different sizes (the mode changed), so the older
+    /// one is stored in full.
+    Full(Vec<u8>),
+}
+
+impl Rewind {
+    /// Creates a buffer recording a snapshot every `interval` frames and
+    /// holding up to `capacity` of them.
+    pub fn new(interval: usize, capacity: usize) -> Self {
+        Self {
+            interval: interval.max(1),
+            capacity: capacity.max(1),
+            frames: 0,
+            latest: None,
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Return the number of recorded snapshots.
+    pub fn len(&self) -> usize {
+        self.latest.iter().count() + self.history.len()
+    }
+
+    /// Return true if nothing has been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    /// Discards every snapshot.
+    pub fn clear(&mut self) {
+        self.frames = 0;
+        self.latest = None;
+        self.history.clear();
+    }
+
+    /// Call once per frame. Records a snapshot of the cpu every `interval`
+    /// frames.
+    pub fn record(&mut self, cpu: &Cpu) {
+        self.frames += 1;
+        if self.frames < self.interval {
+            return;
+        }
+        self.frames = 0;
+        let state = cpu.save_state();
+        if let Some(latest) = self.latest.take() {
+            self.history.push_back(Delta::encode(&state, latest));
+            if self.history.len() >= self.capacity {
+                self.history.pop_front();
+            }
+        }
+        self.latest = Some(state);
+    }
+
+    /// Restores the most recent snapshot and discards it, so the next call
+    /// goes further back in time. The oldest snapshot is never discarded.
+    ///
+    /// Returns false if there was nothing to restore.
+    pub fn rewind(&mut self, cpu: &mut Cpu) -> bool {
+        let latest = match &self.latest {
+            Some(latest) => latest,
+            None => return false,
+        };
+        if cpu.load_state(latest).is_err() {
+            // snapshots come from save_state, so this shouldn't happen
+            self.clear();
+            return false;
+        }
+        self.frames = 0;
+        if let Some(delta) = self.history.pop_back() {
+            let latest = self.latest.take().unwrap();
+            self.latest = Some(delta.apply(latest));
+        }
+        true
+    }
+}
+
+impl Delta {
+    /// Encodes `old` against `new` as a sequence of `(skip, len, bytes)`
+    /// records, with `skip` and `len` as LEB128 varints and `bytes` being the
+    /// XOR of both snapshots.
+    fn encode(new: &[u8], old: Vec<u8>) -> Self {
+        if new.len() != old.len() {
+            return Delta::Full(old);
+        }
+        let mut out = Vec::new();
+        let mut pos = 0;
+        while pos < new.len() {
+            let start = match (pos..new.len()).find(|&i| new[i] != old[i]) {
+                Some(start) => start,
+                None => break,
+            };
+            let end = (start..new.len())
+                .find(|&i| new[i] == old[i])
+                .unwrap_or(new.len());
+            write_varint(&mut out, start - pos);
+            write_varint(&mut out, end - start);
+            out.extend((start..end).map(|i| new[i] ^ old[i]));
+            pos = end;
+        }
+        Delta::Xor(out)
+    }
+
+    /// Turns the newer snapshot into the older one.
+    fn apply(self, mut new: Vec<u8>) -> Vec<u8> {
+        match self {
+            Delta::Full(old) => old,
+            Delta::Xor(delta) => {
+                let mut delta = &delta[..];
+                let mut pos = 0;
+                while !delta.is_empty() {
+                    pos += read_varint(&mut delta);
+                    let len = read_varint(&mut delta);
+                    for (byte, xor) in new[pos..pos + len].iter_mut().zip(&delta[..len]) {
+                        *byte ^= xor;
+                    }
+                    delta = &delta[len..];
+                    pos += len;
+                }
+                new
+            }
+        }
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &mut &[u8]) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[0];
+        *data = &data[1..];
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Delta, Rewind};
+    use crate::cpu::Cpu;
+
+    /// Return a cpu counting up in V0 forever, storing the count at 0xE00.
+    fn counter() -> Cpu {
+        let mut cpu = Cpu::new();
+        // LD I, 0xE00; ADD V0, 1; LD [I], V0; JP 0x202
+        cpu.load([0xAE, 0x00, 0x70, 0x01, 0xF0, 0x55, 0x12, 0x02])
+            .unwrap();
+        cpu
+    }
+
+    #[test]
+    fn delta() {
+        let mut cpu = counter();
+        let old = cpu.save_state();
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+        let new = cpu.save_state();
+        assert_ne!(old, new);
+        match Delta::encode(&new, old.clone()) {
+            Delta::Xor(delta) => {
+                assert!(delta.len() < 32);
+                assert_eq!(Delta::Xor(delta).apply(new), old);
+            }
+            Delta::Full(_) => panic!("same sized snapshots should be XORed"),
+        }
+    }
+
+    #[test]
+    fn delta_resized() {
+        let old = counter().save_state();
+        let new = old[..old.len() - 1].to_vec();
+        assert!(matches!(Delta::encode(&new, old.clone()), Delta::Full(_)));
+        assert_eq!(Delta::encode(&new, old.clone()).apply(new), old);
+    }
+
+    #[test]
+    fn rewind_past_capacity() {
+        let mut cpu = counter();
+        let mut rewind = Rewind::new(1, 3);
+        let mut states = Vec::new();
+        for _ in 0..5 {
+            cpu.step().unwrap();
+            rewind.record(&cpu);
+            states.push(cpu.save_state());
+        }
+        assert_eq!(rewind.len(), 3);
+        for state in states[2..].iter().rev() {
+            assert!(rewind.rewind(&mut cpu));
+            assert_eq!(cpu.save_state(), *state);
+        }
+        // the oldest snapshot is kept
+        cpu.step().unwrap();
+        assert!(rewind.rewind(&mut cpu));
+        assert_eq!(cpu.save_state(), states[2]);
+        assert_eq!(rewind.len(), 1);
+    }
+
+    #[test]
+    fn rewind_empty() {
+        let mut cpu = counter();
+        assert!(!Rewind::new(1, 3).rewind(&mut cpu));
+    }
+}
@@ -1,4 +1,10 @@
-use chip8::cpu::PixelState;
+use chip8::{
+    audio::{Generator, Waveform},
+    clock::Clock,
+    cpu::{Access, Breakpoint, Cmp, CpuState, KeyState, Opcode, PixelState},
+    disasm,
+};
+use std::{convert::TryFrom, fmt::Write, time::Duration};
 use wasm_bindgen::prelude::*;
 
 #[global_allocator]
@@ -7,13 +13,37 @@ static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 const DEMO: &[u8] = include_bytes!("../../roms/Trip8 Demo (2008) [Revival Studios].ch8");
 const PONG: &[u8] = include_bytes!("../../roms/Pong (1 player).ch8");
 
+/// Number of lines of disassembly shown before and after the PC.
+const DISASM_CONTEXT: usize = 16;
+
+/// Instructions per second.
+const CLOCK_HZ: u32 = 500;
+
 #[wasm_bindgen]
-pub struct Cpu(chip8::cpu::Cpu);
+pub struct Cpu(chip8::cpu::Cpu, Clock, disasm::Cache);
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 #[wasm_bindgen]
 impl Cpu {
     pub fn new() -> Self {
-        Self(chip8::cpu::Cpu::new())
+        Self(
+            chip8::cpu::Cpu::new(),
+            Clock::new(CLOCK_HZ),
+            disasm::Cache::default(),
+        )
+    }
+
+    pub fn with_seed(seed: u32) -> Self {
+        Self(
+            chip8::cpu::Cpu::with_seed(seed.into()),
+            Clock::new(CLOCK_HZ),
+            disasm::Cache::default(),
+        )
     }
 
     pub fn update_timers(&mut self) {
@@ -28,20 +58,108 @@ impl Cpu {
         self.0.display().as_ptr()
     }
 
+    pub fn display_plane(&self, plane: usize) -> *const PixelState {
+        self.0.display().plane(plane).as_ptr()
+    }
+
+    pub fn display_width(&self) -> usize {
+        self.0.display().width()
+    }
+
+    pub fn display_height(&self) -> usize {
+        self.0.display().height()
+    }
+
+    /// Loads a program and starts running it. The program must fit in memory
+    /// from `0x200` onwards.
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), JsValue> {
+        if rom.is_empty() {
+            return Err(JsValue::from_str("ROM is empty"));
+        }
+        self.0
+            .load(rom)
+            .map_err(|err| JsValue::from_str(&err.to_string()))
+    }
+
     pub fn load_demo(&mut self) {
-        self.0.load(DEMO)
+        self.0.load(DEMO).expect("bundled ROM fits in memory")
     }
 
     pub fn load_pong(&mut self) {
-        self.0.load(PONG)
+        self.0.load(PONG).expect("bundled ROM fits in memory")
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        self.0.save_state()
+    }
+
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), JsValue> {
+        self.0
+            .load_state(state)
+            .map_err(|err| JsValue::from_str(&err.to_string()))
+    }
+
+    /// Presses (`down`) or releases a key of the keypad. Keys past `0xF`
+    /// are ignored.
+    pub fn set_key(&mut self, key: usize, down: bool) {
+        if key < 16 {
+            let state = if down { KeyState::Down } else { KeyState::Up };
+            self.0.set_key(key, state);
+        }
     }
 
     pub fn halt(&mut self) {
         self.0.halt()
     }
 
-    pub fn step(&mut self) {
-        self.0.step()
+    /// Runs the instructions and timer updates that fit in the given wall
+    /// time, in milliseconds.
+    pub fn advance(&mut self, elapsed_ms: f64) -> Result<(), JsValue> {
+        let elapsed = Duration::from_micros((elapsed_ms.max(0.0) * 1000.0) as u64);
+        self.1
+            .advance(&mut self.0, elapsed)
+            .map(|_| ())
+            .map_err(|err| JsValue::from_str(&err.to_string()))
+    }
+
+    /// Starts rendering sound, at `rate` samples per second. Until then,
+    /// no samples are rendered.
+    pub fn set_sample_rate(&mut self, rate: u32) {
+        self.1.set_audio(Some(Generator::new(rate)));
+    }
+
+    /// Return the samples rendered since the last call, as the emulation
+    /// advances.
+    pub fn take_audio_samples(&mut self) -> Vec<f32> {
+        self.1
+            .audio_mut()
+            .map(|audio| audio.take_samples())
+            .unwrap_or_default()
+    }
+
+    /// Sets the waveform of the beep (`square`, `sine`, `triangle` or
+    /// `noise`).
+    pub fn set_waveform(&mut self, waveform: &str) -> Result<(), JsValue> {
+        let waveform = waveform
+            .parse::<Waveform>()
+            .map_err(|err| JsValue::from_str(&err))?;
+        if let Some(audio) = self.1.audio_mut() {
+            audio.set_waveform(waveform);
+        }
+        Ok(())
+    }
+
+    /// Sets the volume, from 0 to 1.
+    pub fn set_volume(&mut self, volume: f32) {
+        if let Some(audio) = self.1.audio_mut() {
+            audio.set_volume(volume);
+        }
+    }
+
+    pub fn step(&mut self) -> Result<(), JsValue> {
+        self.0
+            .step()
+            .map_err(|err| JsValue::from_str(&err.to_string()))
     }
 
     pub fn program_counter(&self) -> usize {
@@ -63,4 +181,168 @@ impl Cpu {
     pub fn i(&self) -> u16 {
         self.0.i()
     }
+
+    pub fn registers(&self) -> Vec<u8> {
+        self.0.registers().to_vec()
+    }
+
+    pub fn stack(&self) -> Vec<u16> {
+        self.0.stack().to_vec()
+    }
+
+    pub fn memory(&self) -> *const u8 {
+        self.0.memory().as_ptr()
+    }
+
+    pub fn memory_size(&self) -> usize {
+        self.0.memory().len()
+    }
+
+    /// Return a description of the state of the cpu.
+    pub fn state(&self) -> String {
+        match self.0.state() {
+            CpuState::Halt => "Halted".to_string(),
+            CpuState::Running => "Running".to_string(),
+            CpuState::WaitInput(register) => format!("Waiting for a key (V{:X})", register),
+            CpuState::Fault(err) => format!("Fault: {}", err),
+            CpuState::Break(trigger) => match trigger.addr {
+                Some(addr) => format!(
+                    "Breakpoint #{} at {:03X} (address {:03X})",
+                    trigger.id, trigger.pc, addr
+                ),
+                None => format!("Breakpoint #{} at {:03X}", trigger.id, trigger.pc),
+            },
+        }
+    }
+
+    /// Return true if the cpu is stopped at a breakpoint.
+    pub fn is_break(&self) -> bool {
+        matches!(self.0.state(), CpuState::Break(_))
+    }
+
+    /// Return the instruction at the PC, without running it.
+    pub fn fetch(&self) -> Result<String, JsValue> {
+        self.0
+            .fetch()
+            .map(|op| op.to_string())
+            .map_err(|err| JsValue::from_str(&err.to_string()))
+    }
+
+    /// Runs the instruction at the PC, even if the cpu is halted or stopped
+    /// at a breakpoint.
+    pub fn fetch_execute(&mut self) -> Result<(), JsValue> {
+        self.0
+            .fetch_execute()
+            .map_err(|err| JsValue::from_str(&err.to_string()))
+    }
+
+    /// Resumes execution after a breakpoint.
+    pub fn resume(&mut self) {
+        self.0.resume()
+    }
+
+    /// Return the disassembly of the code around the PC, one instruction per
+    /// line. The line of the PC starts with `>`. Memory is only disassembled
+    /// again when it changes.
+    pub fn disassemble(&mut self) -> String {
+        let pc = self.0.program_counter();
+        let listing = self.2.listing(self.0.memory(), pc);
+        let lines = listing.lines();
+        let center = listing.line_index(pc).unwrap_or(0);
+        let start = center.saturating_sub(DISASM_CONTEXT);
+        let end = (center + DISASM_CONTEXT + 1).min(lines.len());
+        let mut text = String::new();
+        for line in &lines[start..end] {
+            if let Some(label) = listing.label(line.addr) {
+                let _ = writeln!(text, "  {}:", label);
+            }
+            let marker = if line.addr == pc { '>' } else { ' ' };
+            let _ = writeln!(
+                text,
+                "{}   {:03X}  {:<14}{}",
+                marker,
+                line.addr,
+                line.hex(),
+                listing.text(line)
+            );
+        }
+        text
+    }
+
+    /// Stops execution when the PC reaches `addr`. Returns the id of the
+    /// breakpoint.
+    pub fn add_breakpoint(&mut self, addr: usize) -> usize {
+        self.0.breakpoints_mut().add(Breakpoint::Pc(addr))
+    }
+
+    /// Stops execution after an instruction reads (or writes, if `write`)
+    /// memory in `addr..addr + len`.
+    pub fn add_watchpoint(&mut self, addr: usize, len: usize, write: bool) -> usize {
+        let access = if write { Access::Write } else { Access::Read };
+        self.0.breakpoints_mut().add(Breakpoint::Watch {
+            range: addr..addr + len.max(1),
+            access,
+        })
+    }
+
+    /// Stops execution when a register compares to a value. `cmp` is one of
+    /// `==`, `!=`, `<`, `<=`, `>` or `>=`.
+    pub fn add_register_breakpoint(
+        &mut self,
+        register: u8,
+        cmp: &str,
+        value: u8,
+    ) -> Result<usize, JsValue> {
+        let cmp = match cmp {
+            "==" => Cmp::Eq,
+            "!=" => Cmp::Ne,
+            "<" => Cmp::Lt,
+            "<=" => Cmp::Le,
+            ">" => Cmp::Gt,
+            ">=" => Cmp::Ge,
+            _ => return Err(JsValue::from_str(&format!("unknown comparison = {}", cmp))),
+        };
+        Ok(self.0.breakpoints_mut().add(Breakpoint::Register {
+            register: register & 0xF,
+            cmp,
+            value,
+        }))
+    }
+
+    /// Stops execution before instructions of the same kind as `opcode`
+    /// (operands are ignored, so `0xD000` stops on every `DRW`).
+    pub fn add_opcode_breakpoint(&mut self, opcode: u16) -> Result<usize, JsValue> {
+        let opcode = Opcode::try_from(opcode)
+            .map_err(|opcode| JsValue::from_str(&format!("invalid opcode = {:04X}", opcode)))?;
+        Ok(self.0.breakpoints_mut().add(Breakpoint::Opcode(opcode)))
+    }
+
+    pub fn remove_breakpoint(&mut self, id: usize) -> bool {
+        self.0.breakpoints_mut().remove(id)
+    }
+
+    pub fn set_breakpoint_enabled(&mut self, id: usize, enabled: bool) {
+        self.0.breakpoints_mut().set_enabled(id, enabled)
+    }
+
+    /// Return the ids of the breakpoints, in the order they were added.
+    pub fn breakpoint_ids(&self) -> Vec<usize> {
+        self.0.breakpoints().iter().map(|entry| entry.id).collect()
+    }
+
+    /// Return a description of a breakpoint.
+    pub fn breakpoint_text(&self, id: usize) -> Option<String> {
+        self.0
+            .breakpoints()
+            .iter()
+            .find(|entry| entry.id == id)
+            .map(|entry| entry.breakpoint.to_string())
+    }
+
+    pub fn breakpoint_enabled(&self, id: usize) -> bool {
+        self.0
+            .breakpoints()
+            .iter()
+            .any(|entry| entry.id == id && entry.enabled)
+    }
 }
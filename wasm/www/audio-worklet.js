@@ -0,0 +1,53 @@
+// Plays the samples rendered by the emulator, posted from the main thread in
+// chunks as the emulation advances.
+
+// samples buffered before playback starts (or restarts after running dry)
+const PREBUFFER = Math.floor(sampleRate / 20)
+
+// older samples are dropped past this, to keep the latency bounded
+const MAX_BUFFERED = Math.floor(sampleRate / 4)
+
+class Chip8Audio extends AudioWorkletProcessor {
+    constructor() {
+        super()
+        this.queue = []
+        this.offset = 0
+        this.buffered = 0
+        this.playing = false
+        this.port.onmessage = (event) => {
+            this.queue.push(event.data)
+            this.buffered += event.data.length
+            while (this.buffered > MAX_BUFFERED && this.queue.length > 1) {
+                this.buffered -= this.queue.shift().length - this.offset
+                this.offset = 0
+            }
+        }
+    }
+
+    process(inputs, outputs) {
+        const out = outputs[0][0]
+        if (!this.playing && this.buffered >= PREBUFFER) {
+            this.playing = true
+        }
+        let i = 0
+        while (this.playing && i < out.length && this.queue.length > 0) {
+            const chunk = this.queue[0]
+            const count = Math.min(out.length - i, chunk.length - this.offset)
+            out.set(chunk.subarray(this.offset, this.offset + count), i)
+            i += count
+            this.offset += count
+            this.buffered -= count
+            if (this.offset == chunk.length) {
+                this.queue.shift()
+                this.offset = 0
+            }
+        }
+        if (i < out.length) {
+            this.playing = false
+            out.fill(0, i)
+        }
+        return true
+    }
+}
+
+registerProcessor("chip8-audio", Chip8Audio)
@@ -0,0 +1,44 @@
+// Sound, rendered by the emulator and played by an AudioWorklet.
+
+// Return a function posting the samples rendered by the emulator since the
+// last call to the worklet. Browsers only start audio after a user gesture,
+// so nothing is rendered until the first click or key press on the page.
+export const setupAudio = (cpu) => {
+    const waveform = document.getElementById("waveform")
+    const volume = document.getElementById("volume")
+    let node = null
+
+    // the settings only apply once the emulator renders sound
+    const applySettings = () => {
+        cpu.set_waveform(waveform.value)
+        cpu.set_volume(volume.value / 100)
+    }
+    waveform.addEventListener("change", applySettings)
+    volume.addEventListener("input", applySettings)
+
+    const start = () => {
+        document.removeEventListener("pointerdown", start)
+        document.removeEventListener("keydown", start)
+        const context = new AudioContext()
+        context.audioWorklet.addModule("audio-worklet.js")
+            .then(() => {
+                node = new AudioWorkletNode(context, "chip8-audio", { outputChannelCount: [1] })
+                node.connect(context.destination)
+                cpu.set_sample_rate(context.sampleRate)
+                applySettings()
+            })
+            .catch((err) => console.error("failed to start audio:", err))
+    }
+    document.addEventListener("pointerdown", start)
+    document.addEventListener("keydown", start)
+
+    return () => {
+        if (node === null) {
+            return
+        }
+        const samples = cpu.take_audio_samples()
+        if (samples.length > 0) {
+            node.port.postMessage(samples, [samples.buffer])
+        }
+    }
+}
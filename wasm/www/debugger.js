@@ -0,0 +1,150 @@
+// Debugger panel: registers, memory, disassembly, stepping and breakpoints.
+import { memory } from "chip8-wasm/chip8_wasm_bg";
+
+// the panel is redrawn at most this often, in milliseconds
+const REFRESH_MS = 100
+
+const hex = (value, digits) => value.toString(16).toUpperCase().padStart(digits, "0")
+
+const escape = (text) => text.replace(/&/g, "&amp;").replace(/</g, "&lt;").replace(/>/g, "&gt;")
+
+const byId = (id) => document.getElementById(id)
+
+export const setupDebugger = (cpu) => {
+    const panel = byId("debugger")
+    const state = byId("debug-state")
+    const registers = byId("debug-registers")
+    const disassembly = byId("debug-disasm")
+    const memoryView = byId("debug-memory")
+    const breakpoints = byId("debug-breakpoints")
+    const opcode = byId("debug-opcode")
+
+    const fault = (err) => console.error("cpu fault:", err)
+    const number = (id) => parseInt(byId(id).value, 16) || 0
+
+    byId("debug-halt").addEventListener("click", () => cpu.halt())
+    byId("debug-step").addEventListener("click", () => {
+        try {
+            cpu.fetch_execute()
+        } catch (err) {
+            fault(err)
+        }
+        refresh()
+    })
+    byId("debug-continue").addEventListener("click", () => cpu.resume())
+    byId("debug-fetch").addEventListener("click", () => {
+        try {
+            opcode.textContent = cpu.fetch()
+        } catch (err) {
+            opcode.textContent = err
+        }
+    })
+
+    const add = (breakpoint) => () => {
+        try {
+            breakpoint()
+        } catch (err) {
+            console.error("invalid breakpoint:", err)
+        }
+        refresh()
+    }
+    byId("debug-break-pc").addEventListener("click", add(() => cpu.add_breakpoint(number("debug-addr"))))
+    byId("debug-watch-reads").addEventListener("click", add(() => cpu.add_watchpoint(number("debug-addr"), number("debug-len"), false)))
+    byId("debug-watch-writes").addEventListener("click", add(() => cpu.add_watchpoint(number("debug-addr"), number("debug-len"), true)))
+    byId("debug-break-register").addEventListener("click", add(() => {
+        cpu.add_register_breakpoint(number("debug-register"), byId("debug-cmp").value, number("debug-value"))
+    }))
+    byId("debug-break-drw").addEventListener("click", add(() => cpu.add_opcode_breakpoint(0xD000)))
+    byId("debug-break-key").addEventListener("click", add(() => cpu.add_opcode_breakpoint(0xF00A)))
+
+    const refreshRegisters = () => {
+        const v = cpu.registers()
+        let text = ""
+        for (let i = 0; i < 16; ++i) {
+            text += "V" + hex(i, 1) + " " + hex(v[i], 2) + (i % 4 == 3 ? "\n" : "   ")
+        }
+        const pc = cpu.program_counter()
+        text += "\nI  " + hex(cpu.i(), 3) + "   PC " + hex(pc, 3) + "   SP " + cpu.stack_pointer()
+        text += "\nDT " + hex(cpu.delay_timer(), 2) + "    ST " + hex(cpu.sound_timer(), 2)
+        text += "\nStack " + Array.from(cpu.stack(), (addr) => hex(addr, 3)).join(" ")
+        registers.textContent = text
+    }
+
+    const refreshMemory = () => {
+        const size = cpu.memory_size()
+        const mem = new Uint8Array(memory.buffer, cpu.memory(), size)
+        const pc = cpu.program_counter()
+        let html = ""
+        for (let row = 0; row < size; row += 16) {
+            html += '<span class="addr">' + hex(row, 4) + "</span> "
+            for (let addr = row; addr < row + 16; ++addr) {
+                const byte = hex(mem[addr], 2)
+                // pc & instruction, empty bytes and the interpreter
+                if (addr == pc || addr == pc + 1) {
+                    html += '<span class="pc">' + byte + "</span>"
+                } else if (mem[addr] == 0) {
+                    html += '<span class="zero">' + byte + "</span>"
+                } else if (addr < 0x200) {
+                    html += '<span class="interpreter">' + byte + "</span>"
+                } else {
+                    html += byte
+                }
+                html += addr % 16 == 7 ? "  " : " "
+            }
+            html += "\n"
+        }
+        memoryView.innerHTML = html
+    }
+
+    const refreshDisassembly = () => {
+        disassembly.innerHTML = cpu.disassemble().split("\n").map((line) => {
+            return line.startsWith(">") ? '<span class="pc">' + escape(line) + "</span>" : escape(line)
+        }).join("\n")
+    }
+
+    // the list is only rebuilt when breakpoints are added or removed, so
+    // clicks on its checkboxes aren't lost
+    let listed = null
+    const refreshBreakpoints = () => {
+        const ids = cpu.breakpoint_ids().join()
+        if (ids === listed) {
+            return
+        }
+        listed = ids
+        breakpoints.innerHTML = ""
+        for (const id of cpu.breakpoint_ids()) {
+            const item = document.createElement("li")
+            const enabled = document.createElement("input")
+            enabled.type = "checkbox"
+            enabled.checked = cpu.breakpoint_enabled(id)
+            enabled.addEventListener("change", () => cpu.set_breakpoint_enabled(id, enabled.checked))
+            const remove = document.createElement("button")
+            remove.textContent = "Remove"
+            remove.addEventListener("click", () => {
+                cpu.remove_breakpoint(id)
+                refreshBreakpoints()
+            })
+            item.append(enabled, " #" + id + " " + cpu.breakpoint_text(id) + " ", remove)
+            breakpoints.appendChild(item)
+        }
+    }
+
+    const refresh = () => {
+        state.textContent = cpu.state()
+        byId("debug-continue").disabled = !cpu.is_break()
+        refreshRegisters()
+        refreshDisassembly()
+        refreshMemory()
+        refreshBreakpoints()
+    }
+
+    let lastRefresh = 0
+    const loop = (timestamp) => {
+        if (panel.open && timestamp - lastRefresh >= REFRESH_MS) {
+            lastRefresh = timestamp
+            refresh()
+        }
+        requestAnimationFrame(loop)
+    }
+    requestAnimationFrame(loop)
+}
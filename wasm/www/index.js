@@ -1,14 +1,46 @@
 import { Cpu } from "chip8-wasm";
 import { memory } from "chip8-wasm/chip8_wasm_bg";
+import { setupInput } from "./input.js";
+import { setupRomLoading } from "./rom.js";
+import { setupDebugger } from "./debugger.js";
+import { setupAudio } from "./audio.js";
 
-const speed = 8
-const cpu = Cpu.new()
+const cpu = Cpu.with_seed(Math.floor(Math.random() * 0xFFFFFFFF))
 
 document.getElementById("demo").addEventListener("click", () => cpu.load_demo())
 document.getElementById("pong").addEventListener("click", () => cpu.load_pong())
 document.getElementById("reset").addEventListener("click", () => cpu.reset())
 document.getElementById("halt").addEventListener("click", () => cpu.halt())
 
+setupInput(cpu)
+setupRomLoading(cpu)
+setupDebugger(cpu)
+const renderAudio = setupAudio(cpu)
+
+// save states are persisted to localStorage as base64 strings
+const STATE_KEY = "chip8-state"
+
+document.getElementById("save").addEventListener("click", () => {
+    const state = cpu.save_state()
+    let binary = ""
+    for (let i = 0; i < state.length; ++i) {
+        binary += String.fromCharCode(state[i])
+    }
+    localStorage.setItem(STATE_KEY, btoa(binary))
+})
+document.getElementById("load").addEventListener("click", () => {
+    const encoded = localStorage.getItem(STATE_KEY)
+    if (encoded === null) {
+        return
+    }
+    const state = Uint8Array.from(atob(encoded), c => c.charCodeAt(0))
+    try {
+        cpu.load_state(state)
+    } catch (err) {
+        console.error("failed to load state:", err)
+    }
+})
+
 const speaker = document.getElementById("speaker");
 let sound = false;
 let sound_timer = 0;
@@ -31,25 +63,39 @@ const timerLoop = () => {
     requestAnimationFrame(timerLoop)
 }
 
-const renderLoop = () => {
-    for (let i = 0; i < speed; ++i) {
-        cpu.step()
+let lastFrame = null;
+
+const renderLoop = (timestamp) => {
+    // the emulation speed is independent of the refresh rate
+    const elapsed = lastFrame === null ? 0 : timestamp - lastFrame
+    lastFrame = timestamp
+    try {
+        cpu.advance(elapsed)
+    } catch (err) {
+        console.error("cpu fault:", err)
     }
-    cpu.update_timers()
+    renderAudio()
     drawDisplay()
     requestAnimationFrame(renderLoop)
 }
 
+// colour of each combination of display planes
+const palette = ["#000000", "#FFFFFF", "#808080", "#C0C0C0"]
+
 const canvas = document.getElementById("display");
 const ctx = canvas.getContext('2d');
 
 const drawDisplay = () => {
-    const size = 8;
-    const displayPtr = cpu.display()
-    const display = new Uint8Array(memory.buffer, displayPtr, 32 * 64);
-    for (let row = 0; row < 32; ++row) {
-        for (let col = 0; col < 64; ++col) {
-            ctx.fillStyle = display[row*64+col] == 255 ? "#FFFFFF" : "#000000"
+    const width = cpu.display_width()
+    const height = cpu.display_height()
+    const size = canvas.width / width;
+    const plane0 = new Uint8Array(memory.buffer, cpu.display_plane(0), width * height);
+    const plane1 = new Uint8Array(memory.buffer, cpu.display_plane(1), width * height);
+    for (let row = 0; row < height; ++row) {
+        for (let col = 0; col < width; ++col) {
+            const idx = row * width + col;
+            const color = (plane0[idx] == 255 ? 1 : 0) | (plane1[idx] == 255 ? 2 : 0)
+            ctx.fillStyle = palette[color]
             ctx.fillRect(size * col, size * row, size, size)
         }
     }
@@ -0,0 +1,143 @@
+// Keypad input from the keyboard and the on-screen keypad.
+
+// keyboard key (KeyboardEvent.code) of each CHIP-8 key, laid out like the
+// COSMAC VIP keypad on the left side of a QWERTY keyboard:
+//
+//   1 2 3 C      1 2 3 4
+//   4 5 6 D  ->  Q W E R
+//   7 8 9 E      A S D F
+//   A 0 B F      Z X C V
+const DEFAULT_KEYS = [
+    "KeyX", "Digit1", "Digit2", "Digit3",
+    "KeyQ", "KeyW", "KeyE", "KeyA",
+    "KeyS", "KeyD", "KeyZ", "KeyC",
+    "Digit4", "KeyR", "KeyF", "KeyV",
+]
+
+// custom mappings are persisted to localStorage as JSON
+const KEYS_KEY = "chip8-keys"
+
+// CHIP-8 keys in the order they appear on the keypad
+const LAYOUT = [
+    0x1, 0x2, 0x3, 0xC,
+    0x4, 0x5, 0x6, 0xD,
+    0x7, 0x8, 0x9, 0xE,
+    0xA, 0x0, 0xB, 0xF,
+]
+
+const hex = (key) => key.toString(16).toUpperCase()
+
+const loadKeys = () => {
+    try {
+        const keys = JSON.parse(localStorage.getItem(KEYS_KEY))
+        if (Array.isArray(keys) && keys.length == 16) {
+            return keys
+        }
+    } catch (err) {
+        console.error("invalid key mapping:", err)
+    }
+    return DEFAULT_KEYS.slice()
+}
+
+export const setupInput = (cpu) => {
+    let keys = loadKeys()
+    // keys held from each source, forwarded to the cpu when they change
+    const keyboard = new Array(16).fill(false)
+    const touch = new Array(16).fill(false)
+    const held = new Array(16).fill(false)
+    // CHIP-8 key waiting for a keyboard key to be bound to it
+    let binding = null
+
+    const buttons = new Array(16)
+    const update = (key) => {
+        const down = keyboard[key] || touch[key]
+        if (down != held[key]) {
+            held[key] = down
+            cpu.set_key(key, down)
+            buttons[key].classList.toggle("held", down)
+        }
+    }
+
+    // keyboard (ignored while typing into form fields)
+    const onKey = (down) => (event) => {
+        if (event.target.tagName == "INPUT" || event.target.tagName == "SELECT") {
+            return
+        }
+        if (binding !== null) {
+            if (down) {
+                event.preventDefault()
+                bind(binding, event.code == "Escape" ? keys[binding] : event.code)
+            }
+            return
+        }
+        const key = event.code ? keys.indexOf(event.code) : -1
+        if (key >= 0) {
+            event.preventDefault()
+            keyboard[key] = down
+            update(key)
+        }
+    }
+    document.addEventListener("keydown", onKey(true))
+    document.addEventListener("keyup", onKey(false))
+    // keys held while the page loses focus would never be released
+    const releaseAll = () => {
+        keyboard.fill(false)
+        touch.fill(false)
+        held.forEach((_, key) => update(key))
+    }
+    window.addEventListener("blur", releaseAll)
+
+    // on-screen keypad
+    const keypad = document.getElementById("keypad")
+    for (const key of LAYOUT) {
+        const button = document.createElement("button")
+        button.textContent = hex(key)
+        const press = (down) => (event) => {
+            event.preventDefault()
+            touch[key] = down
+            update(key)
+        }
+        button.addEventListener("pointerdown", (event) => {
+            button.setPointerCapture(event.pointerId)
+            press(true)(event)
+        })
+        button.addEventListener("pointerup", press(false))
+        button.addEventListener("pointercancel", press(false))
+        buttons[key] = button
+        keypad.appendChild(button)
+    }
+
+    // key mapping: click a key, then press the keyboard key to bind to it
+    const mapping = document.getElementById("mapping")
+    const labels = new Array(16)
+    const bind = (key, code) => {
+        // a keyboard key only drives one CHIP-8 key
+        keys = keys.map((other) => other == code ? "" : other)
+        keys[key] = code
+        releaseAll()
+        localStorage.setItem(KEYS_KEY, JSON.stringify(keys))
+        binding = null
+        refresh()
+    }
+    const refresh = () => {
+        labels.forEach((label, key) => {
+            label.textContent = hex(key) + ": " + (binding === key ? "press a key…" : keys[key] || "-")
+        })
+    }
+    for (const key of LAYOUT) {
+        const label = document.createElement("button")
+        label.addEventListener("click", () => {
+            binding = key
+            refresh()
+        })
+        labels[key] = label
+        mapping.appendChild(label)
+    }
+    document.getElementById("mapping-reset").addEventListener("click", () => {
+        keys = DEFAULT_KEYS.slice()
+        localStorage.removeItem(KEYS_KEY)
+        binding = null
+        refresh()
+    })
+    refresh()
+}
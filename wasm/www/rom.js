@@ -0,0 +1,65 @@
+// Loading ROMs from files (picker or drag and drop) and from URLs.
+
+const status = document.getElementById("status")
+
+const showStatus = (message, error) => {
+    status.textContent = message
+    status.style.color = error ? "#C00000" : ""
+}
+
+const load = (cpu, name, bytes) => {
+    try {
+        cpu.load_rom(new Uint8Array(bytes))
+        showStatus("Loaded " + name + " (" + bytes.byteLength + " bytes)", false)
+    } catch (err) {
+        showStatus("Failed to load " + name + ": " + err, true)
+    }
+}
+
+const loadFile = (cpu, file) => {
+    file.arrayBuffer()
+        .then((bytes) => load(cpu, file.name, bytes))
+        .catch((err) => showStatus("Failed to read " + file.name + ": " + err, true))
+}
+
+// ROMs can be shared as links to the page, as in `?rom=https://host/game.ch8`
+// (the host must allow cross-origin requests)
+const loadUrl = (cpu, url) => {
+    showStatus("Downloading " + url + "…", false)
+    fetch(url)
+        .then((response) => {
+            if (!response.ok) {
+                throw new Error(response.status + " " + response.statusText)
+            }
+            return response.arrayBuffer()
+        })
+        .then((bytes) => load(cpu, url, bytes))
+        .catch((err) => showStatus("Failed to download " + url + ": " + err, true))
+}
+
+export const setupRomLoading = (cpu) => {
+    const picker = document.getElementById("rom")
+    picker.addEventListener("change", () => {
+        if (picker.files.length > 0) {
+            loadFile(cpu, picker.files[0])
+        }
+        // picking the same file again reloads it
+        picker.value = ""
+    })
+
+    document.addEventListener("dragover", (event) => {
+        event.preventDefault()
+        event.dataTransfer.dropEffect = "copy"
+    })
+    document.addEventListener("drop", (event) => {
+        event.preventDefault()
+        if (event.dataTransfer.files.length > 0) {
+            loadFile(cpu, event.dataTransfer.files[0])
+        }
+    })
+
+    const url = new URLSearchParams(window.location.search).get("rom")
+    if (url !== null) {
+        loadUrl(cpu, url)
+    }
+}